@@ -39,20 +39,32 @@ SEXP handle_result(SEXP res_) {
     return (SEXP)res;
 }
 
-SEXP savvy_parse_mjlog__impl(SEXP c_arg__x) {
-    SEXP res = savvy_parse_mjlog__ffi(c_arg__x);
+SEXP savvy_parse_mjlog__impl(SEXP c_arg__x, SEXP c_arg__lenient, SEXP c_arg__kyoku, SEXP c_arg__predicate) {
+    SEXP res = savvy_parse_mjlog__ffi(c_arg__x, c_arg__lenient, c_arg__kyoku, c_arg__predicate);
     return handle_result(res);
 }
 
-SEXP savvy_parse_tenhou6__impl(SEXP c_arg__x) {
-    SEXP res = savvy_parse_tenhou6__ffi(c_arg__x);
+SEXP savvy_parse_mjlog_frames__impl(SEXP c_arg__x, SEXP c_arg__lenient, SEXP c_arg__kyoku, SEXP c_arg__predicate) {
+    SEXP res = savvy_parse_mjlog_frames__ffi(c_arg__x, c_arg__lenient, c_arg__kyoku, c_arg__predicate);
+    return handle_result(res);
+}
+
+SEXP savvy_parse_tenhou6__impl(SEXP c_arg__x, SEXP c_arg__lenient, SEXP c_arg__kyoku, SEXP c_arg__predicate) {
+    SEXP res = savvy_parse_tenhou6__ffi(c_arg__x, c_arg__lenient, c_arg__kyoku, c_arg__predicate);
+    return handle_result(res);
+}
+
+SEXP savvy_parse_tenhou6_frames__impl(SEXP c_arg__x, SEXP c_arg__lenient, SEXP c_arg__kyoku, SEXP c_arg__predicate) {
+    SEXP res = savvy_parse_tenhou6_frames__ffi(c_arg__x, c_arg__lenient, c_arg__kyoku, c_arg__predicate);
     return handle_result(res);
 }
 
 
 static const R_CallMethodDef CallEntries[] = {
-    {"savvy_parse_mjlog__impl", (DL_FUNC) &savvy_parse_mjlog__impl, 1},
-    {"savvy_parse_tenhou6__impl", (DL_FUNC) &savvy_parse_tenhou6__impl, 1},
+    {"savvy_parse_mjlog__impl", (DL_FUNC) &savvy_parse_mjlog__impl, 4},
+    {"savvy_parse_mjlog_frames__impl", (DL_FUNC) &savvy_parse_mjlog_frames__impl, 4},
+    {"savvy_parse_tenhou6__impl", (DL_FUNC) &savvy_parse_tenhou6__impl, 4},
+    {"savvy_parse_tenhou6_frames__impl", (DL_FUNC) &savvy_parse_tenhou6_frames__impl, 4},
     {NULL, NULL, 0}
 };
 
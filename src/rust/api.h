@@ -1,3 +1,5 @@
-SEXP savvy_parse_mjlog__ffi(SEXP c_arg__x);
-SEXP savvy_parse_tenhou6__ffi(SEXP c_arg__x);
+SEXP savvy_parse_mjlog__ffi(SEXP c_arg__x, SEXP c_arg__lenient, SEXP c_arg__kyoku, SEXP c_arg__predicate);
+SEXP savvy_parse_mjlog_frames__ffi(SEXP c_arg__x, SEXP c_arg__lenient, SEXP c_arg__kyoku, SEXP c_arg__predicate);
+SEXP savvy_parse_tenhou6__ffi(SEXP c_arg__x, SEXP c_arg__lenient, SEXP c_arg__kyoku, SEXP c_arg__predicate);
+SEXP savvy_parse_tenhou6_frames__ffi(SEXP c_arg__x, SEXP c_arg__lenient, SEXP c_arg__kyoku, SEXP c_arg__predicate);
 
@@ -0,0 +1,233 @@
+//! Converts many logs on worker threads for the R functions.
+
+#[cfg(not(test))]
+use std::ffi::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// How often the main thread checks for a user interrupt.
+const INTERRUPT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Runs [`convert`] over `inputs`, with a panic in `parse` reported as the
+/// error of that input. If `lenient`, inputs that fail are written as `None`
+/// and their error messages are returned instead.
+pub(crate) fn convert_each<T, F, W>(
+    inputs: &[Option<&str>],
+    lenient: bool,
+    parse: F,
+    mut write: W,
+) -> savvy::Result<Vec<Option<String>>>
+where
+    T: Send,
+    F: Fn(usize, &str) -> Result<T, String> + Sync,
+    W: FnMut(usize, Option<T>) -> savvy::Result<()>,
+{
+    let mut errors = vec![None; inputs.len()];
+
+    convert(
+        num_workers(),
+        inputs,
+        |i, s| match catch_panic(|| parse(i, s)) {
+            Err(e) if lenient => Ok(Err(e)),
+            converted => converted.map(Ok),
+        },
+        |i, converted| match converted {
+            Some(Ok(converted)) => write(i, Some(converted)),
+            Some(Err(e)) => {
+                errors[i] = Some(e);
+                write(i, None)
+            }
+            None => write(i, None),
+        },
+    )?;
+
+    Ok(errors)
+}
+
+/// Converts each of `inputs`, `None` for NA, with `parse` on up to `workers`
+/// threads, and hands the results to `write` on the calling thread in any
+/// order.
+///
+/// `parse` is given the index of the input as well. Stops at the error of the
+/// lowest index or when the user interrupts R.
+fn convert<T, F, W>(
+    workers: usize,
+    inputs: &[Option<&str>],
+    parse: F,
+    mut write: W,
+) -> savvy::Result<()>
+where
+    T: Send,
+    F: Fn(usize, &str) -> Result<T, String> + Sync,
+    W: FnMut(usize, Option<T>) -> savvy::Result<()>,
+{
+    let workers = workers.min(inputs.len());
+    if workers <= 1 {
+        for (i, input) in inputs.iter().enumerate() {
+            if i % 64 == 0 && interrupted() {
+                return Err(savvy::Error::new("interrupted"));
+            }
+            let converted = input.map(|input| parse(i, input)).transpose();
+            write(i, converted.map_err(savvy::Error::new)?)?;
+        }
+        return Ok(());
+    }
+
+    let next = AtomicUsize::new(0);
+    let cancelled = AtomicBool::new(false);
+    thread::scope(|s| {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let (next, cancelled, parse) = (&next, &cancelled, &parse);
+            s.spawn(move || {
+                while !cancelled.load(Ordering::Relaxed) {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(input) = inputs.get(i) else {
+                        break;
+                    };
+                    let converted = input.map(|input| parse(i, input)).transpose();
+                    if tx.send((i, converted)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        let mut error: Option<(usize, String)> = None;
+        let result = (|| loop {
+            match rx.recv_timeout(INTERRUPT_INTERVAL) {
+                Ok((i, Ok(converted))) => {
+                    if error.is_none() {
+                        write(i, converted)?;
+                    }
+                }
+                Ok((i, Err(e))) => {
+                    // Logs before `i` have all been taken, so wait for them
+                    // without taking new ones.
+                    cancelled.store(true, Ordering::Relaxed);
+                    if !matches!(error, Some((j, _)) if j < i) {
+                        error = Some((i, e));
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return match error.take() {
+                        Some((_, e)) => Err(savvy::Error::new(e)),
+                        None => Ok(()),
+                    };
+                }
+            }
+            if interrupted() {
+                return Err(savvy::Error::new("interrupted"));
+            }
+        })();
+        // Let the workers finish the logs at hand and quit.
+        cancelled.store(true, Ordering::Relaxed);
+        result
+    })
+}
+
+/// Runs `parse`, turning a panic into an error message.
+fn catch_panic<T>(parse: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(parse)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown cause");
+        Err(format!("panicked: {message}"))
+    })
+}
+
+fn num_workers() -> usize {
+    // Threads are not available in webR.
+    if cfg!(target_family = "wasm") {
+        return 1;
+    }
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+#[cfg(not(test))]
+extern "C" {
+    fn R_ToplevelExec(fun: Option<unsafe extern "C" fn(*mut c_void)>, data: *mut c_void) -> i32;
+    fn R_CheckUserInterrupt();
+}
+
+#[cfg(not(test))]
+unsafe extern "C" fn check_interrupt(_: *mut c_void) {
+    // SAFETY: Called in `R_ToplevelExec`, which catches the longjmp.
+    unsafe { R_CheckUserInterrupt() };
+}
+
+/// Checks for a user interrupt without letting R jump over Rust frames.
+#[cfg(not(test))]
+fn interrupted() -> bool {
+    // SAFETY: `R_ToplevelExec` returns FALSE instead of jumping out when
+    // `check_interrupt` is interrupted. Only called on the R main thread.
+    unsafe { R_ToplevelExec(Some(check_interrupt), std::ptr::null_mut()) == 0 }
+}
+
+/// Unit tests run without R to interrupt them.
+#[cfg(test)]
+const fn interrupted() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn panics_are_errors() {
+        let inputs = [Some("1"), Some("4"), None, Some("x"), Some("2")];
+        let parse = |_, s: &str| -> Result<u8, String> {
+            let i: usize = s.parse().map_err(|_| format!("not a number: {s}"))?;
+            Ok([10, 11, 12, 13][i])
+        };
+        let mut written = vec![None; inputs.len()];
+        let errors = convert_each(&inputs, true, parse, |i, converted| {
+            written[i] = converted;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(written, [Some(11), None, None, None, Some(12)]);
+        assert_eq!(errors[0], None);
+        let err = errors[1].as_deref().unwrap();
+        assert!(err.starts_with("panicked: index out of bounds"), "{err}");
+        assert_eq!(
+            errors[2..],
+            [None, Some("not a number: x".to_owned()), None]
+        );
+
+        let err = convert_each(&inputs, false, parse, |_, _| Ok(())).unwrap_err();
+        assert!(err.to_string().starts_with("panicked: "), "{err}");
+    }
+
+    #[test]
+    fn lowest_error_first() {
+        let inputs: Vec<_> = (0..200).map(|_| Some("")).collect();
+        for _ in 0..5 {
+            let err = convert(
+                4,
+                &inputs,
+                |i, _| match i {
+                    56 => {
+                        // Let the later errors come first.
+                        thread::sleep(Duration::from_millis(20));
+                        Err(format!("log {i}"))
+                    }
+                    _ if i > 56 && i % 7 == 0 => Err(format!("log {i}")),
+                    _ => Ok(i),
+                },
+                |_, _| Ok(()),
+            )
+            .unwrap_err();
+            assert_eq!(err.to_string(), "log 56");
+        }
+    }
+}
@@ -1,8 +1,9 @@
-use crate::mjai::Event;
+use crate::kyoku_filter::kyoku_label;
+use crate::mjai::{Event, GameResult, HandValue, HoraInfo, RyukyokuInfo, RyukyokuReason};
+use crate::options::ConvertOptions;
 use crate::t;
 use crate::tenhou::{ActionItem, EndStatus, Kyoku, Log, TenhouTile};
 use crate::Tile;
-use std::array;
 use std::collections::hash_map::Entry;
 
 use ahash::AHashMap;
@@ -16,18 +17,20 @@ pub enum ConvertError {
     #[error("invalid tile string: {0:?}")]
     InvalidTile(String),
 
-    #[error("insufficient dora indicators: at kyoku {kyoku} honba {honba}")]
+    #[error("insufficient dora indicators: at {}", kyoku_label(*.kyoku, *.honba))]
     InsufficientDoraIndicators { kyoku: u8, honba: u8 },
 
     #[error(
         "insufficient take sequence size: \
-        at kyoku {kyoku} honba {honba} for actor {actor}"
+        at {} for actor {actor}",
+        kyoku_label(*.kyoku, *.honba)
     )]
     InsufficientTakes { kyoku: u8, honba: u8, actor: u8 },
 
     #[error(
         "insufficient discard sequence size: \
-        at kyoku {kyoku} honba {honba} for actor {actor}"
+        at {} for actor {actor}",
+        kyoku_label(*.kyoku, *.honba)
     )]
     InsufficientDiscards { kyoku: u8, honba: u8, actor: u8 },
 
@@ -36,9 +39,10 @@ pub enum ConvertError {
 
     #[error(
         "unexpected naki: \
-        at kyoku {kyoku} honba {honba} for actor {actor}: \
+        at {} for actor {actor}: \
         action {action:?}, expected tile {last_discard} \
-        from {last_actor:?}"
+        from {last_actor:?}",
+        kyoku_label(*.kyoku, *.honba)
     )]
     UnexpectedNaki {
         action: Event,
@@ -48,6 +52,29 @@ pub enum ConvertError {
         honba: u8,
         actor: u8,
     },
+
+    #[error("at {}: {source}", kyoku_label(*.kyoku, *.honba))]
+    InKyoku {
+        kyoku: u8,
+        honba: u8,
+        source: Box<Self>,
+    },
+}
+
+impl ConvertError {
+    /// Tells the kyoku where the error is from, unless it already does.
+    fn in_kyoku(self, kyoku: u8, honba: u8) -> Self {
+        match self {
+            Self::InvalidNaki(_) | Self::InvalidTile(_) | Self::UnexpectedTsumogiri => {
+                Self::InKyoku {
+                    kyoku,
+                    honba,
+                    source: Box::new(self),
+                }
+            }
+            _ => self,
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, ConvertError>;
@@ -58,28 +85,86 @@ struct BackTrack {
 }
 
 /// Transform a tenhou.net/6 format log into mjai format.
+#[inline]
 pub fn tenhou_to_mjai(log: &Log) -> Result<Vec<Event>> {
+    tenhou_to_mjai_with(log, &ConvertOptions::default())
+}
+
+/// Transform a tenhou.net/6 format log into mjai format with `options`.
+pub fn tenhou_to_mjai_with(log: &Log, options: &ConvertOptions) -> Result<Vec<Event>> {
     let mut events = vec![Event::StartGame {
         kyoku_first: log.game_length as u8,
         aka_flag: log.has_aka,
+        lobby_tier: log.lobby_tier,
         names: log.names.clone(),
+        meta: Box::new(log.meta.clone()),
     }];
 
     for kyoku in &log.kyokus {
-        let kyoku_events = tenhou_kyoku_to_mjai_events(kyoku)?;
+        let kyoku_events = tenhou_kyoku_to_mjai_events(kyoku)
+            .map_err(|e| e.in_kyoku(kyoku.meta.kyoku_num, kyoku.meta.honba))?;
         events.extend(kyoku_events);
     }
 
-    events.push(Event::EndGame);
+    let result = log
+        .result
+        .clone()
+        .or_else(|| reconstruct_result(&events, log.num_players as usize));
+    events.push(Event::EndGame {
+        info: result.map(Box::new),
+    });
+    options.apply(&mut events);
     Ok(events)
 }
 
+/// Final standings from the last kyoku, for logs without `sc`.
+fn reconstruct_result(events: &[Event], num_players: usize) -> Option<GameResult> {
+    let mut total = None;
+    let mut scores = None;
+    for event in events {
+        match event {
+            Event::StartKyoku {
+                kyotaku,
+                scores: start,
+                ..
+            } => {
+                let start: Vec<_> = start.iter().copied().take(num_players).collect();
+                total.get_or_insert_with(|| start.iter().sum::<i32>() + i32::from(*kyotaku) * 1000);
+                scores = Some(start);
+            }
+            &Event::ReachAccepted { actor } => {
+                if let Some(score) = scores.as_mut().and_then(|s| s.get_mut(actor as usize)) {
+                    *score -= 1000;
+                }
+            }
+            Event::Hora {
+                deltas: Some(deltas),
+                ..
+            }
+            | Event::Ryukyoku {
+                deltas: Some(deltas),
+                ..
+            } => {
+                for (score, d) in scores.iter_mut().flatten().zip(deltas) {
+                    *score += d;
+                }
+            }
+            _ => (),
+        }
+    }
+    Some(GameResult::from_scores(scores?, total?))
+}
+
 fn tenhou_kyoku_to_mjai_events(kyoku: &Kyoku) -> Result<Vec<Event>> {
+    // There are only three seats in sanma.
+    let num_players = kyoku.action_tables.len();
+
     // First of all, transform all takes and discards to events.
-    let (take_events, discard_events): (Vec<_>, Vec<_>) = (0..4)
+    let (take_events, discard_events): (Vec<_>, Vec<_>) = (0..num_players as u8)
         .map(|a| {
             parse_takes_and_discards_to_mjai(
                 a,
+                num_players as u8,
                 &kyoku.action_tables[a as usize].takes,
                 &kyoku.action_tables[a as usize].discards,
             )
@@ -116,11 +201,11 @@ fn tenhou_kyoku_to_mjai_events(kyoku: &Kyoku) -> Result<Vec<Event>> {
                     honba: kyoku.meta.honba,
                 })?,
             oya,
-            scores: kyoku.scoreboard,
-            tehais: array::from_fn(|i| kyoku.action_tables[i].haipai),
+            scores: kyoku.scoreboard[..num_players].to_vec(),
+            tehais: kyoku.action_tables.iter().map(|t| t.haipai).collect(),
         });
 
-        let mut discard_sets: Vec<_> = (0..4)
+        let mut discard_sets: Vec<_> = (0..num_players)
             .map(|a| {
                 let mut m = AHashMap::new();
                 for discard in &discard_events[a] {
@@ -292,15 +377,15 @@ fn tenhou_kyoku_to_mjai_events(kyoku: &Kyoku) -> Result<Vec<Event>> {
             //
             // Here it simply checks if there is no more take for every single
             // actor.
-            if (0..4).all(|a| take_idxs[a] >= take_events[a].len()) {
+            if (0..num_players).all(|a| take_idxs[a] >= take_events[a].len()) {
                 end_kyoku(&mut events, kyoku);
                 break;
             }
 
-            // Check if the last discard was ankan or kakan.
+            // Check if the last discard was ankan, kakan or nukidora.
             //
-            // For kan, it will immediately consume the next take event from the
-            // same actor.
+            // For kan and nukidora, it will immediately consume the next take
+            // event from the same actor.
             match discard {
                 Event::Ankan { .. } => {
                     // ankan triggers a dora event immediately.
@@ -318,6 +403,7 @@ fn tenhou_kyoku_to_mjai_events(kyoku: &Kyoku) -> Result<Vec<Event>> {
                     need_new_dora_at_discard = true;
                     continue;
                 }
+                Event::Nukidora { .. } => continue,
                 _ => (),
             }
 
@@ -329,7 +415,7 @@ fn tenhou_kyoku_to_mjai_events(kyoku: &Kyoku) -> Result<Vec<Event>> {
             // There are some edge cases when there are multiple candidates for the
             // next actor, which will be handled by the second pass of the filter.
             last_actor = Some(actor as u8);
-            actor = (0..4)
+            actor = (0..num_players)
                 .filter(|&a| a != actor)
                 // First pass, filter the naki that takes the specific tile from the
                 // specific target.
@@ -415,7 +501,7 @@ fn tenhou_kyoku_to_mjai_events(kyoku: &Kyoku) -> Result<Vec<Event>> {
                         }
                     }
                 })
-                .unwrap_or((actor + 1) % 4);
+                .unwrap_or((actor + 1) % num_players);
         }
 
         Ok(events)
@@ -437,10 +523,11 @@ fn tenhou_kyoku_to_mjai_events(kyoku: &Kyoku) -> Result<Vec<Event>> {
 
 fn parse_takes_and_discards_to_mjai(
     actor: u8,
+    num_players: u8,
     takes: &[ActionItem],
     discards: &[ActionItem],
 ) -> Result<(Vec<Event>, Vec<Event>)> {
-    let mjai_takes = take_action_to_events(actor, takes)?;
+    let mjai_takes = take_action_to_events(actor, num_players, takes)?;
     let mut mjai_discards = discard_action_to_events(actor, discards)?;
     finalize_discards(&mjai_takes, &mut mjai_discards);
 
@@ -486,7 +573,10 @@ fn finalize_discards(takes: &[Event], discards: &mut Vec<Event>) {
     }
 }
 
-fn take_action_to_events(actor: u8, takes: &[ActionItem]) -> Result<Vec<Event>> {
+fn take_action_to_events(actor: u8, num_players: u8, takes: &[ActionItem]) -> Result<Vec<Event>> {
+    let kamicha = (actor + num_players - 1) % num_players;
+    let shimocha = (actor + 1) % num_players;
+
     takes
         .iter()
         .map(|take| match take {
@@ -498,15 +588,16 @@ fn take_action_to_events(actor: u8, takes: &[ActionItem]) -> Result<Vec<Event>>
                 if naki.contains(&b'c') {
                     // chi
                     // you can only chi from kamicha right...?
+                    // and there is no chi in sanma.
 
-                    if naki_string.len() != 7 {
+                    if naki_string.len() != 7 || num_players == 3 {
                         return Err(ConvertError::InvalidNaki(naki_string.clone()));
                     }
 
                     // e.g. "c275226" => chi 7p with 06p from kamicha
                     Ok(Event::Chi {
                         actor,
-                        target: (actor + 3) % 4,
+                        target: kamicha,
                         pai: tiles_from_tenhou_bytes(&naki[1..3])?,
                         consumed: [
                             tiles_from_tenhou_bytes(&naki[3..5])?,
@@ -525,7 +616,7 @@ fn take_action_to_events(actor: u8, takes: &[ActionItem]) -> Result<Vec<Event>>
                         // e.g. "p252525" => pon 5p from kamicha
                         0 => Ok(Event::Pon {
                             actor,
-                            target: (actor + 3) % 4,
+                            target: kamicha,
                             pai: tiles_from_tenhou_bytes(&naki[1..3])?,
                             consumed: [
                                 tiles_from_tenhou_bytes(&naki[3..5])?,
@@ -533,9 +624,9 @@ fn take_action_to_events(actor: u8, takes: &[ActionItem]) -> Result<Vec<Event>>
                             ],
                         }),
 
-                        // from toimen
+                        // from toimen, which does not exist in sanma
                         // e.g. "12p1212" => pon 2m from toimen
-                        2 => Ok(Event::Pon {
+                        2 if num_players == 4 => Ok(Event::Pon {
                             actor,
                             target: (actor + 2) % 4,
                             pai: tiles_from_tenhou_bytes(&naki[3..5])?,
@@ -549,7 +640,7 @@ fn take_action_to_events(actor: u8, takes: &[ActionItem]) -> Result<Vec<Event>>
                         // e.g. "3737p37" => pon 7s from shimocha
                         4 => Ok(Event::Pon {
                             actor,
-                            target: (actor + 1) % 4,
+                            target: shimocha,
                             pai: tiles_from_tenhou_bytes(&naki[5..7])?,
                             consumed: [
                                 tiles_from_tenhou_bytes(&naki[0..2])?,
@@ -572,7 +663,7 @@ fn take_action_to_events(actor: u8, takes: &[ActionItem]) -> Result<Vec<Event>>
                         // e.g. "m39393939" => kan 9s from kamicha
                         0 => Ok(Event::Daiminkan {
                             actor,
-                            target: (actor + 3) % 4,
+                            target: kamicha,
                             pai: tiles_from_tenhou_bytes(&naki[1..3])?,
                             consumed: [
                                 tiles_from_tenhou_bytes(&naki[3..5])?,
@@ -581,9 +672,9 @@ fn take_action_to_events(actor: u8, takes: &[ActionItem]) -> Result<Vec<Event>>
                             ],
                         }),
 
-                        // from toimen
+                        // from toimen, which does not exist in sanma
                         // e.g. "26m262626" => kan 6p from toimen
-                        2 => Ok(Event::Daiminkan {
+                        2 if num_players == 4 => Ok(Event::Daiminkan {
                             actor,
                             target: (actor + 2) % 4,
                             pai: tiles_from_tenhou_bytes(&naki[3..5])?,
@@ -598,7 +689,7 @@ fn take_action_to_events(actor: u8, takes: &[ActionItem]) -> Result<Vec<Event>>
                         // e.g. "131313m13" => kan 3m from shimocha
                         6 => Ok(Event::Daiminkan {
                             actor,
-                            target: (actor + 1) % 4,
+                            target: shimocha,
                             pai: tiles_from_tenhou_bytes(&naki[7..9])?,
                             consumed: [
                                 tiles_from_tenhou_bytes(&naki[0..2])?,
@@ -646,7 +737,7 @@ fn discard_action_to_events(actor: u8, discards: &[ActionItem]) -> Result<Vec<Ev
             ActionItem::Naki(naki_string) => {
                 let naki = naki_string.as_bytes();
 
-                // only ankan, kakan and reach are possible
+                // only ankan, kakan, nukidora and reach are possible
                 if let Some(idx) = naki_string.find('k') {
                     // kakan
 
@@ -719,6 +810,18 @@ fn discard_action_to_events(actor: u8, discards: &[ActionItem]) -> Result<Vec<Ev
                     };
 
                     ret.push(ev);
+                } else if naki.first() == Some(&b'f') {
+                    // nukidora
+                    // e.g. "f44" => kita in sanma
+
+                    if naki_string.len() != 3 {
+                        return Err(ConvertError::InvalidNaki(naki_string.clone()));
+                    }
+
+                    ret.push(Event::Nukidora {
+                        actor,
+                        pai: tiles_from_tenhou_bytes(&naki[1..3])?,
+                    });
                 } else {
                     // reach
                     // e.g. "r35" => discard 5s to reach
@@ -748,19 +851,61 @@ fn discard_action_to_events(actor: u8, discards: &[ActionItem]) -> Result<Vec<Ev
 }
 
 fn end_kyoku(events: &mut Vec<Event>, kyoku: &Kyoku) {
+    let num_players = kyoku.action_tables.len();
     match &kyoku.end_status {
         EndStatus::Hora { details } => {
-            events.extend(details.iter().map(|detail| Event::Hora {
-                actor: detail.who,
-                target: detail.target,
-                deltas: Some(detail.score_deltas),
-                ura_markers: Some(kyoku.ura_indicators.clone()),
-            }));
+            let horas: Vec<_> = details
+                .iter()
+                .map(|detail| Event::Hora {
+                    actor: detail.who,
+                    target: detail.target,
+                    deltas: Some(detail.score_deltas[..num_players].to_vec()),
+                    ura_markers: Some(kyoku.ura_indicators.clone()),
+                    info: detail.value.clone().and_then(|value| {
+                        hora_info(events, detail.who, detail.target, value).map(Box::new)
+                    }),
+                })
+                .collect();
+            events.extend(horas);
         }
 
-        EndStatus::Ryukyoku { score_deltas } => {
+        EndStatus::Ryukyoku {
+            score_deltas,
+            reason,
+            tenpai,
+        } => {
+            let info = reason.map(|reason| {
+                // Only the one who declares 九種九牌 shows the hand.
+                let declarer = events.iter().rev().find_map(|ev| match *ev {
+                    Event::Tsumo { actor, .. } => Some(actor),
+                    _ => None,
+                });
+                let tehais = (0..tenpai.len() as u8)
+                    .map(|actor| {
+                        let shown = if reason == RyukyokuReason::KyuushuKyuuhai {
+                            declarer == Some(actor)
+                        } else {
+                            tenpai[actor as usize]
+                        };
+                        shown
+                            .then(|| {
+                                replay_hand(events, actor).map(|(mut tehai, ..)| {
+                                    tehai.sort_unstable();
+                                    tehai
+                                })
+                            })
+                            .flatten()
+                    })
+                    .collect();
+                Box::new(RyukyokuInfo {
+                    reason,
+                    tenpai: tenpai.clone(),
+                    tehais,
+                })
+            });
             events.push(Event::Ryukyoku {
-                deltas: Some(*score_deltas),
+                deltas: Some(score_deltas[..num_players].to_vec()),
+                info,
             });
         }
     };
@@ -768,6 +913,96 @@ fn end_kyoku(events: &mut Vec<Event>, kyoku: &Kyoku) {
     events.push(Event::EndKyoku);
 }
 
+/// Finds the winning tile, the closed hand and the melds of `actor`.
+fn hora_info(events: &[Event], actor: u8, target: u8, value: HandValue) -> Option<HoraInfo> {
+    let (mut tehai, melds, last_pai) = replay_hand(events, actor)?;
+    let pai = last_pai?;
+    if actor == target {
+        if let Some(idx) = tehai.iter().position(|&t| t == pai) {
+            tehai.swap_remove(idx);
+        }
+    }
+    tehai.sort_unstable();
+    Some(HoraInfo {
+        value,
+        pai,
+        tehai,
+        melds,
+    })
+}
+
+/// Replays the kyoku to get the closed hand and the melds of `actor`, along
+/// with the last tile drawn, discarded or added to a kan by anyone.
+fn replay_hand(events: &[Event], actor: u8) -> Option<(Vec<Tile>, Vec<Event>, Option<Tile>)> {
+    let mut tehai = vec![];
+    let mut melds: Vec<Event> = vec![];
+    let mut last_pai = None;
+
+    let remove = |tehai: &mut Vec<Tile>, tiles: &[Tile]| {
+        for &pai in tiles {
+            if let Some(idx) = tehai.iter().position(|&t| t == pai) {
+                tehai.swap_remove(idx);
+            }
+        }
+    };
+    for event in events {
+        match event {
+            Event::StartKyoku { tehais, .. } => {
+                tehai = tehais.get(actor as usize)?.to_vec();
+            }
+            &Event::Tsumo { actor: a, pai } => {
+                last_pai = Some(pai);
+                if a == actor {
+                    tehai.push(pai);
+                }
+            }
+            &Event::Dahai { actor: a, pai, .. } | &Event::Nukidora { actor: a, pai } => {
+                last_pai = Some(pai);
+                if a == actor {
+                    remove(&mut tehai, &[pai]);
+                }
+            }
+            Event::Chi {
+                actor: a, consumed, ..
+            }
+            | Event::Pon {
+                actor: a, consumed, ..
+            } if *a == actor => {
+                remove(&mut tehai, consumed);
+                melds.push(event.clone());
+            }
+            Event::Daiminkan {
+                actor: a, consumed, ..
+            } if *a == actor => {
+                remove(&mut tehai, consumed);
+                melds.push(event.clone());
+            }
+            &Event::Kakan { actor: a, pai, .. } => {
+                last_pai = Some(pai);
+                if a == actor {
+                    remove(&mut tehai, &[pai]);
+                    let pon = melds.iter().position(
+                        |m| matches!(m, Event::Pon { pai: p, .. } if p.deaka() == pai.deaka()),
+                    )?;
+                    melds[pon] = event.clone();
+                }
+            }
+            Event::Ankan {
+                actor: a, consumed, ..
+            } => {
+                last_pai = Some(consumed[0]);
+                if *a == actor {
+                    remove(&mut tehai, consumed);
+                    melds.push(event.clone());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Some((tehai, melds, last_pai))
+}
+
 pub fn tiles_from_tenhou_bytes(b: &[u8]) -> Result<Tile> {
     let s = String::from_utf8_lossy(b);
     let id: u8 = s
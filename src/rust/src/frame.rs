@@ -0,0 +1,498 @@
+//! Columns of the data frames that the R functions return, filled straight
+//! from the events.
+
+use crate::mjai::Event;
+use crate::Tile;
+
+use savvy::{
+    NotAvailableValue, OwnedIntegerSexp, OwnedListSexp, OwnedLogicalSexp, OwnedRealSexp,
+    OwnedStringSexp,
+};
+
+/// `game_info`, one row for each game.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct GameInfo {
+    pub game_id: Vec<i32>,
+    pub names: Vec<Vec<String>>,
+    /// `kyoku_first` of `start_game`.
+    pub qijia: Vec<u8>,
+    /// `aka_flag` of `start_game`.
+    pub aka: Vec<bool>,
+    pub lobby_tier: Vec<Option<&'static str>>,
+    pub dan: Vec<Option<Vec<String>>>,
+    pub rate: Vec<Option<Vec<f64>>>,
+    /// The rest are from `end_game`.
+    pub final_scores: Vec<Option<Vec<i32>>>,
+    pub ranks: Vec<Option<Vec<i32>>>,
+    pub points: Vec<Option<Vec<f64>>>,
+}
+
+/// `round_info`, one row for each `start_kyoku`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct RoundInfo {
+    pub game_id: Vec<i32>,
+    /// Counts from 1 in each game.
+    pub round_id: Vec<i32>,
+    pub bakaze: Vec<Tile>,
+    pub dora_marker: Vec<Tile>,
+    pub kyoku: Vec<u8>,
+    pub honba: Vec<u8>,
+    pub kyotaku: Vec<u8>,
+    pub oya: Vec<u8>,
+    pub scores: Vec<Vec<i32>>,
+    pub tehais: Vec<Vec<[Tile; 13]>>,
+}
+
+/// `paifu`, one row for each event other than `start_game`, `start_kyoku`,
+/// `end_kyoku` and `end_game`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Paifu {
+    pub game_id: Vec<i32>,
+    pub round_id: Vec<i32>,
+    /// Counts from 1 in each game.
+    pub event_id: Vec<i32>,
+    pub r#type: Vec<&'static str>,
+    pub actor: Vec<Option<u8>>,
+    pub target: Vec<Option<u8>>,
+    pub pai: Vec<Option<Tile>>,
+    pub tsumogiri: Vec<Option<bool>>,
+    pub consumed: Vec<Option<Vec<Tile>>>,
+    pub dora_marker: Vec<Option<Tile>>,
+    pub deltas: Vec<Option<Vec<i32>>>,
+    pub ura_markers: Vec<Option<Vec<Tile>>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Frames {
+    pub game_info: GameInfo,
+    pub round_info: RoundInfo,
+    pub paifu: Paifu,
+}
+
+impl Frames {
+    /// Adds the rows of a converted game.
+    pub fn push_game(&mut self, game_id: i32, events: &[Event]) {
+        let mut round_id = 0;
+        let mut event_id = 0;
+
+        for event in events {
+            match event {
+                Event::StartGame {
+                    names,
+                    kyoku_first,
+                    aka_flag,
+                    lobby_tier,
+                    meta,
+                } => {
+                    let info = &mut self.game_info;
+                    info.game_id.push(game_id);
+                    info.names.push(names.clone());
+                    info.qijia.push(*kyoku_first);
+                    info.aka.push(*aka_flag);
+                    info.lobby_tier.push(lobby_tier.map(|t| t.as_str()));
+                    info.dan.push(meta.dan.clone());
+                    info.rate.push(meta.rate.clone());
+                    info.final_scores.push(None);
+                    info.ranks.push(None);
+                    info.points.push(None);
+                }
+                Event::StartKyoku {
+                    bakaze,
+                    dora_marker,
+                    kyoku,
+                    honba,
+                    kyotaku,
+                    oya,
+                    scores,
+                    tehais,
+                } => {
+                    round_id += 1;
+                    let info = &mut self.round_info;
+                    info.game_id.push(game_id);
+                    info.round_id.push(round_id);
+                    info.bakaze.push(*bakaze);
+                    info.dora_marker.push(*dora_marker);
+                    info.kyoku.push(*kyoku);
+                    info.honba.push(*honba);
+                    info.kyotaku.push(*kyotaku);
+                    info.oya.push(*oya);
+                    info.scores.push(scores.clone());
+                    info.tehais.push(tehais.clone());
+                }
+                Event::EndGame { info: Some(result) } => {
+                    let info = &mut self.game_info;
+                    if let Some(last) = info.final_scores.last_mut() {
+                        *last = Some(result.scores.clone());
+                    }
+                    if let Some(last) = info.ranks.last_mut() {
+                        *last = Some(result.ranks.iter().map(|&r| r.into()).collect());
+                    }
+                    if let Some(last) = info.points.last_mut() {
+                        *last = Some(result.points.clone());
+                    }
+                }
+                Event::EndKyoku | Event::EndGame { .. } => (),
+                _ => {
+                    event_id += 1;
+                    self.paifu.push(game_id, round_id, event_id, event);
+                }
+            }
+        }
+    }
+
+    /// Moves all the rows of `other` after those of `self`.
+    pub fn append(&mut self, other: &mut Self) {
+        self.game_info.append(&mut other.game_info);
+        self.round_info.append(&mut other.round_info);
+        self.paifu.append(&mut other.paifu);
+    }
+
+    /// Converts into a named list of `game_info`, `round_info` and `paifu`,
+    /// each a named list of columns.
+    pub fn to_sexp(&self) -> savvy::Result<OwnedListSexp> {
+        let mut out = OwnedListSexp::new(3, true)?;
+        out.set_name_and_value(0, "game_info", self.game_info.to_sexp()?)?;
+        out.set_name_and_value(1, "round_info", self.round_info.to_sexp()?)?;
+        out.set_name_and_value(2, "paifu", self.paifu.to_sexp()?)?;
+        Ok(out)
+    }
+}
+
+impl GameInfo {
+    fn append(&mut self, other: &mut Self) {
+        self.game_id.append(&mut other.game_id);
+        self.names.append(&mut other.names);
+        self.qijia.append(&mut other.qijia);
+        self.aka.append(&mut other.aka);
+        self.lobby_tier.append(&mut other.lobby_tier);
+        self.dan.append(&mut other.dan);
+        self.rate.append(&mut other.rate);
+        self.final_scores.append(&mut other.final_scores);
+        self.ranks.append(&mut other.ranks);
+        self.points.append(&mut other.points);
+    }
+
+    fn to_sexp(&self) -> savvy::Result<OwnedListSexp> {
+        let mut out = OwnedListSexp::new(10, true)?;
+        out.set_name_and_value(0, "game_id", ints(&self.game_id)?)?;
+        out.set_name_and_value(1, "names", list(&self.names, |v| strs(v))?)?;
+        out.set_name_and_value(2, "qijia", bytes(&self.qijia)?)?;
+        out.set_name_and_value(3, "aka", lgls(self.aka.iter().map(|&x| Some(x)))?)?;
+        out.set_name_and_value(4, "lobby_tier", opt_strs(self.lobby_tier.iter().copied())?)?;
+        out.set_name_and_value(
+            5,
+            "dan",
+            list(&self.dan, |v| match v {
+                Some(v) => strs(v),
+                None => strs(&[<&str>::na()]),
+            })?,
+        )?;
+        out.set_name_and_value(
+            6,
+            "rate",
+            list(&self.rate, |v| reals(or_na(v, &[f64::na()])))?,
+        )?;
+        out.set_name_and_value(
+            7,
+            "final_scores",
+            list(&self.final_scores, |v| ints(or_na(v, &[i32::na()])))?,
+        )?;
+        out.set_name_and_value(
+            8,
+            "ranks",
+            list(&self.ranks, |v| ints(or_na(v, &[i32::na()])))?,
+        )?;
+        out.set_name_and_value(
+            9,
+            "points",
+            list(&self.points, |v| reals(or_na(v, &[f64::na()])))?,
+        )?;
+        Ok(out)
+    }
+}
+
+impl RoundInfo {
+    fn append(&mut self, other: &mut Self) {
+        self.game_id.append(&mut other.game_id);
+        self.round_id.append(&mut other.round_id);
+        self.bakaze.append(&mut other.bakaze);
+        self.dora_marker.append(&mut other.dora_marker);
+        self.kyoku.append(&mut other.kyoku);
+        self.honba.append(&mut other.honba);
+        self.kyotaku.append(&mut other.kyotaku);
+        self.oya.append(&mut other.oya);
+        self.scores.append(&mut other.scores);
+        self.tehais.append(&mut other.tehais);
+    }
+
+    fn to_sexp(&self) -> savvy::Result<OwnedListSexp> {
+        let mut out = OwnedListSexp::new(10, true)?;
+        out.set_name_and_value(0, "game_id", ints(&self.game_id)?)?;
+        out.set_name_and_value(1, "round_id", ints(&self.round_id)?)?;
+        out.set_name_and_value(2, "bakaze", tiles(&self.bakaze)?)?;
+        out.set_name_and_value(3, "dora_marker", tiles(&self.dora_marker)?)?;
+        out.set_name_and_value(4, "kyoku", bytes(&self.kyoku)?)?;
+        out.set_name_and_value(5, "honba", bytes(&self.honba)?)?;
+        out.set_name_and_value(6, "kyotaku", bytes(&self.kyotaku)?)?;
+        out.set_name_and_value(7, "oya", bytes(&self.oya)?)?;
+        out.set_name_and_value(8, "scores", list(&self.scores, |v| ints(v))?)?;
+        out.set_name_and_value(9, "tehais", list(&self.tehais, |v| tehais(v))?)?;
+        Ok(out)
+    }
+}
+
+impl Paifu {
+    fn push(&mut self, game_id: i32, round_id: i32, event_id: i32, event: &Event) {
+        let (mut target, mut pai, mut tsumogiri, mut consumed) = (None, None, None, None);
+        let (mut dora_marker, mut deltas, mut ura_markers) = (None, None, None);
+        match event {
+            Event::Tsumo { pai: p, .. } | Event::Nukidora { pai: p, .. } => pai = Some(*p),
+            Event::Dahai {
+                pai: p,
+                tsumogiri: t,
+                ..
+            } => {
+                pai = Some(*p);
+                tsumogiri = Some(*t);
+            }
+            Event::Chi {
+                target: t,
+                pai: p,
+                consumed: c,
+                ..
+            }
+            | Event::Pon {
+                target: t,
+                pai: p,
+                consumed: c,
+                ..
+            } => {
+                target = Some(*t);
+                pai = Some(*p);
+                consumed = Some(c.to_vec());
+            }
+            Event::Daiminkan {
+                target: t,
+                pai: p,
+                consumed: c,
+                ..
+            } => {
+                target = Some(*t);
+                pai = Some(*p);
+                consumed = Some(c.to_vec());
+            }
+            Event::Kakan {
+                pai: p,
+                consumed: c,
+                ..
+            } => {
+                pai = Some(*p);
+                consumed = Some(c.to_vec());
+            }
+            Event::Ankan { consumed: c, .. } => consumed = Some(c.to_vec()),
+            Event::Dora { dora_marker: d } => dora_marker = Some(*d),
+            Event::Hora {
+                target: t,
+                deltas: d,
+                ura_markers: u,
+                ..
+            } => {
+                target = Some(*t);
+                deltas.clone_from(d);
+                ura_markers.clone_from(u);
+            }
+            Event::Ryukyoku { deltas: d, .. } => deltas.clone_from(d),
+            _ => (),
+        }
+
+        self.game_id.push(game_id);
+        self.round_id.push(round_id);
+        self.event_id.push(event_id);
+        self.r#type.push(event.type_name());
+        self.actor.push(event.actor());
+        self.target.push(target);
+        self.pai.push(pai);
+        self.tsumogiri.push(tsumogiri);
+        self.consumed.push(consumed);
+        self.dora_marker.push(dora_marker);
+        self.deltas.push(deltas);
+        self.ura_markers.push(ura_markers);
+    }
+
+    fn append(&mut self, other: &mut Self) {
+        self.game_id.append(&mut other.game_id);
+        self.round_id.append(&mut other.round_id);
+        self.event_id.append(&mut other.event_id);
+        self.r#type.append(&mut other.r#type);
+        self.actor.append(&mut other.actor);
+        self.target.append(&mut other.target);
+        self.pai.append(&mut other.pai);
+        self.tsumogiri.append(&mut other.tsumogiri);
+        self.consumed.append(&mut other.consumed);
+        self.dora_marker.append(&mut other.dora_marker);
+        self.deltas.append(&mut other.deltas);
+        self.ura_markers.append(&mut other.ura_markers);
+    }
+
+    fn to_sexp(&self) -> savvy::Result<OwnedListSexp> {
+        let opt_tiles = |v: &[Option<Tile>]| opt_strs(v.iter().map(|x| x.map(Tile::as_str)));
+        let tiles_or_na = |v: &Option<Vec<Tile>>| match v {
+            Some(v) => tiles(v),
+            None => strs(&[<&str>::na()]),
+        };
+
+        let mut out = OwnedListSexp::new(12, true)?;
+        out.set_name_and_value(0, "game_id", ints(&self.game_id)?)?;
+        out.set_name_and_value(1, "round_id", ints(&self.round_id)?)?;
+        out.set_name_and_value(2, "event_id", ints(&self.event_id)?)?;
+        out.set_name_and_value(3, "type", strs(&self.r#type)?)?;
+        out.set_name_and_value(4, "actor", opt_bytes(&self.actor)?)?;
+        out.set_name_and_value(5, "target", opt_bytes(&self.target)?)?;
+        out.set_name_and_value(6, "pai", opt_tiles(&self.pai)?)?;
+        out.set_name_and_value(7, "tsumogiri", lgls(self.tsumogiri.iter().copied())?)?;
+        out.set_name_and_value(8, "consumed", list(&self.consumed, tiles_or_na)?)?;
+        out.set_name_and_value(9, "dora_marker", opt_tiles(&self.dora_marker)?)?;
+        out.set_name_and_value(
+            10,
+            "deltas",
+            // Doubles, as the JSON path gave.
+            list(&self.deltas, |v| match v {
+                Some(v) => OwnedRealSexp::try_from_iter(v.iter().map(|&d| f64::from(d))),
+                None => reals(&[f64::na()]),
+            })?,
+        )?;
+        out.set_name_and_value(11, "ura_markers", list(&self.ura_markers, tiles_or_na)?)?;
+        Ok(out)
+    }
+}
+
+// The builders below return owned vectors, which stay protected until they
+// are set into their list.
+
+fn ints(values: &[i32]) -> savvy::Result<OwnedIntegerSexp> {
+    OwnedIntegerSexp::try_from_slice(values)
+}
+
+fn bytes(values: &[u8]) -> savvy::Result<OwnedIntegerSexp> {
+    OwnedIntegerSexp::try_from_iter(values.iter().map(|&x| i32::from(x)))
+}
+
+fn opt_bytes(values: &[Option<u8>]) -> savvy::Result<OwnedIntegerSexp> {
+    OwnedIntegerSexp::try_from_iter(values.iter().map(|x| x.map_or_else(i32::na, i32::from)))
+}
+
+fn reals(values: &[f64]) -> savvy::Result<OwnedRealSexp> {
+    OwnedRealSexp::try_from_slice(values)
+}
+
+fn lgls(values: impl ExactSizeIterator<Item = Option<bool>>) -> savvy::Result<OwnedLogicalSexp> {
+    let mut out = OwnedLogicalSexp::new(values.len())?;
+    for (i, x) in values.enumerate() {
+        match x {
+            Some(x) => out.set_elt(i, x)?,
+            None => out.set_na(i)?,
+        }
+    }
+    Ok(out)
+}
+
+fn strs<S: AsRef<str>>(values: &[S]) -> savvy::Result<OwnedStringSexp> {
+    OwnedStringSexp::try_from_slice(values)
+}
+
+fn opt_strs<'a>(values: impl Iterator<Item = Option<&'a str>>) -> savvy::Result<OwnedStringSexp> {
+    OwnedStringSexp::try_from_iter(values.map(|x| x.unwrap_or_else(<&str>::na)))
+}
+
+fn tiles(values: &[Tile]) -> savvy::Result<OwnedStringSexp> {
+    OwnedStringSexp::try_from_iter(values.iter().map(|t| t.as_str()))
+}
+
+/// A character matrix with a row for each seat, as `fparse` used to give.
+fn tehais(values: &[[Tile; 13]]) -> savvy::Result<OwnedStringSexp> {
+    let nrow = values.len();
+    let mut out = OwnedStringSexp::new(nrow * 13)?;
+    for (row, tehai) in values.iter().enumerate() {
+        for (col, tile) in tehai.iter().enumerate() {
+            // R matrices are column-major.
+            out.set_elt(col * nrow + row, tile.as_str())?;
+        }
+    }
+    out.set_dim(&[nrow, 13])?;
+    Ok(out)
+}
+
+/// A list column with an element made by `f` for each of `values`.
+fn list<T, S, F>(values: &[T], f: F) -> savvy::Result<OwnedListSexp>
+where
+    S: Into<savvy::Sexp>,
+    F: Fn(&T) -> savvy::Result<S>,
+{
+    let mut out = OwnedListSexp::new(values.len(), false)?;
+    for (i, v) in values.iter().enumerate() {
+        out.set_value(i, f(v)?)?;
+    }
+    Ok(out)
+}
+
+/// The element of a list column, a single NA when missing.
+fn or_na<'a, T>(values: &'a Option<Vec<T>>, na: &'a [T; 1]) -> &'a [T] {
+    values.as_deref().unwrap_or(na)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tenhou::Log;
+    use crate::{tenhou_to_mjai, testdata};
+
+    #[test]
+    fn rows() {
+        let log = Log::from_json_str(&testdata::read("output_log.example.json")).unwrap();
+        let events = tenhou_to_mjai(&log).unwrap();
+
+        let mut frames = Frames::default();
+        frames.push_game(1, &events);
+        let mut second = Frames::default();
+        second.push_game(2, &events);
+        frames.append(&mut second);
+
+        let num_kyokus = log.kyokus.len();
+        let num_events = events
+            .iter()
+            .filter(|ev| {
+                !matches!(
+                    ev,
+                    Event::StartGame { .. }
+                        | Event::StartKyoku { .. }
+                        | Event::EndKyoku
+                        | Event::EndGame { .. }
+                )
+            })
+            .count();
+
+        assert_eq!(frames.game_info.game_id, [1, 2]);
+        assert!(frames.game_info.final_scores.iter().all(Option::is_some));
+        assert_eq!(frames.round_info.game_id.len(), num_kyokus * 2);
+        assert_eq!(frames.round_info.round_id[num_kyokus], 1);
+        assert_eq!(
+            frames.round_info.round_id[num_kyokus - 1],
+            num_kyokus as i32
+        );
+
+        let paifu = &frames.paifu;
+        assert_eq!(paifu.game_id.len(), num_events * 2);
+        assert_eq!(paifu.event_id[num_events - 1], num_events as i32);
+        assert_eq!(paifu.event_id[num_events], 1);
+        assert_eq!(paifu.round_id.last(), Some(&(num_kyokus as i32)));
+        for (i, ty) in paifu.r#type.iter().enumerate() {
+            match *ty {
+                "dahai" => assert!(paifu.pai[i].is_some() && paifu.tsumogiri[i].is_some()),
+                "chi" | "pon" => assert_eq!(paifu.consumed[i].as_ref().map(Vec::len), Some(2)),
+                "hora" => assert!(paifu.deltas[i].is_some() && paifu.target[i].is_some()),
+                "dora" => assert!(paifu.dora_marker[i].is_some()),
+                _ => (),
+            }
+        }
+    }
+}
@@ -1,10 +1,28 @@
+use crate::tenhou::{self, Log, RawLog};
+use crate::{tu8, Event};
+
 use std::str::FromStr;
 
 use thiserror::Error;
 
+/// Kyokus to keep, parsed from comma-separated rules such as
+/// `"E1-S2.*,!E3,last"`.
+///
+/// Each rule selects kyokus and optionally their honba after a dot:
+///
+/// * `E1`, or a range such as `E1-S2`, by bakaze and kyoku number.
+/// * `last` for all-last, the kyoku number of the last kyoku in the game.
+/// * `first-3` for the first three kyokus in the game, honba counted.
+///
+/// The honba is `3`, `*` for any, or a comparison such as `>=2`, `>2`,
+/// `<=1` or `<1`. It defaults to 0 for kyoku numbers, and to any for `last`
+/// and `first-n`. A rule starting with `!` drops what it selects.
+///
+/// A kyoku passes if any rule without `!` selects it, or if there is no such
+/// rule, and no rule with `!` does.
 #[derive(Debug, Clone)]
 pub struct KyokuFilter {
-    whitelist: [Vec<u8>; 16],
+    rules: Vec<Rule>,
 }
 
 #[derive(Debug, Error)]
@@ -23,68 +41,300 @@ pub enum ParseError {
 
     #[error("invalid kyoku range: {0:?} (expected within [1, 4])")]
     InvalidKyokuRange(u8),
+
+    #[error("invalid range: {0:?} (expected the first kyoku not after the last)")]
+    InvalidRange(String),
+    #[error("invalid count: {0:?}")]
+    InvalidCount(#[source] <usize as FromStr>::Err),
+}
+
+/// Picks kyokus of a game, such as [`KyokuFilter`] by their numbers and
+/// [`KyokuPredicate`](crate::KyokuPredicate) by what happens in them.
+pub trait KyokuSelector {
+    /// Tests each kyoku of `log` in order.
+    fn select_kyokus(&self, log: &Log) -> Vec<bool>;
+
+    /// Tests each kyoku of a raw `log` in order. The default parses a copy of
+    /// it, returning the error if that fails.
+    fn select_raw_kyokus(&self, log: &RawLog) -> Result<Vec<bool>, tenhou::ParseError> {
+        Log::try_from(log.clone()).map(|log| self.select_kyokus(&log))
+    }
+}
+
+/// Where a kyoku is in its game, which `last` and `first-n` depend on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KyokuPosition {
+    /// Counts from 0 in the game.
+    pub index: usize,
+    /// The kyoku number of the last kyoku in the game.
+    pub last_kyoku: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rule {
+    negated: bool,
+    selector: Selector,
+    honba: Honba,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Selector {
+    /// Kyoku numbers from 0 for E1 to 15 for N4, both ends included.
+    Range(u8, u8),
+    Last,
+    First(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Honba {
+    Any,
+    Eq(u8),
+    Ge(u8),
+    Gt(u8),
+    Le(u8),
+    Lt(u8),
 }
 
 impl FromStr for KyokuFilter {
     type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut whitelist: [Vec<u8>; 16] = Default::default();
-
-        for part in s.split(',') {
-            let mut chars = part.chars();
-
-            let bakaze = chars
-                .next()
-                .map(|p| p.to_uppercase().to_string())
-                .ok_or(ParseError::MissingBakaze)?;
-
-            let offset = match bakaze.as_str() {
-                "E" => 0,
-                "S" => 4,
-                "W" => 8,
-                "N" => 12,
-                _ => return Err(ParseError::InvalidBakaze(bakaze)),
-            };
-            let kyoku_num: u8 = chars
-                .next()
-                .ok_or(ParseError::MissingKyoku)?
-                .to_string()
-                .parse()
-                .map_err(ParseError::InvalidKyoku)?;
-
-            if !(1..=4).contains(&kyoku_num) {
-                return Err(ParseError::InvalidKyokuRange(kyoku_num));
-            }
+        let rules = s.split(',').map(parse_rule).collect::<Result<_, _>>()?;
+        Ok(Self { rules })
+    }
+}
+
+fn parse_rule(part: &str) -> Result<Rule, ParseError> {
+    let part = part.trim();
+    let (negated, part) = match part.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, part),
+    };
+    let (selector, honba) = match part.split_once('.') {
+        Some((selector, honba)) => (selector, Some(honba)),
+        None => (part, None),
+    };
 
-            let kyoku = offset + kyoku_num - 1;
-            let honba = if chars.next() == Some('.') {
-                chars
-                    .collect::<String>()
-                    .parse()
-                    .map_err(ParseError::InvalidHonba)?
-            } else {
-                0
-            };
-
-            whitelist[kyoku as usize].push(honba);
+    let selector = if selector.eq_ignore_ascii_case("last") {
+        Selector::Last
+    } else if let Some(n) = selector
+        .get(..6)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("first-"))
+        .map(|_| &selector[6..])
+    {
+        Selector::First(n.parse().map_err(ParseError::InvalidCount)?)
+    } else if let Some((first, last)) = selector.split_once('-') {
+        let (first, last) = (parse_kyoku(first)?, parse_kyoku(last)?);
+        if first > last {
+            return Err(ParseError::InvalidRange(selector.to_owned()));
         }
+        Selector::Range(first, last)
+    } else {
+        let kyoku = parse_kyoku(selector)?;
+        Selector::Range(kyoku, kyoku)
+    };
+
+    let honba = match honba {
+        Some(honba) => parse_honba(honba)?,
+        None if matches!(selector, Selector::Range(..)) => Honba::Eq(0),
+        None => Honba::Any,
+    };
+
+    Ok(Rule {
+        negated,
+        selector,
+        honba,
+    })
+}
+
+/// Parses such as "E1" into 0 and "N4" into 15.
+fn parse_kyoku(s: &str) -> Result<u8, ParseError> {
+    let mut chars = s.chars();
+    let bakaze = chars
+        .next()
+        .map(|p| p.to_uppercase().to_string())
+        .ok_or(ParseError::MissingBakaze)?;
+
+    let offset = match bakaze.as_str() {
+        "E" => 0,
+        "S" => 4,
+        "W" => 8,
+        "N" => 12,
+        _ => return Err(ParseError::InvalidBakaze(bakaze)),
+    };
+    let rest = chars.as_str();
+    if rest.is_empty() {
+        return Err(ParseError::MissingKyoku);
+    }
+    let kyoku_num: u8 = rest.parse().map_err(ParseError::InvalidKyoku)?;
+
+    if !(1..=4).contains(&kyoku_num) {
+        return Err(ParseError::InvalidKyokuRange(kyoku_num));
+    }
+    Ok(offset + kyoku_num - 1)
+}
+
+/// Writes a kyoku number and honba the way they are parsed, such as 4 and 1
+/// into "S1.1".
+pub(crate) fn kyoku_label(kyoku: u8, honba: u8) -> String {
+    let bakaze = ["E", "S", "W", "N"][usize::from(kyoku / 4 % 4)];
+    format!("{bakaze}{}.{honba}", kyoku % 4 + 1)
+}
+
+fn parse_honba(s: &str) -> Result<Honba, ParseError> {
+    if s == "*" {
+        return Ok(Honba::Any);
+    }
+    let (make, n): (fn(u8) -> Honba, _) = if let Some(n) = s.strip_prefix(">=") {
+        (Honba::Ge, n)
+    } else if let Some(n) = s.strip_prefix("<=") {
+        (Honba::Le, n)
+    } else if let Some(n) = s.strip_prefix('>') {
+        (Honba::Gt, n)
+    } else if let Some(n) = s.strip_prefix('<') {
+        (Honba::Lt, n)
+    } else {
+        (Honba::Eq, s)
+    };
+    Ok(make(n.parse().map_err(ParseError::InvalidHonba)?))
+}
+
+impl Honba {
+    const fn test(self, honba: u8) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Eq(n) => honba == n,
+            Self::Ge(n) => honba >= n,
+            Self::Gt(n) => honba > n,
+            Self::Le(n) => honba <= n,
+            Self::Lt(n) => honba < n,
+        }
+    }
+}
 
-        Ok(Self { whitelist })
+impl Rule {
+    fn test(&self, kyoku: u8, honba: u8, position: Option<KyokuPosition>) -> bool {
+        let selected = match self.selector {
+            Selector::Range(first, last) => (first..=last).contains(&kyoku),
+            Selector::Last => position.is_some_and(|p| p.last_kyoku == kyoku),
+            Selector::First(n) => position.is_some_and(|p| p.index < n),
+        };
+        selected && self.honba.test(honba)
     }
 }
 
 impl KyokuFilter {
+    /// Tests a kyoku out of its game, where `last` and `first-n` select
+    /// nothing. Use [`KyokuFilter::test_at`] or [`KyokuFilter::select`] for
+    /// them.
     #[inline]
     #[must_use]
     pub fn test(&self, kyoku: u8, honba: u8) -> bool {
-        kyoku < 16 && self.whitelist[kyoku as usize].contains(&honba)
+        self.test_with(kyoku, honba, None)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn test_at(&self, kyoku: u8, honba: u8, position: KyokuPosition) -> bool {
+        self.test_with(kyoku, honba, Some(position))
+    }
+
+    /// Tests each of `kyokus`, pairs of kyoku number and honba of a game in
+    /// order.
+    #[must_use]
+    pub fn select(&self, kyokus: &[(u8, u8)]) -> Vec<bool> {
+        let Some(&(last_kyoku, _)) = kyokus.last() else {
+            return vec![];
+        };
+        kyokus
+            .iter()
+            .enumerate()
+            .map(|(index, &(kyoku, honba))| {
+                self.test_at(kyoku, honba, KyokuPosition { index, last_kyoku })
+            })
+            .collect()
+    }
+
+    fn test_with(&self, kyoku: u8, honba: u8, position: Option<KyokuPosition>) -> bool {
+        let mut included = self.rules.iter().filter(|r| !r.negated).peekable();
+        let is_included =
+            included.peek().is_none() || included.any(|r| r.test(kyoku, honba, position));
+        is_included
+            && !self
+                .rules
+                .iter()
+                .any(|r| r.negated && r.test(kyoku, honba, position))
+    }
+
+    /// Drops each kyoku not passing the filter from converted events, for
+    /// sources such as mjlog that are not filtered before conversion.
+    ///
+    /// Kyokus whose bakaze is not a wind are dropped too, and do not count
+    /// for `last` and `first-n`.
+    pub fn filter_events(&self, events: &mut Vec<Event>) {
+        let kyokus: Vec<_> = events
+            .iter()
+            .filter_map(|event| match *event {
+                Event::StartKyoku {
+                    bakaze,
+                    kyoku,
+                    honba,
+                    ..
+                } => {
+                    let bakaze_num = match bakaze.as_u8() {
+                        tu8!(E) => Some(0),
+                        tu8!(S) => Some(1),
+                        tu8!(W) => Some(2),
+                        tu8!(N) => Some(3),
+                        _ => None,
+                    };
+                    Some(bakaze_num.map(|b| (b * 4 + kyoku.saturating_sub(1) % 4, honba)))
+                }
+                _ => None,
+            })
+            .collect();
+        let known: Vec<_> = kyokus.iter().flatten().copied().collect();
+        let mut selected_known = self.select(&known).into_iter();
+        let mut selected = kyokus
+            .iter()
+            .map(|k| k.is_some() && selected_known.next().unwrap_or(false));
+
+        let mut keep = true;
+        events.retain(|event| {
+            match event {
+                Event::StartKyoku { .. } => keep = selected.next().unwrap_or(false),
+                Event::EndGame { .. } => keep = true,
+                _ => (),
+            }
+            keep
+        });
+    }
+}
+
+impl KyokuSelector for KyokuFilter {
+    fn select_kyokus(&self, log: &Log) -> Vec<bool> {
+        let kyokus: Vec<_> = log
+            .kyokus
+            .iter()
+            .map(|k| (k.meta.kyoku_num, k.meta.honba))
+            .collect();
+        self.select(&kyokus)
+    }
+
+    fn select_raw_kyokus(&self, log: &RawLog) -> Result<Vec<bool>, tenhou::ParseError> {
+        let kyokus: Vec<_> = log
+            .logs
+            .iter()
+            .map(|k| (k.meta.kyoku_num, k.meta.honba))
+            .collect();
+        Ok(self.select(&kyokus))
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{mjlog, t, testdata};
 
     #[test]
     fn filter() {
@@ -98,8 +348,104 @@ mod test {
         assert!(!kf.test(2, 10));
         assert!(kf.test(2, 11));
 
+        assert_eq!(kyoku_label(6, 1), "S3.1");
+        let kf: KyokuFilter = kyoku_label(15, 2).parse().unwrap();
+        assert!(kf.test(15, 2));
+
         "e9".parse::<KyokuFilter>().unwrap_err();
         "w0".parse::<KyokuFilter>().unwrap_err();
         "".parse::<KyokuFilter>().unwrap_err();
     }
+
+    #[test]
+    fn grammar() {
+        let kf: KyokuFilter = "E2-S1.*".parse().unwrap();
+        assert!(!kf.test(0, 0));
+        assert!(kf.test(1, 0));
+        assert!(kf.test(4, 5));
+        assert!(!kf.test(5, 0));
+
+        let kf: KyokuFilter = "E1-N4.>=3".parse().unwrap();
+        assert!(!kf.test(7, 2));
+        assert!(kf.test(7, 3));
+        let kf: KyokuFilter = "S4.<2".parse().unwrap();
+        assert!(kf.test(7, 1));
+        assert!(!kf.test(7, 2));
+
+        let kf: KyokuFilter = "!E1.*,!S2".parse().unwrap();
+        assert!(!kf.test(0, 2));
+        assert!(!kf.test(5, 0));
+        assert!(kf.test(5, 1));
+        let kf: KyokuFilter = "E1-E4.*, !E3.*".parse().unwrap();
+        assert!(kf.test(1, 0));
+        assert!(!kf.test(2, 0));
+        assert!(!kf.test(4, 0));
+
+        let kyokus = [(0, 0), (1, 0), (1, 1), (7, 0), (7, 1)];
+        let kf: KyokuFilter = "last".parse().unwrap();
+        assert_eq!(kf.select(&kyokus), [false, false, false, true, true]);
+        assert!(!kf.test(7, 0));
+        let kf: KyokuFilter = "first-2,last.1".parse().unwrap();
+        assert_eq!(kf.select(&kyokus), [true, true, false, false, true]);
+        let kf: KyokuFilter = "!first-1".parse().unwrap();
+        assert_eq!(kf.select(&kyokus), [false, true, true, true, true]);
+
+        "S2-E1".parse::<KyokuFilter>().unwrap_err();
+        "E1.>".parse::<KyokuFilter>().unwrap_err();
+        "first-".parse::<KyokuFilter>().unwrap_err();
+        "E12".parse::<KyokuFilter>().unwrap_err();
+        "E".parse::<KyokuFilter>().unwrap_err();
+    }
+
+    #[test]
+    fn filter_events() {
+        let xml = testdata::read("2010091009gm-00a9-0000-83af2648&tw=2.mjlog");
+        let events = mjlog::parse_str(&xml).unwrap();
+        let kyokus = |events: &[Event]| -> Vec<_> {
+            events
+                .iter()
+                .filter_map(|ev| match *ev {
+                    Event::StartKyoku {
+                        bakaze,
+                        kyoku,
+                        honba,
+                        ..
+                    } => Some((bakaze, kyoku, honba)),
+                    _ => None,
+                })
+                .collect()
+        };
+        let all = kyokus(&events);
+        assert!(all.len() > 1);
+        let (bakaze, kyoku, honba) = *all.last().unwrap();
+
+        let mut filtered = events.clone();
+        let kf: KyokuFilter = format!("{bakaze}{kyoku}.{honba}").parse().unwrap();
+        kf.filter_events(&mut filtered);
+        assert_eq!(kyokus(&filtered), [(bakaze, kyoku, honba)]);
+        assert!(matches!(filtered[0], Event::StartGame { .. }));
+        assert!(matches!(filtered[1], Event::StartKyoku { .. }));
+        assert!(matches!(filtered.last(), Some(Event::EndGame { .. })));
+
+        let mut filtered = events.clone();
+        let kf: KyokuFilter = "last".parse().unwrap();
+        kf.filter_events(&mut filtered);
+        let expected: Vec<_> = all
+            .iter()
+            .copied()
+            .filter(|&(b, k, _)| (b, k) == (bakaze, kyoku))
+            .collect();
+        assert_eq!(kyokus(&filtered), expected);
+
+        let mut filtered = events;
+        for event in &mut filtered {
+            if let Event::StartKyoku { bakaze, .. } = event {
+                *bakaze = t!(P);
+                break;
+            }
+        }
+        let kf: KyokuFilter = "E1-N4.*".parse().unwrap();
+        kf.filter_events(&mut filtered);
+        assert_eq!(kyokus(&filtered), all[1..]);
+    }
 }
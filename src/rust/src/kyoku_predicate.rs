@@ -0,0 +1,495 @@
+use crate::tenhou::{ActionItem, EndStatus, Kyoku, Log};
+use crate::{t, Event, KyokuSelector};
+
+use std::iter::Peekable;
+use std::str::{CharIndices, FromStr};
+
+use thiserror::Error;
+
+/// Tests what happens in a kyoku, parsed from expressions such as
+/// `"oya_hora | reach_before(6) & !player(\"A\")"`.
+///
+/// The atoms are:
+///
+/// * `hora`, the kyoku ends in hora.
+/// * `oya_hora`, the dealer is one of those who win.
+/// * `ryukyoku`, the kyoku ends in a draw.
+/// * `tenpai(n)`, the kyoku ends in a draw with exactly `n` players tenpai.
+/// * `reach_before(n)`, someone declares riichi before their `n`th discard.
+/// * `player(name)`, a player of the game is named `name`, which may be
+///   quoted.
+///
+/// They combine with `!`, `&` and `|` in order of precedence, and
+/// parentheses. Predicates can also be built with [`KyokuPredicate::and`],
+/// [`KyokuPredicate::or`] and [`KyokuPredicate::not`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KyokuPredicate {
+    Hora,
+    OyaHora,
+    Ryukyoku,
+    Tenpai(u8),
+    ReachBefore(u8),
+    Player(String),
+
+    Not(Box<Self>),
+    All(Vec<Self>),
+    Any(Vec<Self>),
+}
+
+#[derive(Debug, Error)]
+pub enum ParseError {
+    #[error("unexpected end of predicate")]
+    UnexpectedEnd,
+    #[error("unexpected {0:?} at {1}")]
+    Unexpected(char, usize),
+
+    #[error("unknown predicate: {0:?}")]
+    UnknownPredicate(String),
+    #[error("missing argument of {0:?}")]
+    MissingArgument(String),
+    #[error("invalid count: {0:?}")]
+    InvalidCount(#[source] <u8 as FromStr>::Err),
+}
+
+/// What predicates look at in a kyoku, told the same way from either
+/// source.
+struct Facts<'a> {
+    names: &'a [String],
+    oya: u8,
+    /// Those who win, empty if the kyoku ends in a draw.
+    winners: Vec<u8>,
+    /// The number of players tenpai if the kyoku ends in a draw and it is
+    /// known.
+    tenpai: Option<usize>,
+    ryukyoku: bool,
+    /// The discard each riichi is declared with, counting from 1.
+    reach_turns: Vec<u8>,
+}
+
+impl KyokuPredicate {
+    #[inline]
+    #[must_use]
+    pub fn and(self, other: Self) -> Self {
+        match self {
+            Self::All(mut preds) => {
+                preds.push(other);
+                Self::All(preds)
+            }
+            pred => Self::All(vec![pred, other]),
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn or(self, other: Self) -> Self {
+        match self {
+            Self::Any(mut preds) => {
+                preds.push(other);
+                Self::Any(preds)
+            }
+            pred => Self::Any(vec![pred, other]),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    #[inline]
+    #[must_use]
+    pub fn not(self) -> Self {
+        Self::Not(Box::new(self))
+    }
+
+    /// Tests a kyoku of a game whose players are `names`.
+    #[must_use]
+    pub fn test_kyoku(&self, kyoku: &Kyoku, names: &[String]) -> bool {
+        let (winners, tenpai, ryukyoku) = match &kyoku.end_status {
+            EndStatus::Hora { details } => (details.iter().map(|d| d.who).collect(), None, false),
+            EndStatus::Ryukyoku { tenpai, .. } => {
+                (vec![], Some(tenpai.iter().filter(|&&t| t).count()), true)
+            }
+        };
+        let reach_turns = kyoku
+            .action_tables
+            .iter()
+            .filter_map(|table| {
+                table
+                    .discards
+                    .iter()
+                    .filter(|item| match item {
+                        ActionItem::Tile(tile) => *tile != t!(?),
+                        ActionItem::Tsumogiri(_) => true,
+                        ActionItem::Naki(naki) => naki.starts_with('r'),
+                    })
+                    .position(|item| matches!(item, ActionItem::Naki(_)))
+                    .map(|i| i as u8 + 1)
+            })
+            .collect();
+
+        self.test(&Facts {
+            names,
+            oya: kyoku.meta.kyoku_num % 4,
+            winners,
+            tenpai,
+            ryukyoku,
+            reach_turns,
+        })
+    }
+
+    /// Tests a kyoku of converted events, from its `start_kyoku` to its
+    /// `end_kyoku`, of a game whose players are `names`.
+    #[must_use]
+    pub fn test_events(&self, events: &[Event], names: &[String]) -> bool {
+        let mut facts = Facts {
+            names,
+            oya: 0,
+            winners: vec![],
+            tenpai: None,
+            ryukyoku: false,
+            reach_turns: vec![],
+        };
+        let mut discards = [0_u8; 4];
+        for event in events {
+            match event {
+                &Event::StartKyoku { oya, .. } => facts.oya = oya,
+                &Event::Dahai { actor, .. } => {
+                    if let Some(d) = discards.get_mut(actor as usize) {
+                        *d += 1;
+                    }
+                }
+                &Event::Reach { actor } => {
+                    if let Some(&d) = discards.get(actor as usize) {
+                        facts.reach_turns.push(d + 1);
+                    }
+                }
+                &Event::Hora { actor, .. } => facts.winners.push(actor),
+                Event::Ryukyoku { info, .. } => {
+                    facts.ryukyoku = true;
+                    facts.tenpai = info
+                        .as_ref()
+                        .map(|info| info.tenpai.iter().filter(|&&t| t).count());
+                }
+                _ => (),
+            }
+        }
+        self.test(&facts)
+    }
+
+    /// Drops each kyoku not passing the predicate from converted events of a
+    /// whole game, for sources such as mjlog that are not filtered before
+    /// conversion.
+    pub fn filter_events(&self, events: &mut Vec<Event>) {
+        let mut names: &[String] = &[];
+        let mut selected = vec![];
+        let mut start = 0;
+        for (i, event) in events.iter().enumerate() {
+            match event {
+                Event::StartGame { names: n, .. } => names = n,
+                Event::StartKyoku { .. } => start = i,
+                Event::EndKyoku => selected.push(self.test_events(&events[start..=i], names)),
+                _ => (),
+            }
+        }
+        let mut selected = selected.into_iter();
+
+        let mut keep = true;
+        events.retain(|event| {
+            match event {
+                Event::StartKyoku { .. } => keep = selected.next().unwrap_or(false),
+                Event::EndGame { .. } => keep = true,
+                _ => (),
+            }
+            keep
+        });
+    }
+
+    fn test(&self, facts: &Facts<'_>) -> bool {
+        match self {
+            Self::Hora => !facts.winners.is_empty(),
+            Self::OyaHora => facts.winners.contains(&facts.oya),
+            Self::Ryukyoku => facts.ryukyoku,
+            &Self::Tenpai(n) => facts.tenpai == Some(n as usize),
+            &Self::ReachBefore(n) => facts.reach_turns.iter().any(|&turn| turn < n),
+            Self::Player(name) => facts.names.contains(name),
+
+            Self::Not(pred) => !pred.test(facts),
+            Self::All(preds) => preds.iter().all(|p| p.test(facts)),
+            Self::Any(preds) => preds.iter().any(|p| p.test(facts)),
+        }
+    }
+}
+
+impl KyokuSelector for KyokuPredicate {
+    fn select_kyokus(&self, log: &Log) -> Vec<bool> {
+        log.kyokus
+            .iter()
+            .map(|kyoku| self.test_kyoku(kyoku, &log.names))
+            .collect()
+    }
+}
+
+impl FromStr for KyokuPredicate {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            s,
+            chars: s.char_indices().peekable(),
+        };
+        let pred = parser.any()?;
+        match parser.next() {
+            Some((i, c)) => Err(ParseError::Unexpected(c, i)),
+            None => Ok(pred),
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    /// The next char not being whitespace.
+    fn next(&mut self) -> Option<(usize, char)> {
+        self.peek()?;
+        self.chars.next()
+    }
+
+    fn peek(&mut self) -> Option<(usize, char)> {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        self.chars.peek().copied()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        match self.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            Some((i, c)) => Err(ParseError::Unexpected(c, i)),
+            None => Err(ParseError::UnexpectedEnd),
+        }
+    }
+
+    fn any(&mut self) -> Result<KyokuPredicate, ParseError> {
+        let mut preds = vec![self.all()?];
+        while self.peek().is_some_and(|(_, c)| c == '|') {
+            self.next();
+            preds.push(self.all()?);
+        }
+        Ok(if preds.len() == 1 {
+            preds.remove(0)
+        } else {
+            KyokuPredicate::Any(preds)
+        })
+    }
+
+    fn all(&mut self) -> Result<KyokuPredicate, ParseError> {
+        let mut preds = vec![self.unary()?];
+        while self.peek().is_some_and(|(_, c)| c == '&') {
+            self.next();
+            preds.push(self.unary()?);
+        }
+        Ok(if preds.len() == 1 {
+            preds.remove(0)
+        } else {
+            KyokuPredicate::All(preds)
+        })
+    }
+
+    fn unary(&mut self) -> Result<KyokuPredicate, ParseError> {
+        match self.peek() {
+            Some((_, '!')) => {
+                self.next();
+                Ok(self.unary()?.not())
+            }
+            Some((_, '(')) => {
+                self.next();
+                let pred = self.any()?;
+                self.expect(')')?;
+                Ok(pred)
+            }
+            Some((start, c)) if c.is_ascii_alphabetic() => {
+                let mut end = start;
+                while let Some((i, c)) = self
+                    .chars
+                    .next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+                {
+                    end = i + c.len_utf8();
+                }
+                self.atom(&self.s[start..end])
+            }
+            Some((i, c)) => Err(ParseError::Unexpected(c, i)),
+            None => Err(ParseError::UnexpectedEnd),
+        }
+    }
+
+    fn atom(&mut self, name: &str) -> Result<KyokuPredicate, ParseError> {
+        let pred = match name.to_ascii_lowercase().as_str() {
+            "hora" => KyokuPredicate::Hora,
+            "oya_hora" => KyokuPredicate::OyaHora,
+            "ryukyoku" => KyokuPredicate::Ryukyoku,
+            "tenpai" => KyokuPredicate::Tenpai(self.count(name)?),
+            "reach_before" => KyokuPredicate::ReachBefore(self.count(name)?),
+            "player" => KyokuPredicate::Player(self.argument(name)?),
+            _ => return Err(ParseError::UnknownPredicate(name.to_owned())),
+        };
+        Ok(pred)
+    }
+
+    fn count(&mut self, name: &str) -> Result<u8, ParseError> {
+        self.argument(name)?
+            .parse()
+            .map_err(ParseError::InvalidCount)
+    }
+
+    /// Reads `(arg)` or `("arg")` following the name of an atom.
+    fn argument(&mut self, name: &str) -> Result<String, ParseError> {
+        if !matches!(self.peek(), Some((_, '('))) {
+            return Err(ParseError::MissingArgument(name.to_owned()));
+        }
+        self.next();
+
+        let quoted = self.peek().is_some_and(|(_, c)| c == '"');
+        if quoted {
+            self.next();
+        }
+        let close = if quoted { '"' } else { ')' };
+        let mut arg = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, c)) if c == close => break,
+                Some((_, c)) => arg.push(c),
+                None => return Err(ParseError::UnexpectedEnd),
+            }
+        }
+        if quoted {
+            self.expect(')')?;
+        } else {
+            arg.truncate(arg.trim_end().len());
+        }
+        Ok(arg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tenhou::{self, RawLog};
+    use crate::{tenhou_to_mjai, testdata};
+
+    #[test]
+    fn grammar() {
+        use KyokuPredicate::*;
+
+        let pred: KyokuPredicate = "oya_hora | reach_before(6) & !player(\"A B\")"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            pred,
+            Any(vec![
+                OyaHora,
+                All(vec![ReachBefore(6), Player("A B".to_owned()).not()]),
+            ])
+        );
+        let pred: KyokuPredicate = "(hora | ryukyoku) & tenpai( 2 )".parse().unwrap();
+        assert_eq!(pred, All(vec![Any(vec![Hora, Ryukyoku]), Tenpai(2)]));
+        let pred: KyokuPredicate = "player(ASAPIN)".parse().unwrap();
+        assert_eq!(pred, Player("ASAPIN".to_owned()));
+
+        "".parse::<KyokuPredicate>().unwrap_err();
+        "hora |".parse::<KyokuPredicate>().unwrap_err();
+        "(hora".parse::<KyokuPredicate>().unwrap_err();
+        "hora ryukyoku".parse::<KyokuPredicate>().unwrap_err();
+        "tenpai".parse::<KyokuPredicate>().unwrap_err();
+        "tenpai(x)".parse::<KyokuPredicate>().unwrap_err();
+        "tsumo".parse::<KyokuPredicate>().unwrap_err();
+        "player(\"A)".parse::<KyokuPredicate>().unwrap_err();
+    }
+
+    #[test]
+    fn kyoku_and_events_agree() {
+        let preds: Vec<KyokuPredicate> = [
+            "hora",
+            "oya_hora",
+            "ryukyoku",
+            "tenpai(0)",
+            "tenpai(2)",
+            "reach_before(6)",
+            "reach_before(10) & !oya_hora",
+        ]
+        .into_iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+
+        let mut tested = 0;
+        for (name, json) in testdata::tenhou_logs() {
+            let log = Log::from_json_str(&json).unwrap();
+            let events = tenhou_to_mjai(&log).unwrap();
+            let kyokus: Vec<_> = events
+                .split_inclusive(|ev| matches!(ev, Event::EndKyoku))
+                .filter(|evs| evs.iter().any(|ev| matches!(ev, Event::StartKyoku { .. })))
+                .collect();
+            assert_eq!(kyokus.len(), log.kyokus.len());
+
+            for pred in &preds {
+                for (kyoku, events) in log.kyokus.iter().zip(&kyokus) {
+                    assert_eq!(
+                        pred.test_kyoku(kyoku, &log.names),
+                        pred.test_events(events, &log.names),
+                        "{pred:?} on {name}",
+                    );
+                    tested += 1;
+                }
+            }
+
+            let name = &log.names[0];
+            let pred = KyokuPredicate::Player(name.clone());
+            assert!(pred.select_kyokus(&log).iter().all(|&s| s));
+            let pred = pred.not().or(KyokuPredicate::Hora);
+            let mut filtered = events.clone();
+            pred.filter_events(&mut filtered);
+            let horas = events
+                .iter()
+                .filter(|ev| matches!(ev, Event::StartKyoku { .. }))
+                .zip(&log.kyokus)
+                .filter(|(_, k)| matches!(k.end_status, EndStatus::Hora { .. }))
+                .count();
+            let kept = filtered
+                .iter()
+                .filter(|ev| matches!(ev, Event::StartKyoku { .. }))
+                .count();
+            assert_eq!(kept, horas);
+            let mut raw: RawLog = serde_json::from_str(&json).unwrap();
+            raw.filter_kyokus(&pred).unwrap();
+            assert_eq!(raw.logs.len(), horas);
+            assert!(matches!(filtered.last(), Some(Event::EndGame { .. })));
+        }
+        assert!(tested > 0);
+    }
+
+    #[test]
+    fn raw_parse_error() {
+        let (_, json) = testdata::tenhou_logs().swap_remove(0);
+        let mut raw: RawLog = serde_json::from_str(&json).unwrap();
+        raw.logs[0].haipai_0.pop();
+        let len = raw.logs.len();
+        let err = raw.filter_kyokus(&KyokuPredicate::Hora).unwrap_err();
+        assert!(matches!(
+            err,
+            tenhou::ParseError::InvalidHaipai { actor: 0, .. }
+        ));
+        assert_eq!(raw.logs.len(), len);
+    }
+
+    #[test]
+    fn actors_out_of_range() {
+        let events = [
+            Event::Dahai {
+                actor: 4,
+                pai: t!(1m),
+                tsumogiri: true,
+            },
+            Event::Reach { actor: 4 },
+            Event::EndKyoku,
+        ];
+        let pred: KyokuPredicate = "reach_before(6)".parse().unwrap();
+        assert!(!pred.test_events(&events, &[]));
+    }
+}
@@ -0,0 +1,344 @@
+//! Follows the points of a game from kyoku to kyoku.
+
+use crate::mjai::{Event, GameResult};
+use crate::Tile;
+
+/// Where the points of one kyoku went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KyokuLedger {
+    /// Index of the `start_kyoku` in the events.
+    pub index: usize,
+    pub bakaze: Tile,
+    /// Counts from 1.
+    pub kyoku: u8,
+    pub honba: u8,
+    /// Kyotaku on the table at the start.
+    pub kyotaku: u8,
+    /// As recorded in `start_kyoku`.
+    pub scores: Vec<i32>,
+    /// Seats whose reach has been accepted, putting 1000 points on the table.
+    pub deposited: Vec<bool>,
+    /// The winner who takes the kyotaku, the first one on double ron.
+    pub collector: Option<u8>,
+    /// The honba part of `deltas`.
+    pub honba_payments: Vec<i32>,
+    /// Sums of `deltas` of `hora` and `ryukyoku`, kyotaku and honba included.
+    pub deltas: Vec<i32>,
+    /// Scores after the kyoku.
+    pub final_scores: Vec<i32>,
+    /// Kyotaku carried over to the next kyoku.
+    pub kyotaku_left: u8,
+}
+
+/// A difference between the running total and the recorded scores.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScoreDrift {
+    /// Index of the `start_kyoku` or `end_game` whose scores differ.
+    pub index: usize,
+    pub expected: Vec<i32>,
+    pub actual: Vec<i32>,
+    pub expected_kyotaku: u8,
+    pub actual_kyotaku: u8,
+    /// What the difference matches in the previous kyoku, if anything.
+    pub cause: Option<DriftCause>,
+}
+
+/// A part of the points of a kyoku that a drift is exactly made of, which
+/// tells which step one side has left out or counted twice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriftCause {
+    /// 1000 points for each accepted reach.
+    ReachSticks,
+    /// Honba paid to the winner.
+    Honba,
+    /// Kyotaku taken by the winner.
+    Kyotaku,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ledger {
+    pub kyokus: Vec<KyokuLedger>,
+    pub drifts: Vec<ScoreDrift>,
+}
+
+/// Runs through a converted game, following reach sticks, honba and scores,
+/// and checks each `start_kyoku` and the result in `end_game` against the
+/// running total.
+///
+/// On double ron, honba and kyotaku go to the first winner. Missing `deltas`
+/// count as zeros.
+#[must_use]
+pub fn ledger(events: &[Event]) -> Ledger {
+    let mut ret = Ledger::default();
+    let mut current: Option<KyokuLedger> = None;
+
+    for (index, event) in events.iter().enumerate() {
+        match *event {
+            Event::StartKyoku {
+                bakaze,
+                kyoku,
+                honba,
+                kyotaku,
+                ref scores,
+                ..
+            } => {
+                if let Some(prev) = current.take() {
+                    if prev.final_scores != *scores || prev.kyotaku_left != kyotaku {
+                        ret.drifts.push(ScoreDrift {
+                            index,
+                            expected: prev.final_scores.clone(),
+                            actual: scores.clone(),
+                            expected_kyotaku: prev.kyotaku_left,
+                            actual_kyotaku: kyotaku,
+                            cause: prev.cause(scores),
+                        });
+                    }
+                    ret.kyokus.push(prev);
+                }
+                let n = scores.len();
+                current = Some(KyokuLedger {
+                    index,
+                    bakaze,
+                    kyoku,
+                    honba,
+                    kyotaku,
+                    scores: scores.clone(),
+                    deposited: vec![false; n],
+                    collector: None,
+                    honba_payments: vec![0; n],
+                    deltas: vec![0; n],
+                    final_scores: scores.clone(),
+                    kyotaku_left: kyotaku,
+                });
+            }
+            Event::ReachAccepted { actor } => {
+                if let Some(k) = &mut current {
+                    k.reach(actor);
+                }
+            }
+            Event::Hora {
+                actor,
+                target,
+                ref deltas,
+                ..
+            } => {
+                if let Some(k) = &mut current {
+                    k.hora(actor, target, deltas.as_deref());
+                }
+            }
+            Event::Ryukyoku { ref deltas, .. } => {
+                if let Some(k) = &mut current {
+                    k.apply(deltas.as_deref());
+                }
+            }
+            Event::EndGame { ref info } => {
+                let Some(k) = &current else {
+                    continue;
+                };
+                let Some(info) = info else {
+                    continue;
+                };
+                // Kyotaku left on the table go to the top.
+                let total = k.final_scores.iter().sum::<i32>() + i32::from(k.kyotaku_left) * 1000;
+                let expected = GameResult::from_scores(k.final_scores.clone(), total).scores;
+                if expected != info.scores {
+                    ret.drifts.push(ScoreDrift {
+                        index,
+                        expected,
+                        actual: info.scores.clone(),
+                        expected_kyotaku: k.kyotaku_left,
+                        actual_kyotaku: 0,
+                        cause: k.cause(&info.scores),
+                    });
+                }
+            }
+            _ => (),
+        }
+    }
+
+    ret.kyokus.extend(current);
+    ret
+}
+
+impl KyokuLedger {
+    fn reach(&mut self, actor: u8) {
+        let a = actor as usize;
+        if let Some(deposited) = self.deposited.get_mut(a) {
+            *deposited = true;
+            self.final_scores[a] -= 1000;
+            self.kyotaku_left += 1;
+        }
+    }
+
+    fn hora(&mut self, actor: u8, target: u8, deltas: Option<&[i32]>) {
+        // Only the first winner takes honba and kyotaku.
+        if self.collector.is_none() {
+            self.collector = Some(actor);
+            self.kyotaku_left = 0;
+
+            let n = self.scores.len();
+            let honba = i32::from(self.honba) * 100;
+            let payers: Vec<_> = if actor == target {
+                (0..n).filter(|&s| s != actor as usize).collect()
+            } else {
+                vec![target as usize]
+            };
+            // Ron pays for the three others.
+            let each = if actor == target { honba } else { honba * 3 };
+            for &p in &payers {
+                if let Some(payment) = self.honba_payments.get_mut(p) {
+                    *payment -= each;
+                }
+            }
+            if let Some(payment) = self.honba_payments.get_mut(actor as usize) {
+                *payment += each * payers.len() as i32;
+            }
+        }
+        self.apply(deltas);
+    }
+
+    fn apply(&mut self, deltas: Option<&[i32]>) {
+        for ((d, score), delta) in self
+            .deltas
+            .iter_mut()
+            .zip(&mut self.final_scores)
+            .zip(deltas.unwrap_or_default())
+        {
+            *d += delta;
+            *score += delta;
+        }
+    }
+
+    /// Kyotaku taken by the collector, the sticks on the table and those
+    /// deposited in the kyoku.
+    fn kyotaku_payments(&self) -> Vec<i32> {
+        let sticks = i32::from(self.kyotaku) + self.deposited.iter().filter(|&&d| d).count() as i32;
+        (0..self.scores.len())
+            .map(|s| match self.collector {
+                Some(c) if c as usize == s => sticks * 1000,
+                _ => 0,
+            })
+            .collect()
+    }
+
+    /// Tells which part of the points of the kyoku `actual` is off by.
+    fn cause(&self, actual: &[i32]) -> Option<DriftCause> {
+        let diff: Vec<_> = actual
+            .iter()
+            .zip(&self.final_scores)
+            .map(|(a, e)| a - e)
+            .collect();
+        let matches = |part: &[i32]| {
+            part.iter().any(|&p| p != 0)
+                && (diff.iter().zip(part).all(|(d, p)| d == p)
+                    || diff.iter().zip(part).all(|(d, p)| *d == -p))
+        };
+
+        let sticks: Vec<_> = self
+            .deposited
+            .iter()
+            .map(|&d| if d { -1000 } else { 0 })
+            .collect();
+        if matches(&sticks) {
+            Some(DriftCause::ReachSticks)
+        } else if matches(&self.honba_payments) {
+            Some(DriftCause::Honba)
+        } else if matches(&self.kyotaku_payments()) {
+            Some(DriftCause::Kyotaku)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tenhou::Log;
+    use crate::testdata::{self, KYOTAKU_LEFT_OUT};
+    use crate::{mjlog, tenhou_to_mjai};
+
+    fn example() -> Vec<Event> {
+        let log = Log::from_json_str(&testdata::read("output_log.example.json")).unwrap();
+        tenhou_to_mjai(&log).unwrap()
+    }
+
+    #[test]
+    fn no_drift() {
+        for (name, json) in testdata::tenhou_logs() {
+            let log = Log::from_json_str(&json).unwrap();
+            let events = tenhou_to_mjai(&log).unwrap();
+            let ledger = ledger(&events);
+            if KYOTAKU_LEFT_OUT.contains(&name.as_str()) {
+                // The final scores are made up with the kyotaku on the table.
+                assert!(ledger
+                    .drifts
+                    .iter()
+                    .all(|d| matches!(events[d.index], Event::EndGame { .. })));
+            } else {
+                assert_eq!(ledger.drifts, [], "{name}");
+            }
+            assert_eq!(ledger.kyokus.len(), log.kyokus.len());
+        }
+
+        for (name, xml) in testdata::mjlogs() {
+            let events = mjlog::parse_str(&xml).unwrap();
+            assert_eq!(ledger(&events).drifts, [], "{name}");
+        }
+
+        let ledger = ledger(&example());
+        for (k, next) in ledger.kyokus.iter().zip(&ledger.kyokus[1..]) {
+            assert_eq!(k.final_scores, next.scores);
+            assert_eq!(k.kyotaku_left, next.kyotaku);
+            let collected: i32 = k.honba_payments.iter().sum();
+            assert_eq!(collected, 0);
+        }
+    }
+
+    #[test]
+    fn drifts() {
+        let events = example();
+        let kyokus = ledger(&events).kyokus;
+
+        // Scores carried over as if a reach had cost nothing.
+        let k = kyokus
+            .iter()
+            .position(|k| k.deposited.contains(&true))
+            .unwrap();
+        let mut broken = events.clone();
+        if let Event::StartKyoku { scores, .. } = &mut broken[kyokus[k + 1].index] {
+            for (score, &d) in scores.iter_mut().zip(&kyokus[k].deposited) {
+                if d {
+                    *score += 1000;
+                }
+            }
+        }
+        // The next kyoku goes on from the broken scores and drifts back.
+        let drifts = ledger(&broken).drifts;
+        assert_eq!(drifts.len(), 2);
+        assert_eq!(drifts[0].index, kyokus[k + 1].index);
+        assert_eq!(drifts[0].cause, Some(DriftCause::ReachSticks));
+
+        // Honba left out of the deltas.
+        let k = kyokus
+            .iter()
+            .position(|k| k.honba > 0 && k.collector.is_some())
+            .unwrap();
+        let mut broken = events;
+        let kyoku_events = &mut broken[kyokus[k].index..kyokus[k + 1].index];
+        for ev in kyoku_events {
+            if let Event::Hora {
+                deltas: Some(deltas),
+                ..
+            } = ev
+            {
+                for (d, p) in deltas.iter_mut().zip(&kyokus[k].honba_payments) {
+                    *d -= p;
+                }
+            }
+        }
+        let drifts = ledger(&broken).drifts;
+        assert_eq!(drifts.len(), 1);
+        assert_eq!(drifts[0].cause, Some(DriftCause::Honba));
+    }
+}
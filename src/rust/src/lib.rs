@@ -74,13 +74,12 @@
     clippy::string_add,
     clippy::string_add_assign,
     clippy::string_lit_as_bytes,
-    clippy::string_to_string,
     clippy::suboptimal_flops,
     clippy::suspicious_to_owned,
     clippy::trait_duplication_in_bounds,
     clippy::trivially_copy_pass_by_ref,
     clippy::type_repetition_in_bounds,
-    clippy::unchecked_duration_subtraction,
+    clippy::unchecked_time_subtraction,
     clippy::undocumented_unsafe_blocks,
     clippy::unicode_not_nfc,
     clippy::uninlined_format_args,
@@ -96,285 +95,294 @@
     clippy::useless_let_if_seq
 )]
 
+mod batch;
 mod conv;
+mod frame;
 mod kyoku_filter;
+mod kyoku_predicate;
+pub mod ledger;
+pub mod machi;
 mod macros;
 mod mjai;
-mod mjlog;
+pub mod mjlog;
+mod options;
+mod reverse;
+pub mod score;
+pub mod shanten;
+pub mod state;
+#[cfg(test)]
+mod testdata;
 mod tile;
+mod validate;
 
-mod tenhou;
+pub mod tenhou;
 
-use std::str::FromStr;
-
-pub use conv::{ConvertError, tenhou_to_mjai};
-pub use kyoku_filter::KyokuFilter;
-pub use mjai::Event;
+pub use conv::{tenhou_to_mjai, tenhou_to_mjai_with, ConvertError};
+pub use kyoku_filter::{KyokuFilter, KyokuPosition, KyokuSelector};
+pub use kyoku_predicate::KyokuPredicate;
+pub use mjai::{
+    Event, GameMeta, GameResult, HandValue, HoraInfo, Limit, LobbyTier, RyukyokuInfo,
+    RyukyokuReason, Yaku,
+};
+pub use mjlog::{mjai_to_mjlog, mjlog_to_tenhou, MjlogError, WriteError};
+pub use options::{ConvertOptions, Tsumogiri, UraMarkers};
+pub use reverse::{mjai_to_tenhou, ReverseError};
 pub use tile::{tile_set_eq, Tile};
+pub use validate::{validate, Violation, ViolationKind};
 
-use quick_xml::events::Event as XmlEvent;
-use quick_xml::reader::Reader as XmlReader;
-use serde_json as json;
-
-use savvy::{savvy, savvy_err};
-use savvy::{OwnedListSexp, OwnedStringSexp, StringSexp, NotAvailableValue};
+use frame::Frames;
+use savvy::savvy;
+use savvy::{NotAvailableValue, OwnedListSexp, OwnedStringSexp, StringSexp};
 
 /// Convert 'tenhou.net/6' JSON strings into mjai log format
 ///
 /// @param x A character vector.
+/// @param lenient If `TRUE`, logs that fail to convert are left out
+/// and their error messages are given in the "errors" attribute,
+/// a character vector with `NA` for the other elements.
+/// Otherwise, the first error found is thrown.
+/// @param kyoku Kyokus to keep such as `"E1-S2.*,!E3,last"`, or `NULL` for all.
+/// See `KyokuFilter` for the syntax.
+/// @param predicate Kyokus to keep by what happens in them such as
+/// `"oya_hora | reach_before(6)"`, or `NULL` for all.
+/// See `KyokuPredicate` for the syntax.
 /// @returns A list of character vectors
 /// where each element represents one mjai event as a JSON string.
+/// `NA` for `NA` inputs and, if lenient, for logs that fail.
 /// @noRd
 #[savvy]
-fn parse_tenhou6(x: StringSexp) -> savvy::Result<savvy::Sexp> {
-    let mut out = OwnedListSexp::new(x.len(), false)?;
-
-    for (i, e) in x.iter().enumerate() {
-        if e.is_na() {
-            let mut dummy = OwnedStringSexp::new(1)?;
-            dummy.set_na(0)?;
-            out.set_value(i, dummy)?;
-            continue;
-        }
-        let tenhou_log = tenhou::Log::from_json_str(&e)?;
-        let events = tenhou_to_mjai(&tenhou_log)?;
-
-        let mut ret = OwnedStringSexp::new(events.len())?;
-        for (j, event) in events.iter().enumerate() {
-            let to_write = json::to_string(event)?;
-            ret.set_elt(j, &to_write)?;
-        }
-        out.set_value(i, ret)?;
-    }
-
-    Ok(out.into())
+fn parse_tenhou6(
+    x: StringSexp,
+    lenient: bool,
+    kyoku: Option<&str>,
+    predicate: Option<&str>,
+) -> savvy::Result<savvy::Sexp> {
+    to_json_list(
+        &x,
+        lenient,
+        Selection::new(kyoku, predicate)?,
+        tenhou6_events,
+    )
 }
 
 /// Parse mjlog XML into mjai log format
 ///
 /// @param x A character vector.
+/// @param lenient If `TRUE`, logs that fail to convert are left out
+/// and their error messages are given in the "errors" attribute,
+/// a character vector with `NA` for the other elements.
+/// Otherwise, the first error found is thrown.
+/// @param kyoku Kyokus to keep such as `"E1-S2.*,!E3,last"`, or `NULL` for all.
+/// See `KyokuFilter` for the syntax.
+/// @param predicate Kyokus to keep by what happens in them such as
+/// `"oya_hora | reach_before(6)"`, or `NULL` for all.
+/// See `KyokuPredicate` for the syntax.
 /// @returns A list of character vectors
 /// where each element represents one mjai event as a JSON string.
+/// `NA` for `NA` inputs and, if lenient, for logs that fail.
+/// @noRd
+#[savvy]
+fn parse_mjlog(
+    x: StringSexp,
+    lenient: bool,
+    kyoku: Option<&str>,
+    predicate: Option<&str>,
+) -> savvy::Result<savvy::Sexp> {
+    to_json_list(&x, lenient, Selection::new(kyoku, predicate)?, mjlog_events)
+}
+
+/// Convert 'tenhou.net/6' JSON strings into columns of data frames
+///
+/// @param x A character vector.
+/// @param lenient If `TRUE`, logs that fail to convert are left out
+/// and their error messages are given in the "errors" attribute,
+/// a character vector with `NA` for the other elements.
+/// Otherwise, the first error found is thrown.
+/// @param kyoku Kyokus to keep such as `"E1-S2.*,!E3,last"`, or `NULL` for all.
+/// See `KyokuFilter` for the syntax.
+/// @param predicate Kyokus to keep by what happens in them such as
+/// `"oya_hora | reach_before(6)"`, or `NULL` for all.
+/// See `KyokuPredicate` for the syntax.
+/// @returns A named list of `game_info`, `round_info` and `paifu`,
+/// each of which is a named list of columns.
+/// `game_id` is the position of the log in `x`.
+/// @noRd
+#[savvy]
+fn parse_tenhou6_frames(
+    x: StringSexp,
+    lenient: bool,
+    kyoku: Option<&str>,
+    predicate: Option<&str>,
+) -> savvy::Result<savvy::Sexp> {
+    to_frames(
+        &x,
+        lenient,
+        Selection::new(kyoku, predicate)?,
+        tenhou6_events,
+    )
+}
+
+/// Parse mjlog XML into columns of data frames
+///
+/// @param x A character vector.
+/// @param lenient If `TRUE`, logs that fail to convert are left out
+/// and their error messages are given in the "errors" attribute,
+/// a character vector with `NA` for the other elements.
+/// Otherwise, the first error found is thrown.
+/// @param kyoku Kyokus to keep such as `"E1-S2.*,!E3,last"`, or `NULL` for all.
+/// See `KyokuFilter` for the syntax.
+/// @param predicate Kyokus to keep by what happens in them such as
+/// `"oya_hora | reach_before(6)"`, or `NULL` for all.
+/// See `KyokuPredicate` for the syntax.
+/// @returns A named list of `game_info`, `round_info` and `paifu`,
+/// each of which is a named list of columns.
+/// `game_id` is the position of the log in `x`.
 /// @noRd
 #[savvy]
-fn parse_mjlog(x: StringSexp) -> savvy::Result<savvy::Sexp> {
+fn parse_mjlog_frames(
+    x: StringSexp,
+    lenient: bool,
+    kyoku: Option<&str>,
+    predicate: Option<&str>,
+) -> savvy::Result<savvy::Sexp> {
+    to_frames(&x, lenient, Selection::new(kyoku, predicate)?, mjlog_events)
+}
+
+/// Signature of the functions converting one log.
+type ParseFn = fn(&str, &Selection) -> Result<Vec<Event>, String>;
+
+/// Kyokus to keep, passing both of the filters given.
+struct Selection {
+    filter: Option<KyokuFilter>,
+    predicate: Option<KyokuPredicate>,
+}
+
+impl Selection {
+    fn new(kyoku: Option<&str>, predicate: Option<&str>) -> savvy::Result<Self> {
+        let filter = kyoku
+            .map(str::parse)
+            .transpose()
+            .map_err(|e: kyoku_filter::ParseError| savvy::Error::new(e.to_string()))?;
+        let predicate = predicate
+            .map(str::parse)
+            .transpose()
+            .map_err(|e: kyoku_predicate::ParseError| savvy::Error::new(e.to_string()))?;
+        Ok(Self { filter, predicate })
+    }
+}
+
+// Both sources are read with the normalized options, so that the R functions
+// give the same result for a game whichever source it comes from.
+fn tenhou6_events(s: &str, selection: &Selection) -> Result<Vec<Event>, String> {
+    let mut tenhou_log = tenhou::Log::from_json_str(s).map_err(|e| e.to_string())?;
+    if let Some(filter) = &selection.filter {
+        tenhou_log.filter_kyokus(filter);
+    }
+    if let Some(predicate) = &selection.predicate {
+        tenhou_log.filter_kyokus(predicate);
+    }
+    tenhou_to_mjai_with(&tenhou_log, &ConvertOptions::normalized()).map_err(|e| e.to_string())
+}
+
+fn mjlog_events(s: &str, selection: &Selection) -> Result<Vec<Event>, String> {
+    let mut events =
+        mjlog::parse_str_with(s, &ConvertOptions::normalized()).map_err(|e| e.to_string())?;
+    if let Some(filter) = &selection.filter {
+        filter.filter_events(&mut events);
+    }
+    if let Some(predicate) = &selection.predicate {
+        predicate.filter_events(&mut events);
+    }
+    Ok(events)
+}
+
+/// Elements of `x`, `None` for NA.
+fn non_na(x: &StringSexp) -> Vec<Option<&str>> {
+    x.iter().map(|e| (!e.is_na()).then_some(e)).collect()
+}
+
+/// Sets the error messages of failed logs as the "errors" attribute.
+fn set_errors(out: &mut OwnedListSexp, errors: &[Option<String>]) -> savvy::Result<()> {
+    let errors = OwnedStringSexp::try_from_iter(
+        errors
+            .iter()
+            .map(|e| e.as_deref().unwrap_or_else(<&str>::na)),
+    )?;
+    // `errors` stays protected until it is set.
+    out.set_attrib("errors", savvy::Sexp(errors.inner()))
+}
+
+fn to_json_list(
+    x: &StringSexp,
+    lenient: bool,
+    selection: Selection,
+    parse: ParseFn,
+) -> savvy::Result<savvy::Sexp> {
     let mut out = OwnedListSexp::new(x.len(), false)?;
-    let mut buf = Vec::new();
 
-    for (i, elem) in x.iter().enumerate() {
-        if elem.is_na() {
-            let mut dummy = OwnedStringSexp::new(1)?;
-            dummy.set_na(0)?;
-            out.set_value(i, dummy)?;
-            continue;
-        }
-        let mut reader = XmlReader::from_str(elem);
+    let errors = batch::convert_each(
+        &non_na(x),
+        lenient,
+        |_, s| {
+            parse(s, &selection)?
+                .iter()
+                .map(|ev| serde_json::to_string(ev).map_err(|e| e.to_string()))
+                .collect::<Result<Vec<_>, _>>()
+        },
+        |i, events| set_events(&mut out, i, events),
+    )?;
+    if lenient {
+        set_errors(&mut out, &errors)?;
+    }
 
-        let mut aka_flag: bool = false;
-        let mut is_initialized: bool = false;
-        let mut player_names: (String, String, String, String) = (
-            "player1".to_string(),
-            "player2".to_string(),
-            "player3".to_string(),
-            "player4".to_string(),
-        );
-        let mut last_draw: [u8; 4] = vec![Tile::from_str("?").unwrap().as_u8(); 4].try_into().unwrap();
-        let mut reach_count: u8 = 0;
+    Ok(out.into())
+}
 
-        let mut events: Vec<Event> = Vec::new();
-        'read_event: loop {
-            match reader.read_event_into(&mut buf) {
-                Ok(XmlEvent::Eof) => break 'read_event,
-                Ok(XmlEvent::Start(e)) => match e.name().as_ref() {
-                    b"mjloggm" => {
-                        let ver = mjlog::parse_mjloggm_version(&e)?;
-                        if ver != "2.3" {
-                            return Err(savvy_err!("mjloggm ver {} is not supported.", ver));
-                        }
-                    }
-                    _ => (),
-                },
-                Ok(XmlEvent::Empty(e)) => {
-                    match e.name().as_ref() {
-                        b"GO" => {
-                            let (flag, is_sanma) = mjlog::parse_game_type(&e)?;
-                            if is_sanma {
-                                return Err(savvy_err!("sanma is not supported."));
-                            }
-                            aka_flag = flag;
-                        }
-                        b"UN" => {
-                            if !is_initialized {
-                                player_names = mjlog::parse_names(&e)?;
-                                is_initialized = true;
-                            }
-                        }
-                        b"TAIKYOKU" => {
-                            let names: [String; 4] = [
-                                player_names.0.clone(),
-                                player_names.1.clone(),
-                                player_names.2.clone(),
-                                player_names.3.clone(),
-                            ];
-                            events.push(Event::StartGame {
-                                names,
-                                kyoku_first: 0,
-                                aka_flag,
-                            });
-                        }
-                        b"SHUFFLE" => {}
-                        b"INIT" => {
-                            // NOTE: 手牌は並び替えされていない
-                            let (bakaze, dora_marker, kyoku, honba, kyotaku, oya) =
-                                mjlog::parse_init_others(&e, aka_flag)?;
-                            let scores = mjlog::parse_init_scores(&e)?;
-                            let tehais = mjlog::parse_init_tehais(&e, aka_flag)?;
-                            events.push(Event::StartKyoku {
-                                bakaze,
-                                dora_marker,
-                                kyoku,
-                                honba,
-                                kyotaku,
-                                oya,
-                                scores,
-                                tehais
-                            });
-                        }
-                        b"DORA" => {
-                            let dora_marker = mjlog::parse_dora(&e, aka_flag)?;
-                            events.push(Event::Dora { dora_marker });
-                        }
-                        b"N" => {
-                           let (call_type, caller, callee, tiles) = mjlog::parse_n(&e, aka_flag)?;
-                           match call_type.as_str() {
-                               "Chi" => {
-                                    events.push(Event::Chi {
-                                        actor: caller,
-                                        target: callee,
-                                        pai: tiles[0],
-                                        consumed: tiles[1..].try_into().unwrap(),
-                                    });
-                               }
-                               "Pon" => {
-                                    events.push(Event::Pon {
-                                        actor: caller,
-                                        target: callee,
-                                        pai: tiles[0],
-                                        consumed: tiles[1..].try_into().unwrap(),
-                                    });
-                               }
-                               "Kakan" => {
-                                    events.push(Event::Kakan {
-                                        actor: caller,
-                                        pai: tiles[0],
-                                        consumed: vec![tiles[1], tiles[1], tiles[1]].try_into().unwrap(),
-                                    });
-                               }
-                               "Ankan" => {
-                                    // 5m,5p,5sは、赤ありのとき1枚赤くする
-                                    let tile_0: Tile = if aka_flag & matches_tu8!(tiles[0].as_u8(), 5m | 5p | 5s) {
-                                        tiles[0].akaize()
-                                    } else {
-                                        tiles[0]
-                                    };
-                                    events.push(Event::Ankan {
-                                        actor: caller,
-                                        consumed: vec![tiles[1], tiles[1], tiles[1], tile_0].try_into().unwrap(),
-                                    });
-                                }
-                                "Minkan" => {
-                                    events.push(Event::Daiminkan {
-                                        actor: caller,
-                                        target: callee,
-                                        pai: tiles[0],
-                                        consumed: tiles[1..].try_into().unwrap(),
-                                    });
-                               }
-                               _ => {}
-                           }
-                        }
-                        b"REACH" => {
-                            // NOTE: 本来の`reach_accepted`は宣言牌が鳴かれた場合は次以降の巡目になるが、ここでは考慮しない
-                            let (actor, step) = mjlog::parse_reach(&e)?;
-                            match step {
-                                1 => events.push(Event::Reach { actor }),
-                                2 if reach_count < 4 => {
-                                    reach_count += 1;
-                                    events.push(Event::ReachAccepted { actor })
-                                }
-                                _ => (),
-                            }
-                        }
-                        b"AGARI" => {
-                            let (actor, target, ura_markers, deltas) =
-                                mjlog::parse_agari(&e, aka_flag)?;
-                            events.push(Event::Hora {
-                                actor,
-                                target,
-                                ura_markers,
-                                deltas,
-                            });
-                            events.push(Event::EndKyoku);
-                            reach_count = 0;
-                            if mjlog::check_if_owari(&e)? {
-                                events.push(Event::EndGame);
-                            }
-                        }
-                        b"RYUUKYOKU" => {
-                            let deltas = mjlog::parse_ryuukyoku(&e)?;
-                            events.push(Event::Ryukyoku { deltas });
-                            events.push(Event::EndKyoku);
-                            reach_count = 0;
-                            if mjlog::check_if_owari(&e)? {
-                                events.push(Event::EndGame);
-                            }
-                        }
-                        b"BYE" => {}
-                        _ => {
-                            let name = e.name().into_inner();
-                            let name = String::from_utf8_lossy(name).into_owned();
-                            let tile_int = name[1..].parse::<u8>().unwrap();
-                            let pai = mjlog::translate_mjlog_tile(tile_int, aka_flag).unwrap();
-                            match name.chars().next() {
-                                // [T-W]はTsumo
-                                Some('T') => {
-                                    last_draw[0] = pai.as_u8();
-                                    events.push(Event::Tsumo { actor: 0, pai })
-                                }
-                                Some('U') => {
-                                    last_draw[1] = pai.as_u8();
-                                    events.push(Event::Tsumo { actor: 1, pai })
-                                }
-                                Some('V') => {
-                                    last_draw[2] = pai.as_u8();
-                                    events.push(Event::Tsumo { actor: 2, pai })
-                                }
-                                Some('W') => {
-                                    last_draw[3] = pai.as_u8();
-                                    events.push(Event::Tsumo { actor: 3, pai })
-                                }
-                                // [D-G]はDahai
-                                Some('D') => events.push(Event::Dahai { actor: 0, pai, tsumogiri: last_draw[0] == pai.as_u8() }),
-                                Some('E') => events.push(Event::Dahai { actor: 1, pai, tsumogiri: last_draw[1] == pai.as_u8() }),
-                                Some('F') => events.push(Event::Dahai { actor: 2, pai, tsumogiri: last_draw[2] == pai.as_u8() }),
-                                Some('G') => events.push(Event::Dahai { actor: 3, pai, tsumogiri: last_draw[3] == pai.as_u8() }),
-                                _ => (),
-                            }
-                        }
-                    }
-                }
-                _ => (),
-            }
-            buf.clear();
-        }
+/// Sets the events of the `i`-th log as a character vector, or NA.
+fn set_events(out: &mut OwnedListSexp, i: usize, events: Option<Vec<String>>) -> savvy::Result<()> {
+    let Some(events) = events else {
+        let mut dummy = OwnedStringSexp::new(1)?;
+        dummy.set_na(0)?;
+        return out.set_value(i, dummy);
+    };
 
-        let mut ret = OwnedStringSexp::new(events.len())?;
-        for (j, event) in events.iter().enumerate() {
-            let to_write = json::to_string(event)?;
-            ret.set_elt(j, &to_write)?;
-        }
-        out.set_value(i, ret)?;
+    let mut ret = OwnedStringSexp::new(events.len())?;
+    for (j, event) in events.iter().enumerate() {
+        ret.set_elt(j, event)?;
     }
+    out.set_value(i, ret)
+}
+
+fn to_frames(
+    x: &StringSexp,
+    lenient: bool,
+    selection: Selection,
+    parse: ParseFn,
+) -> savvy::Result<savvy::Sexp> {
+    // Games come back in any order, so keep them until all are done.
+    let mut games: Vec<Option<Frames>> = (0..x.len()).map(|_| None).collect();
 
+    let errors = batch::convert_each(
+        &non_na(x),
+        lenient,
+        |i, s| {
+            let mut game = Frames::default();
+            game.push_game(i as i32 + 1, &parse(s, &selection)?);
+            Ok(game)
+        },
+        |i, game| {
+            games[i] = game;
+            Ok(())
+        },
+    )?;
+
+    let mut frames = Frames::default();
+    for mut game in games.into_iter().flatten() {
+        frames.append(&mut game);
+    }
+    let mut out = frames.to_sexp()?;
+    if lenient {
+        set_errors(&mut out, &errors)?;
+    }
     Ok(out.into())
 }
@@ -0,0 +1,299 @@
+//! Waits (machi) and furiten of players along a game.
+
+use crate::mjai::Event;
+use crate::score::wait_shapes;
+use crate::shanten::{self, to_counts};
+use crate::state::{GameState, Result};
+use crate::Tile;
+
+/// How a wait is formed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitShape {
+    /// 両面, e.g. 23m waiting on 1m and 4m.
+    Ryanmen,
+    /// 嵌張, e.g. 13m waiting on 2m.
+    Kanchan,
+    /// 辺張, e.g. 12m waiting on 3m.
+    Penchan,
+    /// 双碰, two pairs waiting on either to become a koutsu.
+    Shanpon,
+    /// 単騎, one tile waiting on its pair. Also given for chiitoitsu and
+    /// kokushi.
+    Tanki,
+    /// 延べ単, e.g. 1234m waiting on 1m and 4m.
+    Nobetan,
+}
+
+/// A tile the hand waits on and the shapes it completes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Machi {
+    pub pai: Tile,
+    pub shapes: Vec<WaitShape>,
+}
+
+/// Why a tenpai player cannot win by ron.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Furiten {
+    /// A tile the player waits on is in their own river.
+    Permanent,
+    /// The player passed a winning tile since their last discard.
+    Temporary,
+    /// The player passed a winning tile after their reach.
+    Riichi,
+}
+
+/// The waits of the actor of a `dahai` right after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DahaiMachi {
+    /// Index of the `dahai` in the events.
+    pub index: usize,
+    pub actor: u8,
+    /// Empty when the actor is not tenpai.
+    pub waits: Vec<Machi>,
+    pub furiten: Option<Furiten>,
+}
+
+/// Waits of a closed hand of 13 tiles less three for each meld.
+///
+/// Tiles of which the hand holds all four are not waits.
+#[must_use]
+pub fn waits(tehai: &[Tile], num_melds: u8) -> Vec<Machi> {
+    let counts = to_counts(tehai);
+    let mut ret: Vec<Machi> = wait_tiles(&counts, num_melds)
+        .into_iter()
+        .map(|pai| {
+            let mut drawn = counts;
+            drawn[pai.as_usize()] += 1;
+            // More than four melds are counted as four, as in shanten.
+            let need = 4_usize.saturating_sub(num_melds as usize);
+            let mut shapes = wait_shapes(&drawn, need, pai.as_usize());
+            if shapes.is_empty() {
+                // Chiitoitsu or kokushi.
+                shapes.push(WaitShape::Tanki);
+            }
+            Machi { pai, shapes }
+        })
+        .collect();
+
+    // A tanki three tiles apart from another in the same suit is a nobetan.
+    let tanki: Vec<_> = ret
+        .iter()
+        .filter(|m| m.shapes.contains(&WaitShape::Tanki))
+        .map(|m| m.pai.as_usize())
+        .collect();
+    for m in &mut ret {
+        let i = m.pai.as_usize();
+        let nobetan = i < 27
+            && tanki.contains(&i)
+            && ((i % 9 >= 3 && tanki.contains(&(i - 3)))
+                || (i % 9 < 6 && tanki.contains(&(i + 3))));
+        if nobetan {
+            for shape in &mut m.shapes {
+                if *shape == WaitShape::Tanki {
+                    *shape = WaitShape::Nobetan;
+                }
+            }
+        }
+    }
+    ret
+}
+
+fn wait_tiles(counts: &[u8; 34], num_melds: u8) -> Vec<Tile> {
+    if shanten::shanten(counts, num_melds) != 0 {
+        return vec![];
+    }
+    (0..34)
+        .filter(|&i| {
+            let mut drawn = *counts;
+            drawn[i] += 1;
+            counts[i] < 4 && shanten::shanten(&drawn, num_melds) == -1
+        })
+        .filter_map(|i| Tile::try_from(i).ok())
+        .collect()
+}
+
+/// Follows the waits and furiten of each player.
+///
+/// Call [`MachiTracker::update`] with each event after applying it to the
+/// [`GameState`].
+#[derive(Debug, Clone, Default)]
+pub struct MachiTracker {
+    /// As of the last discard of each player.
+    waits: Vec<Vec<Tile>>,
+    temporary: Vec<bool>,
+    riichi: Vec<bool>,
+    /// A tile others could ron on and who gave it.
+    ronable: Option<(u8, Tile)>,
+}
+
+impl MachiTracker {
+    pub fn update(&mut self, state: &GameState, event: &Event) {
+        // Whoever waits on the last discard and does not ron passes it.
+        if !matches!(event, Event::Hora { .. }) {
+            if let Some((giver, pai)) = self.ronable.take() {
+                for (seat, waits) in self.waits.iter().enumerate() {
+                    if seat != giver as usize && waits.contains(&pai.deaka()) {
+                        self.temporary[seat] = true;
+                        if state.players[seat].reached {
+                            self.riichi[seat] = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        match *event {
+            Event::StartKyoku { .. } => {
+                let n = state.players.len();
+                self.waits = state
+                    .players
+                    .iter()
+                    .map(|p| wait_tiles(&to_counts(&p.tehai), 0))
+                    .collect();
+                self.temporary = vec![false; n];
+                self.riichi = vec![false; n];
+                self.ronable = None;
+            }
+            Event::Dahai { actor, pai, .. } => {
+                let a = actor as usize;
+                let player = &state.players[a];
+                self.waits[a] = wait_tiles(&to_counts(&player.tehai), player.melds.len() as u8);
+                self.temporary[a] = false;
+                self.ronable = Some((actor, pai));
+            }
+            // 槍槓, or a ron on kita.
+            Event::Kakan { actor, pai, .. } | Event::Nukidora { actor, pai } => {
+                self.ronable = Some((actor, pai));
+            }
+            _ => (),
+        }
+    }
+
+    /// The furiten of `seat`, `None` if the seat can win by ron or is not
+    /// tenpai. Permanent furiten is told before riichi and temporary ones.
+    #[must_use]
+    pub fn furiten(&self, state: &GameState, seat: u8) -> Option<Furiten> {
+        let s = seat as usize;
+        let waits = self.waits.get(s).filter(|w| !w.is_empty())?;
+        let river = &state.players.get(s)?.river;
+        if river.iter().any(|d| waits.contains(&d.pai.deaka())) {
+            Some(Furiten::Permanent)
+        } else if self.riichi[s] {
+            Some(Furiten::Riichi)
+        } else if self.temporary[s] {
+            Some(Furiten::Temporary)
+        } else {
+            None
+        }
+    }
+}
+
+/// Replays `events` and annotates each `dahai` with the waits and furiten of
+/// its actor.
+pub fn annotate(events: &[Event]) -> Result<Vec<DahaiMachi>> {
+    let mut state = GameState::default();
+    let mut tracker = MachiTracker::default();
+    let mut ret = vec![];
+    for (index, event) in events.iter().enumerate() {
+        state.update(event)?;
+        tracker.update(&state, event);
+        if let Event::Dahai { actor, .. } = *event {
+            let player = &state.players[actor as usize];
+            ret.push(DahaiMachi {
+                index,
+                actor,
+                waits: waits(&player.tehai, player.melds.len() as u8),
+                furiten: tracker.furiten(&state, actor),
+            });
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tenhou::Log;
+    use crate::testdata::{self, NOT_A_HAND};
+    use crate::{t, tenhou_to_mjai};
+
+    fn tiles(s: &str) -> Vec<Tile> {
+        s.split_whitespace().map(|t| t.parse().unwrap()).collect()
+    }
+
+    fn shapes(tehai: &str, num_melds: u8) -> Vec<(Tile, Vec<WaitShape>)> {
+        waits(&tiles(tehai), num_melds)
+            .into_iter()
+            .map(|m| (m.pai, m.shapes))
+            .collect()
+    }
+
+    #[test]
+    fn wait_shapes() {
+        use WaitShape::*;
+
+        assert_eq!(
+            shapes("2m 3m 5p 6p 7p 3s 4s 5s 7s 8s 9s E E", 0),
+            [(t!(1m), vec![Ryanmen]), (t!(4m), vec![Ryanmen])],
+        );
+        assert_eq!(
+            shapes("1m 2m 3m 4m 5p 6p 7p 7s 8s 9s E E E", 0),
+            [(t!(1m), vec![Nobetan]), (t!(4m), vec![Nobetan])],
+        );
+        assert_eq!(
+            shapes("1m 1m 9p 9p", 3),
+            [(t!(1m), vec![Shanpon]), (t!(9p), vec![Shanpon])],
+        );
+        assert_eq!(shapes("1m 2m P P", 3), [(t!(3m), vec![Penchan])]);
+        assert_eq!(shapes("4s 6s 8s 8s", 3), [(t!(5s), vec![Kanchan])]);
+        assert_eq!(shapes("E", 5), [(t!(E), vec![Tanki])]);
+        assert_eq!(
+            shapes("1m 1m 2p 2p 3s 3s 4s 4s E E S S P", 0),
+            [(t!(P), vec![Tanki])]
+        );
+        assert_eq!(shapes("1m 1m 2p 2p 3s 3s 4s 4s E E S S P P", 1), []);
+        // 3334 waits on 2, 4 and 5.
+        assert_eq!(
+            shapes("3p 3p 3p 4p", 3),
+            [
+                (t!(2p), vec![Ryanmen]),
+                (t!(4p), vec![Tanki]),
+                (t!(5p), vec![Ryanmen]),
+            ],
+        );
+    }
+
+    #[test]
+    fn winners_wait_on_the_winning_tile() {
+        for (name, json) in testdata::tenhou_logs() {
+            if name == NOT_A_HAND {
+                continue;
+            }
+            let log = Log::from_json_str(&json).unwrap();
+            let events = tenhou_to_mjai(&log).unwrap();
+            let annotations = annotate(&events).unwrap();
+            for (index, event) in events.iter().enumerate() {
+                let Event::Hora {
+                    actor,
+                    target,
+                    info: Some(info),
+                    ..
+                } = event
+                else {
+                    continue;
+                };
+                let last = annotations
+                    .iter()
+                    .rfind(|a| a.index < index && a.actor == *actor)
+                    .unwrap();
+                assert!(
+                    last.waits.iter().any(|m| m.pai == info.pai.deaka()),
+                    "{name}"
+                );
+                if actor != target {
+                    assert_eq!(last.furiten, None, "{name}");
+                }
+            }
+        }
+    }
+}
@@ -6,18 +6,23 @@ use serde_with::{serde_as, skip_serializing_none};
 /// Describes an event in mjai format.
 #[serde_as]
 #[skip_serializing_none]
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 pub enum Event {
     None,
 
     StartGame {
-        names: [String; 4],
+        /// Three names for sanma, four otherwise.
+        names: Vec<String>,
 
         // akochan specific
         kyoku_first: u8,
         aka_flag: bool,
+        lobby_tier: Option<LobbyTier>,
+        /// Not a part of mjai.
+        #[serde(flatten)]
+        meta: Box<GameMeta>,
     },
     StartKyoku {
         bakaze: Tile,
@@ -26,8 +31,8 @@ pub enum Event {
         honba: u8,
         kyotaku: u8,
         oya: u8,
-        scores: [i32; 4],
-        tehais: [[Tile; 13]; 4],
+        scores: Vec<i32>,
+        tehais: Vec<[Tile; 13]>,
     },
 
     Tsumo {
@@ -70,6 +75,11 @@ pub enum Event {
     Dora {
         dora_marker: Tile,
     },
+    /// Kita declaration (抜きドラ) in sanma.
+    Nukidora {
+        actor: u8,
+        pai: Tile,
+    },
 
     Reach {
         actor: u8,
@@ -82,18 +92,284 @@ pub enum Event {
         actor: u8,
         target: u8,
 
-        deltas: Option<[i32; 4]>,
+        /// One for each seat, three in sanma.
+        deltas: Option<Vec<i32>>,
         ura_markers: Option<Vec<Tile>>,
+        /// Not a part of mjai, filled when the source records it.
+        info: Option<Box<HoraInfo>>,
     },
     Ryukyoku {
-        deltas: Option<[i32; 4]>,
+        /// One for each seat, three in sanma.
+        deltas: Option<Vec<i32>>,
+        /// Not a part of mjai, filled when the source records it.
+        info: Option<Box<RyukyokuInfo>>,
     },
 
     EndKyoku,
-    EndGame,
+    EndGame {
+        /// Not a part of mjai, filled when the source records it.
+        info: Option<Box<GameResult>>,
+    },
+}
+
+/// Metadata of a game recorded by tenhou, one element for each seat in
+/// `dan`, `rate` and `sx`.
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GameMeta {
+    pub lobby: Option<i32>,
+    pub ratingc: Option<String>,
+    /// Names of ranks such as "七段".
+    pub dan: Option<Vec<String>>,
+    pub rate: Option<Vec<f64>>,
+    /// "M" or "F", or "C" for computers.
+    pub sx: Option<Vec<String>>,
+}
+
+/// Final standings of a game, one element for each seat.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GameResult {
+    /// Final scores, including kyotaku left on the table for the top.
+    pub scores: Vec<i32>,
+    /// Placements counting from 1, ties broken by seat order.
+    pub ranks: Vec<u8>,
+    /// Points in thousands with oka and uma applied.
+    pub points: Vec<f64>,
+}
+
+impl GameResult {
+    /// Ranks `scores` that sum up to `total` with kyotaku, which goes to the
+    /// top, and applies tenhou's oka and uma (+20/+10/-10/-20, or +15/0/-15
+    /// in sanma), rounded the way tenhou does.
+    pub(crate) fn from_scores(mut scores: Vec<i32>, total: i32) -> Self {
+        let (returned, uma): (i32, &[i32]) = if scores.len() == 3 {
+            (400, &[0, 0, -15])
+        } else {
+            (300, &[0, 10, -10, -20])
+        };
+        let order = rank_order(&scores);
+        scores[order[0]] += total - scores.iter().sum::<i32>();
+
+        let mut points = vec![0; scores.len()];
+        for (rank, &seat) in order.iter().enumerate().skip(1) {
+            let diff = scores[seat] / 100 - returned;
+            let rounded = diff.div_euclid(10) + i32::from(diff.rem_euclid(10) >= 6);
+            points[seat] = rounded + uma[rank];
+        }
+        points[order[0]] = -points.iter().sum::<i32>();
+        Self::with_points(scores, points.into_iter().map(f64::from).collect())
+    }
+
+    /// Ranks `scores` keeping `points` as recorded.
+    pub(crate) fn with_points(scores: Vec<i32>, points: Vec<f64>) -> Self {
+        let mut ranks = vec![0; scores.len()];
+        for (rank, seat) in rank_order(&scores).into_iter().enumerate() {
+            ranks[seat] = rank as u8 + 1;
+        }
+        Self {
+            scores,
+            ranks,
+            points,
+        }
+    }
+}
+
+/// Seats from the top to the last.
+fn rank_order(scores: &[i32]) -> Vec<usize> {
+    let mut order: Vec<_> = (0..scores.len()).collect();
+    order.sort_by_key(|&i| -scores[i]);
+    order
+}
+
+/// How a hora is made up and how much it is worth.
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HoraInfo {
+    #[serde(flatten)]
+    pub value: HandValue,
+    /// The winning tile.
+    pub pai: Tile,
+    /// The closed hand without the winning tile, sorted.
+    pub tehai: Vec<Tile>,
+    /// `chi`, `pon`, `daiminkan`, `kakan` and `ankan` events of the winner.
+    pub melds: Vec<Event>,
+}
+
+/// The value of a winning hand.
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HandValue {
+    /// 0 for limit hands from tenhou.net/6, which does not record it.
+    pub fu: u8,
+    /// Sum of `han` of `yaku`.
+    pub han: u8,
+    /// Points the winner earns, without honba and kyotaku.
+    pub points: i32,
+    pub limit: Option<Limit>,
+    pub yaku: Vec<Yaku>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Yaku {
+    /// The name tenhou uses, e.g. "立直" or "場風 東".
+    pub name: String,
+    /// 13 for each yakuman.
+    pub han: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Limit {
+    Mangan,
+    Haneman,
+    Baiman,
+    Sanbaiman,
+    Yakuman,
+}
+
+impl Limit {
+    /// Returns the name tenhou uses in point texts, e.g. "満貫".
+    #[inline]
+    #[must_use]
+    pub const fn to_str(self) -> &'static str {
+        match self {
+            Self::Mangan => "満貫",
+            Self::Haneman => "跳満",
+            Self::Baiman => "倍満",
+            Self::Sanbaiman => "三倍満",
+            Self::Yakuman => "役満",
+        }
+    }
+}
+
+/// Why a kyoku ended in a draw and who was tenpai.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RyukyokuInfo {
+    pub reason: RyukyokuReason,
+    /// One for each seat.
+    pub tenpai: Vec<bool>,
+    /// Closed hands shown at the end, one for each seat.
+    pub tehais: Vec<Option<Vec<Tile>>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RyukyokuReason {
+    /// 荒牌平局, the wall is exhausted.
+    Exhaustive,
+    /// 九種九牌
+    KyuushuKyuuhai,
+    /// 四家立直
+    SuuchaRiichi,
+    /// 三家和了
+    SanchaHou,
+    /// 四槓散了
+    SuukanSanra,
+    /// 四風連打
+    SuufonRenda,
+    /// 流し満貫
+    NagashiMangan,
+}
+
+impl RyukyokuReason {
+    /// Returns the status text tenhou.net/6 uses, "流局" for exhaustive draws.
+    #[inline]
+    #[must_use]
+    pub const fn to_str(self) -> &'static str {
+        match self {
+            Self::Exhaustive => "流局",
+            Self::KyuushuKyuuhai => "九種九牌",
+            Self::SuuchaRiichi => "四家立直",
+            Self::SanchaHou => "三家和了",
+            Self::SuukanSanra => "四槓散了",
+            Self::SuufonRenda => "四風連打",
+            Self::NagashiMangan => "流し満貫",
+        }
+    }
+
+    /// Returns the `type` of RYUUKYOKU in mjlog, `None` for exhaustive draws.
+    #[inline]
+    #[must_use]
+    pub const fn mjlog_type(self) -> Option<&'static str> {
+        match self {
+            Self::Exhaustive => None,
+            Self::KyuushuKyuuhai => Some("yao9"),
+            Self::SuuchaRiichi => Some("reach4"),
+            Self::SanchaHou => Some("ron3"),
+            Self::SuukanSanra => Some("kan4"),
+            Self::SuufonRenda => Some("kaze4"),
+            Self::NagashiMangan => Some("nm"),
+        }
+    }
+}
+
+/// The tier of the lobby where a game took place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LobbyTier {
+    /// 一般
+    Ippan,
+    /// 上級
+    Joukyuu,
+    /// 特上
+    Tokujou,
+    /// 鳳凰
+    Houou,
+}
+
+impl LobbyTier {
+    /// Returns the character tenhou uses for the tier in rule names.
+    #[inline]
+    #[must_use]
+    pub const fn to_char(self) -> char {
+        match self {
+            Self::Ippan => '般',
+            Self::Joukyuu => '上',
+            Self::Tokujou => '特',
+            Self::Houou => '鳳',
+        }
+    }
+
+    /// Returns the name used in mjai logs, e.g. "houou".
+    #[inline]
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Ippan => "ippan",
+            Self::Joukyuu => "joukyuu",
+            Self::Tokujou => "tokujou",
+            Self::Houou => "houou",
+        }
+    }
 }
 
 impl Event {
+    /// Returns the `type` of the event in mjai logs, e.g. "start_kyoku".
+    #[inline]
+    #[must_use]
+    pub const fn type_name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::StartGame { .. } => "start_game",
+            Self::StartKyoku { .. } => "start_kyoku",
+            Self::Tsumo { .. } => "tsumo",
+            Self::Dahai { .. } => "dahai",
+            Self::Chi { .. } => "chi",
+            Self::Pon { .. } => "pon",
+            Self::Daiminkan { .. } => "daiminkan",
+            Self::Kakan { .. } => "kakan",
+            Self::Ankan { .. } => "ankan",
+            Self::Dora { .. } => "dora",
+            Self::Nukidora { .. } => "nukidora",
+            Self::Reach { .. } => "reach",
+            Self::ReachAccepted { .. } => "reach_accepted",
+            Self::Hora { .. } => "hora",
+            Self::Ryukyoku { .. } => "ryukyoku",
+            Self::EndKyoku => "end_kyoku",
+            Self::EndGame { .. } => "end_game",
+        }
+    }
+
     #[inline]
     #[must_use]
     pub const fn actor(&self) -> Option<u8> {
@@ -105,6 +381,7 @@ impl Event {
             | Self::Daiminkan { actor, .. }
             | Self::Kakan { actor, .. }
             | Self::Ankan { actor, .. }
+            | Self::Nukidora { actor, .. }
             | Self::Reach { actor, .. }
             | Self::ReachAccepted { actor, .. }
             | Self::Hora { actor, .. } => Some(actor),
@@ -140,5 +417,39 @@ mod test {
     fn optional_field_deser() {
         let a = r#"{"type":"hora","actor":0,"target":0}"#;
         serde_json::from_str::<Event>(a).unwrap();
+        let a = r#"{"type":"end_game"}"#;
+        serde_json::from_str::<Event>(a).unwrap();
+    }
+
+    #[test]
+    fn type_name() {
+        let events = [
+            Event::EndKyoku,
+            Event::ReachAccepted { actor: 0 },
+            Event::Ryukyoku {
+                deltas: None,
+                info: None,
+            },
+        ];
+        for ev in events {
+            let json = serde_json::to_value(&ev).unwrap();
+            assert_eq!(json["type"], ev.type_name());
+        }
+    }
+
+    #[test]
+    fn game_result() {
+        let result = GameResult::from_scores(vec![34_100, 22_100, 21_800, 22_000], 100_000);
+        assert_eq!(result.ranks, [1, 2, 4, 3]);
+        assert_eq!(result.points, [44., 2., -28., -18.]);
+
+        let result = GameResult::from_scores(vec![25_000, 25_000, 25_000, 24_000], 100_000);
+        assert_eq!(result.scores, [26_000, 25_000, 25_000, 24_000]);
+        assert_eq!(result.ranks, [1, 2, 3, 4]);
+        assert_eq!(result.points, [36., 5., -15., -26.]);
+
+        let result = GameResult::from_scores(vec![35_000, 35_000, 35_000], 105_000);
+        assert_eq!(result.ranks, [1, 2, 3]);
+        assert_eq!(result.points, [25., -5., -20.]);
     }
 }
@@ -1,14 +1,345 @@
+//! Reads and writes mjlog (ver 2.3), the XML format tenhou uses to store
+//! game records.
+
 // these logics are taken from https://github.com/fstqwq/mjlog2mjai/blob/master/parse.py
-use crate::mjlog;
-use crate::Tile;
+use crate::kyoku_filter::kyoku_label;
+use crate::mjai::{
+    Event, GameMeta, GameResult, HandValue, HoraInfo, Limit, RyukyokuInfo, RyukyokuReason, Yaku,
+};
+use crate::options::ConvertOptions;
+use crate::reverse::ReverseError;
+use crate::{tu8, Tile};
 
 use quick_xml::encoding::Decoder;
-use quick_xml::events::attributes::Attribute;
 use quick_xml::events::BytesStart;
+use quick_xml::events::Event as XmlEvent;
+use quick_xml::reader::Reader as XmlReader;
+use std::mem;
 use std::str::FromStr;
 use std::vec;
+use thiserror::Error;
 use urlencoding::decode;
 
+mod game_type;
+mod to_tenhou;
+mod writer;
+
+pub use game_type::GameType;
+pub use to_tenhou::mjlog_to_tenhou;
+pub use writer::{mjai_to_mjlog, WriteError};
+
+#[derive(Debug, Error)]
+pub enum MjlogError {
+    #[error("invalid xml at byte {offset}: {source}")]
+    Xml {
+        source: quick_xml::Error,
+        offset: u64,
+    },
+
+    #[error("mjloggm ver {0} is not supported")]
+    UnsupportedVersion(String),
+
+    #[error("missing attribute {attr:?} in <{tag}> at byte {offset}")]
+    MissingAttribute {
+        tag: String,
+        attr: &'static str,
+        offset: u64,
+    },
+
+    #[error("invalid attribute {attr}={value:?} in <{tag}> at byte {offset}")]
+    InvalidAttribute {
+        tag: String,
+        attr: &'static str,
+        value: String,
+        offset: u64,
+    },
+
+    #[error("invalid tile tag <{tag}> at byte {offset}")]
+    InvalidTileTag { tag: String, offset: u64 },
+
+    #[error(transparent)]
+    Reverse(#[from] ReverseError),
+
+    #[error("at {}: {source}", kyoku_label(*.kyoku, *.honba))]
+    InKyoku {
+        kyoku: u8,
+        honba: u8,
+        source: Box<Self>,
+    },
+}
+
+impl MjlogError {
+    /// Tells the kyoku of the last INIT read, if any, where the error is from.
+    fn in_kyoku(self, init: Option<(u8, u8)>) -> Self {
+        match (self, init) {
+            (err @ Self::InKyoku { .. }, _) | (err, None) => err,
+            (err, Some((kyoku, honba))) => Self::InKyoku {
+                kyoku,
+                honba,
+                source: Box::new(err),
+            },
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, MjlogError>;
+
+/// Parse a mjlog XML into mjai format.
+///
+/// This is the mjlog counterpart of
+/// [`Log::from_json_str`](crate::tenhou::Log::from_json_str) followed by
+/// [`tenhou_to_mjai`](crate::tenhou_to_mjai).
+#[inline]
+pub fn parse_str(xml: &str) -> Result<Vec<Event>> {
+    parse_str_with(xml, &ConvertOptions::default())
+}
+
+/// Parse a mjlog XML into mjai format with `options`.
+pub fn parse_str_with(xml: &str, options: &ConvertOptions) -> Result<Vec<Event>> {
+    let mut init = None;
+    parse_events(xml, *options, &mut init).map_err(|e| e.in_kyoku(init))
+}
+
+/// Does [`parse_str_with`], keeping the kyoku number and honba of the last
+/// INIT in `init`.
+fn parse_events(
+    xml: &str,
+    options: ConvertOptions,
+    init: &mut Option<(u8, u8)>,
+) -> Result<Vec<Event>> {
+    let mut reader = XmlReader::from_str(xml);
+    let mut buf = Vec::new();
+
+    let game_type = GameType::default();
+    let mut aka_flag = game_type.aka;
+    let mut num_players = game_type.num_players();
+    let mut kyoku_first = game_type.kyoku_first();
+    let mut lobby_tier = None;
+    let mut meta = GameMeta::default();
+    let mut is_initialized: bool = false;
+    let mut player_names: Vec<String> = vec![];
+    let mut last_draw: [Option<u8>; 4] = [None; 4];
+    // Like `tenhou_to_mjai`, a reach is accepted only on the next take.
+    let mut reach_flag: Option<u8> = None;
+    // Double ron has one AGARI tag per winner but only one `end_kyoku`.
+    let mut is_kyoku_ended = false;
+
+    let mut events: Vec<Event> = Vec::new();
+    loop {
+        let tag = match read_event(&mut reader, &mut buf)? {
+            (XmlEvent::Eof, _) => break,
+            (XmlEvent::Start(e), offset) if e.name().as_ref() == b"mjloggm" => {
+                let ver = parse_mjloggm_version(&Tag { e, offset })?;
+                if ver != "2.3" {
+                    return Err(MjlogError::UnsupportedVersion(ver));
+                }
+                buf.clear();
+                continue;
+            }
+            (XmlEvent::Empty(e), offset) => Tag { e, offset },
+            _ => {
+                buf.clear();
+                continue;
+            }
+        };
+
+        match tag.e.name().as_ref() {
+            b"GO" => {
+                let (game_type, lobby) = parse_go(&tag)?;
+                aka_flag = game_type.aka;
+                num_players = game_type.num_players();
+                kyoku_first = game_type.kyoku_first();
+                lobby_tier = Some(game_type.lobby_tier);
+                meta.lobby = lobby;
+                meta.ratingc = Some(game_type.ratingc().to_owned());
+            }
+            b"UN" => {
+                if !is_initialized {
+                    player_names = parse_names(&tag, num_players)?;
+                    let (dan, rate, sx) = parse_player_meta(&tag)?;
+                    let n = num_players as usize;
+                    meta.dan = dan.map(|v| v.into_iter().take(n).collect());
+                    meta.rate = rate.map(|v| v.into_iter().take(n).collect());
+                    meta.sx = sx.map(|v| v.into_iter().take(n).collect());
+                    is_initialized = true;
+                }
+            }
+            b"TAIKYOKU" => {
+                let names = if player_names.is_empty() {
+                    (1..=num_players).map(|i| format!("player{i}")).collect()
+                } else {
+                    player_names.clone()
+                };
+                events.push(Event::StartGame {
+                    names,
+                    kyoku_first,
+                    aka_flag,
+                    lobby_tier,
+                    meta: Box::new(meta.clone()),
+                });
+            }
+            b"SHUFFLE" | b"BYE" => {}
+            b"INIT" => {
+                if mem::take(&mut is_kyoku_ended) {
+                    events.push(Event::EndKyoku);
+                }
+                // NOTE: 手牌は並び替えされていない
+                *init = None;
+                let (bakaze, dora_marker, kyoku, honba, kyotaku, oya) =
+                    parse_init_others(&tag, aka_flag)?;
+                *init = Some((kyoku_num(bakaze, kyoku), honba));
+                let scores = parse_init_scores(&tag, num_players)?;
+                let tehais = parse_init_tehais(&tag, aka_flag, num_players)?;
+                events.push(Event::StartKyoku {
+                    bakaze,
+                    dora_marker,
+                    kyoku,
+                    honba,
+                    kyotaku,
+                    oya,
+                    scores,
+                    tehais,
+                });
+            }
+            b"DORA" => {
+                let dora_marker = parse_dora(&tag, aka_flag)?;
+                events.push(Event::Dora { dora_marker });
+            }
+            b"N" => {
+                let (call_type, caller, callee, tiles) = parse_n(&tag, aka_flag, num_players)?;
+                if let Some(event) = meld_to_event(&call_type, caller, callee, &tiles) {
+                    if matches!(
+                        event,
+                        Event::Chi { .. } | Event::Pon { .. } | Event::Daiminkan { .. }
+                    ) {
+                        if let Some(actor) = reach_flag.take() {
+                            events.push(Event::ReachAccepted { actor });
+                        }
+                    }
+                    events.push(event);
+                }
+            }
+            b"REACH" => {
+                let (actor, step) = parse_reach(&tag, num_players)?;
+                match step {
+                    1 => events.push(Event::Reach { actor }),
+                    2 => reach_flag = Some(actor),
+                    _ => (),
+                }
+            }
+            b"AGARI" => {
+                let (actor, target, ura_markers, deltas) =
+                    parse_agari(&tag, aka_flag, num_players)?;
+                let info = parse_hora_info(&tag, aka_flag, num_players)?;
+                events.push(Event::Hora {
+                    actor,
+                    target,
+                    ura_markers,
+                    deltas,
+                    info: info.map(Box::new),
+                });
+                reach_flag = None;
+                is_kyoku_ended = true;
+                if let Some(result) = parse_owari(&tag, num_players)? {
+                    events.extend([
+                        Event::EndKyoku,
+                        Event::EndGame {
+                            info: Some(Box::new(result)),
+                        },
+                    ]);
+                    is_kyoku_ended = false;
+                }
+            }
+            b"RYUUKYOKU" => {
+                let (deltas, info) = parse_ryuukyoku(&tag, aka_flag, num_players)?;
+                events.push(Event::Ryukyoku {
+                    deltas,
+                    info: Some(Box::new(info)),
+                });
+                reach_flag = None;
+                is_kyoku_ended = true;
+                if let Some(result) = parse_owari(&tag, num_players)? {
+                    events.extend([
+                        Event::EndKyoku,
+                        Event::EndGame {
+                            info: Some(Box::new(result)),
+                        },
+                    ]);
+                    is_kyoku_ended = false;
+                }
+            }
+            _ => match parse_tile_tag(&tag, aka_flag)? {
+                // [T-W]はTsumo
+                Some((head @ b'T'..=b'W', id, pai)) => {
+                    let actor = head - b'T';
+                    last_draw[actor as usize] = Some(id);
+                    if let Some(actor) = reach_flag.take() {
+                        events.push(Event::ReachAccepted { actor });
+                    }
+                    events.push(Event::Tsumo { actor, pai });
+                }
+                // [D-G]はDahai
+                Some((head, id, pai)) => {
+                    let actor = head - b'D';
+                    events.push(Event::Dahai {
+                        actor,
+                        pai,
+                        tsumogiri: last_draw[actor as usize].take() == Some(id),
+                    });
+                }
+                None => (),
+            },
+        }
+        buf.clear();
+    }
+    if is_kyoku_ended {
+        events.push(Event::EndKyoku);
+    }
+
+    options.apply(&mut events);
+    Ok(events)
+}
+
+/// Reads the next XML event along with the byte offset where it starts.
+fn read_event<'b>(
+    reader: &mut XmlReader<&[u8]>,
+    buf: &'b mut Vec<u8>,
+) -> Result<(XmlEvent<'b>, u64)> {
+    let offset = reader.buffer_position();
+    let event = reader
+        .read_event_into(buf)
+        .map_err(|source| MjlogError::Xml {
+            source,
+            offset: reader.error_position(),
+        })?;
+    Ok((event, offset))
+}
+
+/// Splits a tile tag such as `T52` or `D52` into its head, the 136-tile ID
+/// and the tile. Returns `None` for tags of other kinds.
+fn parse_tile_tag(tag: &Tag<'_>, aka_flag: bool) -> Result<Option<(u8, u8, Tile)>> {
+    let name = tag.e.name();
+    let (head, id) = match name.as_ref().split_first() {
+        Some((&head, id)) if b"TUVWDEFG".contains(&head) && !id.is_empty() => (head, id),
+        _ => return Ok(None),
+    };
+    if !id.iter().all(u8::is_ascii_digit) {
+        // e.g. `DORA`
+        return Ok(None);
+    }
+
+    std::str::from_utf8(id)
+        .ok()
+        .and_then(|id| id.parse::<u8>().ok())
+        .and_then(|id| Some((head, id, translate_mjlog_tile(id, aka_flag)?)))
+        .map(Some)
+        .ok_or_else(|| MjlogError::InvalidTileTag {
+            tag: tag.name(),
+            offset: tag.offset,
+        })
+}
+
+#[rustfmt::skip]
 const TRANSLATION: [&str; 35] = [
     "1m", "2m", "3m", "4m", "5m", "6m", "7m", "8m", "9m",
     "1p", "2p", "3p", "4p", "5p", "6p", "7p", "8p", "9p",
@@ -17,15 +348,26 @@ const TRANSLATION: [&str; 35] = [
     "?", // 稀にID=136のタイルがある
 ];
 
-pub fn translate_mjlog_tile(tile: u8, red: bool) -> Option<Tile> {
-    let ret = String::from(TRANSLATION[(tile >> 2) as usize]);
+fn translate_mjlog_tile(tile: u8, red: bool) -> Option<Tile> {
+    let ret = String::from(*TRANSLATION.get((tile >> 2) as usize)?);
     if red && ret.starts_with('5') && (tile & 3) == 0 {
-        Tile::from_str(&format!("{}r", ret)).ok()
+        Tile::from_str(&format!("{ret}r")).ok()
     } else {
         Tile::from_str(&ret).ok()
     }
 }
 
+/// Names of `dan` in UN tags as written in tenhou.net/6 logs.
+#[rustfmt::skip]
+const DAN_NAMES: [&str; 21] = [
+    "新人", "９級", "８級", "７級", "６級", "５級", "４級", "３級", "２級", "１級",
+    "初段", "二段", "三段", "四段", "五段", "六段", "七段", "八段", "九段", "十段",
+    "天鳳位",
+];
+
+/// `dan`, `rate` and `sx` of the players.
+type PlayerMeta = (Option<Vec<String>>, Option<Vec<f64>>, Option<Vec<String>>);
+
 fn parse_chi(meld: u16) -> Vec<u8> {
     let mut base_tile = (meld & 0xfc00) >> 10;
     let rotation = base_tile % 3;
@@ -33,10 +375,10 @@ fn parse_chi(meld: u16) -> Vec<u8> {
     base_tile = 9 * (base_tile / 7) + (base_tile % 7);
     base_tile *= 4;
 
-    let tiles: Vec<u8> = vec![
-        base_tile + 4 * 0 + ((meld & 0x0018) >> 3),
-        base_tile + 4 * 1 + ((meld & 0x0060) >> 5),
-        base_tile + 4 * 2 + ((meld & 0x0180) >> 7),
+    let tiles: Vec<u8> = [
+        base_tile + ((meld & 0x0018) >> 3),
+        base_tile + 4 + ((meld & 0x0060) >> 5),
+        base_tile + 8 + ((meld & 0x0180) >> 7),
     ]
     .iter()
     .map(|&x| x as u8)
@@ -56,7 +398,7 @@ fn parse_pon(meld: u16) -> Vec<u8> {
     base_tile /= 3;
     base_tile *= 4;
 
-    let mut tiles: Vec<u8> = vec![base_tile, base_tile, base_tile]
+    let mut tiles: Vec<u8> = [base_tile, base_tile, base_tile]
         .iter()
         .map(|&x| x as u8)
         .collect();
@@ -91,252 +433,258 @@ fn parse_pon(meld: u16) -> Vec<u8> {
 }
 
 fn parse_kakan(meld: u16) -> Vec<u8> {
-    let added_tile = (meld & 0x0060) >> 5;
-    let mut base_tile = (meld & 0xfe00) >> 9;
-    let rotation = base_tile % 3;
-    base_tile /= 3;
-    base_tile *= 4;
+    let added = ((meld & 0x0060) >> 5) as u8;
+    let base = ((meld & 0xfe00) >> 9) as u8 / 3 * 4;
 
-    let mut tiles: Vec<u8> = vec![base_tile, base_tile, base_tile]
-        .iter()
-        .map(|&x| x as u8)
-        .collect();
+    // The added tile first, then the three of the pon.
+    std::iter::once(base + added)
+        .chain((base..=base + 3).filter(|&id| id != base + added))
+        .collect()
+}
 
-    match added_tile {
-        0 => {
-            tiles[0] += 1;
-            tiles[1] += 2;
-            tiles[2] += 3;
-        }
-        1 => {
-            tiles[0] += 0;
-            tiles[1] += 2;
-            tiles[2] += 3;
-        }
-        2 => {
-            tiles[0] += 0;
-            tiles[1] += 1;
-            tiles[2] += 3;
-        }
-        3 => {
-            tiles[0] += 0;
-            tiles[1] += 1;
-            tiles[2] += 2;
-        }
-        _ => {}
-    }
+fn parse_kan(meld: u16) -> Vec<u8> {
+    let tile = ((meld & 0xff00) >> 8) as u8;
+    let kui = (meld & 0x3) as u8;
 
-    if rotation == 1 {
-        vec![tiles[1], tiles[0], tiles[2]]
-    } else if rotation == 2 {
-        vec![tiles[2], tiles[0], tiles[1]]
+    // All four copies of the kind, the called one first for daiminkan.
+    let base = tile & !3;
+    if kui == 0 {
+        (base..=base + 3).collect()
     } else {
-        let insert: u8 = (base_tile + added_tile).try_into().unwrap();
-        tiles.insert(0, insert);
-        tiles
+        std::iter::once(tile)
+            .chain((base..=base + 3).filter(|&id| id != tile))
+            .collect()
     }
 }
 
-fn parse_kan(meld: u16) -> Vec<u8> {
-    let tile = ((meld & 0xff00) >> 8) as u8;
-    let kui = (meld & 0x3) as u8;
+fn parse_nukidora(meld: u16) -> Vec<u8> {
+    vec![((meld & 0xff00) >> 8) as u8]
+}
 
-    let mut tiles = vec![tile, tile, tile];
-    let remainder = tile % 4;
+/// An element of mjlog along with the byte offset where it starts, so that
+/// errors can point at it.
+struct Tag<'a> {
+    e: BytesStart<'a>,
+    offset: u64,
+}
 
-    match remainder {
-        0 => {
-            tiles[0] += 1;
-            tiles[1] += 2;
-            tiles[2] += 3;
-        }
-        1 => {
-            tiles[0] += 0;
-            tiles[1] += 2;
-            tiles[2] += 3;
-        }
-        2 => {
-            tiles[0] += 0;
-            tiles[1] += 1;
-            tiles[2] += 3;
-        }
-        3 => {
-            tiles[0] += 0;
-            tiles[1] += 1;
-            tiles[2] += 2;
+impl Tag<'_> {
+    fn name(&self) -> String {
+        String::from_utf8_lossy(self.e.name().as_ref()).into_owned()
+    }
+
+    /// Returns the unescaped value of the attribute, if any.
+    fn attr(&self, attr: &'static str) -> Result<Option<String>> {
+        self.e
+            .try_get_attribute(attr)
+            .map_err(quick_xml::Error::from)
+            .and_then(|a| {
+                a.map(|a| {
+                    a.decode_and_unescape_value(Decoder {})
+                        .map(|v| v.into_owned())
+                })
+                .transpose()
+            })
+            .map_err(|source| MjlogError::Xml {
+                source,
+                offset: self.offset,
+            })
+    }
+
+    fn required(&self, attr: &'static str) -> Result<String> {
+        self.attr(attr)?
+            .ok_or_else(|| MjlogError::MissingAttribute {
+                tag: self.name(),
+                attr,
+                offset: self.offset,
+            })
+    }
+
+    fn invalid(&self, attr: &'static str, value: &str) -> MjlogError {
+        MjlogError::InvalidAttribute {
+            tag: self.name(),
+            attr,
+            value: value.to_owned(),
+            offset: self.offset,
         }
-        _ => {}
     }
 
-    if kui == 0 {
-        tiles[0..2].to_vec()
-    } else {
-        let mut ret = vec![tile];
-        ret.extend(tiles);
-        ret
+    fn parse<T: FromStr>(&self, attr: &'static str) -> Result<T> {
+        let value = self.required(attr)?;
+        value.parse().map_err(|_| self.invalid(attr, &value))
     }
-}
 
-fn parse_deltas<'a>(sc: Option<Attribute<'a>>) -> Result<Option<[i32; 4]>, quick_xml::Error> {
-    let deltas = sc
-        .map(|a| a.decode_and_unescape_value(Decoder {}))
-        .transpose()?;
+    /// Parses a seat, which must be one of the `num_players`.
+    fn seat(&self, attr: &'static str, num_players: u8) -> Result<u8> {
+        let value = self.required(attr)?;
+        value
+            .parse()
+            .ok()
+            .filter(|&seat| seat < num_players)
+            .ok_or_else(|| self.invalid(attr, &value))
+    }
 
-    let deltas: Vec<i32> = deltas
-        .iter()
-        .flat_map(|s| s.split(",").enumerate())
-        .filter(|&(i, _)| i % 2 != 0)
-        .map(|(_, s)| s.parse::<i32>().unwrap() * 100)
-        .collect();
+    /// Parses a comma-separated attribute, if any.
+    fn parse_list<T: FromStr>(&self, attr: &'static str) -> Result<Option<Vec<T>>> {
+        self.attr(attr)?
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|s| s.parse().map_err(|_| self.invalid(attr, &value)))
+                    .collect()
+            })
+            .transpose()
+    }
 
-    let deltas: Option<[i32; 4]> = match deltas.len() {
-        4 => Some(deltas.try_into().unwrap()),
-        _ => None,
+    fn tile(&self, attr: &'static str, id: u8, aka_flag: bool) -> Result<Tile> {
+        translate_mjlog_tile(id, aka_flag).ok_or_else(|| self.invalid(attr, &id.to_string()))
+    }
+}
+
+fn parse_deltas(tag: &Tag<'_>, num_players: u8) -> Result<Option<Vec<i32>>> {
+    let Some(sc) = tag.parse_list::<i32>("sc")? else {
+        return Ok(None);
     };
+    let mut deltas: Vec<i32> = sc.into_iter().skip(1).step_by(2).map(|d| d * 100).collect();
 
-    Ok(deltas)
-}
-
-pub fn parse_mjloggm_version<'a>(e: &BytesStart<'a>) -> Result<String, quick_xml::Error> {
-    let version = e
-        .try_get_attribute("ver")?
-        .expect("Failed to parse 'ver' attribute.")
-        .decode_and_unescape_value(Decoder {})?
-        .into_owned();
-    Ok(version)
-}
-
-pub fn parse_game_type<'a>(e: &BytesStart<'a>) -> Result<(bool, bool), quick_xml::Error> {
-    let game_type = e
-        .try_get_attribute("type")?
-        .expect("Failed to parse 'type' attribute.")
-        .decode_and_unescape_value(Decoder {})?
-        .parse::<u16>()
-        .unwrap();
-    let aka_flag: bool = (game_type & 0x02) >> 2 != 0;
-    let is_sanma: bool = (game_type & 0x10) >> 4 != 0;
-    Ok((!aka_flag, is_sanma))
-}
-
-pub fn parse_names<'a>(
-    e: &BytesStart<'a>,
-) -> Result<(String, String, String, String), quick_xml::Error> {
-    let mut names = vec![String::new(); 4];
-    for i in 0..4 {
-        let name = e
-            .try_get_attribute(&format!("n{}", i))?
-            .expect("Failed to parse player names.")
-            .decode_and_unescape_value(Decoder {})?
-            .into_owned();
-        let name = decode(&name).unwrap();
-        names[i] = name.into_owned();
+    // The fourth seat, always 0, may still be written in sanma.
+    if deltas.len() < num_players as usize {
+        return Ok(None);
     }
-    Ok((
-        names[0].clone(),
-        names[1].clone(),
-        names[2].clone(),
-        names[3].clone(),
-    ))
+    deltas.truncate(num_players as usize);
+
+    Ok(Some(deltas))
 }
 
-pub fn parse_init_others<'a>(
-    e: &BytesStart<'a>,
-    aka_flag: bool,
-) -> Result<(Tile, Tile, u8, u8, u8, u8), quick_xml::Error> {
-    let seed_values = e
-        .try_get_attribute("seed")?
-        .expect("Failed to parse 'seed' attribute.")
-        .decode_and_unescape_value(Decoder {})?
-        .split(',')
-        .map(|s| s.parse::<u8>().unwrap())
-        .collect::<Vec<u8>>();
+fn parse_mjloggm_version(tag: &Tag<'_>) -> Result<String> {
+    tag.required("ver")
+}
 
-    let bakaze_index = (seed_values[0] / 4) % 4;
-    let bakaze_tile = [109, 113, 117, 121][bakaze_index as usize];
-    let bakaze = translate_mjlog_tile(bakaze_tile, aka_flag).unwrap();
+/// Returns the `type` flags and the `lobby` of the GO tag.
+fn parse_go(tag: &Tag<'_>) -> Result<(GameType, Option<i32>)> {
+    let game_type = GameType::from_bits(tag.parse::<u16>("type")?);
+    let lobby = tag.attr("lobby")?.and_then(|s| s.parse::<i32>().ok());
+    Ok((game_type, lobby))
+}
+
+fn parse_names(tag: &Tag<'_>, num_players: u8) -> Result<Vec<String>> {
+    const ATTRS: [&str; 4] = ["n0", "n1", "n2", "n3"];
+
+    ATTRS
+        .into_iter()
+        .take(num_players as usize)
+        .map(|attr| {
+            let name = tag.required(attr)?;
+            decode(&name)
+                .map(|name| name.into_owned())
+                .map_err(|_| tag.invalid(attr, &name))
+        })
+        .collect()
+}
 
-    let dora_tile = seed_values[5];
-    let dora = translate_mjlog_tile(dora_tile, aka_flag).unwrap();
+/// Returns `dan`, `rate` and `sx` of the UN tag, if any.
+fn parse_player_meta(tag: &Tag<'_>) -> Result<PlayerMeta> {
+    let dan = tag.parse_list::<String>("dan")?.map(|dan| {
+        dan.iter()
+            .map(|d| {
+                d.parse::<usize>()
+                    .ok()
+                    .and_then(|d| DAN_NAMES.get(d))
+                    .map_or_else(|| d.clone(), |&name| name.to_owned())
+            })
+            .collect()
+    });
+    let rate = tag
+        .parse_list::<String>("rate")?
+        .map(|rate| rate.iter().map(|r| r.parse().unwrap_or_default()).collect());
+    let sx = tag.parse_list("sx")?;
+    Ok((dan, rate, sx))
+}
 
-    let kyoku = (seed_values[0] % 4) + 1;
-    let honba = seed_values[1];
-    let kyotaku = seed_values[2];
+fn parse_init_others(tag: &Tag<'_>, aka_flag: bool) -> Result<(Tile, Tile, u8, u8, u8, u8)> {
+    let seed = tag.required("seed")?;
+    let seed_values = seed
+        .split(',')
+        .map(|s| s.parse::<u8>().ok())
+        .collect::<Option<Vec<u8>>>();
+    let Some(&[kyoku_num, honba, kyotaku, _, _, dora_tile]) = seed_values.as_deref() else {
+        return Err(tag.invalid("seed", &seed));
+    };
 
-    let oya = e
-        .try_get_attribute("oya")?
-        .expect("Failed to parse 'oya' attribute.")
-        .decode_and_unescape_value(Decoder {})?
-        .parse::<u8>()
-        .unwrap();
+    let bakaze_index = (kyoku_num / 4) % 4;
+    let bakaze_tile = [109, 113, 117, 121][bakaze_index as usize];
+    let bakaze = tag.tile("seed", bakaze_tile, aka_flag)?;
+    let dora = tag.tile("seed", dora_tile, aka_flag)?;
+    let kyoku = (kyoku_num % 4) + 1;
+
+    let oya = tag.parse::<u8>("oya")?;
 
     Ok((bakaze, dora, kyoku, honba, kyotaku, oya))
 }
 
-pub fn parse_init_scores(e: &BytesStart<'_>) -> Result<[i32; 4], quick_xml::Error> {
+/// Counts from 0 for E1, as in tenhou.net/6.
+const fn kyoku_num(bakaze: Tile, kyoku: u8) -> u8 {
+    (bakaze.as_u8() - tu8!(E)) * 4 + kyoku - 1
+}
+
+fn parse_init_scores(tag: &Tag<'_>, num_players: u8) -> Result<Vec<i32>> {
     // NOTE: Old logs do not have 'ten' values.
-    let scores = e
-        .try_get_attribute("ten")?
-        .expect("Failed to parse 'ten' attribute. Possibly this mjlog file is too old to parse.")
-        .decode_and_unescape_value(Decoder {})?;
-    let scores: [i32; 4] = scores
+    let ten = tag.required("ten")?;
+    let scores = ten
         .split(',')
-        .map(|s| s.parse::<i32>().unwrap() * 100)
-        .collect::<Vec<i32>>()
-        .try_into()
-        .unwrap();
+        .take(num_players as usize)
+        .map(|s| s.parse::<i32>().ok().map(|s| s * 100))
+        .collect::<Option<Vec<i32>>>()
+        .filter(|scores| scores.len() == num_players as usize)
+        .ok_or_else(|| tag.invalid("ten", &ten))?;
     Ok(scores)
 }
 
-pub fn parse_init_tehais<'a>(
-    e: &BytesStart<'a>,
-    aka_flag: bool,
-) -> Result<[[Tile; 13]; 4], quick_xml::Error> {
-    let mut tehais = [[Tile::default(); 13]; 4];
-    for i in 0..4 {
-        let attr_name = format!("hai{}", i);
-        if let Some(attribute) = e.try_get_attribute(attr_name.as_str())? {
-            let tiles = attribute
-                .decode_and_unescape_value(Decoder {})?
+fn parse_init_tehais(tag: &Tag<'_>, aka_flag: bool, num_players: u8) -> Result<Vec<[Tile; 13]>> {
+    const ATTRS: [&str; 4] = ["hai0", "hai1", "hai2", "hai3"];
+
+    let mut tehais = vec![[Tile::default(); 13]; num_players as usize];
+    for (tehai, attr) in tehais.iter_mut().zip(ATTRS) {
+        if let Some(hai) = tag.attr(attr)? {
+            *tehai = hai
                 .split(',')
                 .map(|s| {
-                    let tile = s.parse::<u8>().unwrap();
-                    mjlog::translate_mjlog_tile(tile, aka_flag).unwrap()
+                    s.parse::<u8>()
+                        .ok()
+                        .and_then(|id| translate_mjlog_tile(id, aka_flag))
                 })
-                .collect::<Vec<Tile>>();
-            tehais[i] = tiles.try_into().unwrap();
+                .collect::<Option<Vec<Tile>>>()
+                .and_then(|tiles| tiles.try_into().ok())
+                .ok_or_else(|| tag.invalid(attr, &hai))?;
         }
     }
     Ok(tehais)
 }
 
-pub fn parse_dora<'a>(e: &BytesStart<'a>, aka_flag: bool) -> Result<Tile, quick_xml::Error> {
-    let dora_tile = e
-        .try_get_attribute("hai")?
-        .expect("Failed to parse 'hai' attribute.")
-        .decode_and_unescape_value(Decoder {})?;
-    let dora_tile = dora_tile.parse::<u8>().unwrap();
-    let dora = mjlog::translate_mjlog_tile(dora_tile, aka_flag).unwrap();
-    Ok(dora)
+fn parse_dora(tag: &Tag<'_>, aka_flag: bool) -> Result<Tile> {
+    let dora_tile = tag.parse::<u8>("hai")?;
+    tag.tile("hai", dora_tile, aka_flag)
 }
 
-pub fn parse_n<'a>(
-    e: &BytesStart<'a>,
+fn parse_n(tag: &Tag<'_>, aka_flag: bool, num_players: u8) -> Result<(String, u8, u8, Vec<Tile>)> {
+    let caller = tag.seat("who", num_players)?;
+    let meld = tag.parse::<u16>("m")?;
+    decode_meld(tag, caller, meld, aka_flag, num_players)
+}
+
+/// Decodes a meld code of the `m` attribute, which also appears in AGARI.
+fn decode_meld(
+    tag: &Tag<'_>,
+    caller: u8,
+    meld: u16,
     aka_flag: bool,
-) -> Result<(String, u8, u8, Vec<Tile>), quick_xml::Error> {
-    let caller = e
-        .try_get_attribute("who")?
-        .expect("Failed to parse 'who' attribute.")
-        .decode_and_unescape_value(Decoder {})?
-        .parse::<u8>()
-        .unwrap();
-    let meld = e
-        .try_get_attribute("m")?
-        .expect("Failed to parse 'm' attribute.")
-        .decode_and_unescape_value(Decoder {})?
-        .parse::<u16>()
-        .unwrap();
+    num_players: u8,
+) -> Result<(String, u8, u8, Vec<Tile>)> {
     let callee_rel = (meld & 0x3) as u8;
-    let callee = (caller + callee_rel) % 4;
+    let callee = caller
+        .checked_add(callee_rel)
+        .map(|seat| seat % num_players)
+        .ok_or_else(|| tag.invalid("who", &caller.to_string()))?;
 
     let (call_type, mianzi): (&str, Vec<u8>) = if meld & (1 << 2) != 0 {
         ("Chi", parse_chi(meld))
@@ -344,78 +692,381 @@ pub fn parse_n<'a>(
         ("Pon", parse_pon(meld))
     } else if meld & (1 << 4) != 0 {
         ("Kakan", parse_kakan(meld))
+    } else if meld & (1 << 5) != 0 {
+        ("Nukidora", parse_nukidora(meld))
     } else {
         (
-            if callee_rel == 1 { "Minkan" } else { "Ankan" },
+            if callee_rel == 0 { "Ankan" } else { "Minkan" },
             parse_kan(meld),
         )
     };
 
     let tiles = mianzi
         .iter()
-        .map(|&x| mjlog::translate_mjlog_tile(x, aka_flag).unwrap())
-        .collect::<Vec<Tile>>();
-
-    Ok((call_type.to_string(), caller, callee, tiles))
-}
-
-pub fn parse_reach<'a>(e: &BytesStart<'a>) -> Result<(u8, u8), quick_xml::Error> {
-    let who = e
-        .try_get_attribute("who")?
-        .expect("Failed to parse 'who' attribute.")
-        .decode_and_unescape_value(Decoder {})?;
-    let who = who.parse::<u8>().unwrap();
-    let step = e
-        .try_get_attribute("step")?
-        .expect("Failed to parse 'step' attribute.")
-        .decode_and_unescape_value(Decoder {})?;
-    let step = step.parse::<u8>().unwrap();
+        .map(|&x| tag.tile("m", x, aka_flag))
+        .collect::<Result<Vec<Tile>>>()?;
+
+    Ok((call_type.to_owned(), caller, callee, tiles))
+}
+
+fn parse_reach(tag: &Tag<'_>, num_players: u8) -> Result<(u8, u8)> {
+    let who = tag.seat("who", num_players)?;
+    let step = tag.parse::<u8>("step")?;
     Ok((who, step))
 }
 
-pub fn parse_agari<'a>(
-    e: &BytesStart<'a>,
+#[allow(clippy::type_complexity)]
+fn parse_agari(
+    tag: &Tag<'_>,
     aka_flag: bool,
-) -> Result<(u8, u8, Option<Vec<Tile>>, Option<[i32; 4]>), quick_xml::Error> {
-    let who = e
-        .try_get_attribute("who")?
-        .expect("Failed to parse 'who' attribute.")
-        .decode_and_unescape_value(Decoder {})?;
-    let who = who.parse::<u8>().unwrap();
-
-    let from_who = e
-        .try_get_attribute("fromWho")?
-        .expect("Failed to parse 'fromWho' attribute.")
-        .decode_and_unescape_value(Decoder {})?;
-    let from_who = from_who.parse::<u8>().unwrap();
-
-    let dora_ura_attribute = e.try_get_attribute("doraHaiUra")?;
-    let ura_markers: Option<Vec<Tile>> = if let Some(dora_ura_attribute) = dora_ura_attribute {
-        let dora_ura_str = dora_ura_attribute.decode_and_unescape_value(Decoder {})?;
-        let ura_markers: Vec<Tile> = dora_ura_str
-            .split(",")
-            .map(|s| -> Tile {
-                let tile = s.parse::<u8>().unwrap();
-                mjlog::translate_mjlog_tile(tile, aka_flag).unwrap()
-            })
-            .collect::<Vec<Tile>>();
-        Some(ura_markers)
-    } else {
-        None
-    };
+    num_players: u8,
+) -> Result<(u8, u8, Option<Vec<Tile>>, Option<Vec<i32>>)> {
+    let who = tag.seat("who", num_players)?;
+    let from_who = tag.seat("fromWho", num_players)?;
+
+    let ura_markers = tag
+        .parse_list::<u8>("doraHaiUra")?
+        .map(|ids| {
+            ids.into_iter()
+                .map(|id| tag.tile("doraHaiUra", id, aka_flag))
+                .collect::<Result<Vec<Tile>>>()
+        })
+        .transpose()?;
 
-    let sc_attribute = e.try_get_attribute("sc")?;
-    let deltas = parse_deltas(sc_attribute)?;
+    let deltas = parse_deltas(tag, num_players)?;
     Ok((who, from_who, ura_markers, deltas))
 }
 
-pub fn check_if_owari<'a>(e: &BytesStart<'a>) -> Result<bool, quick_xml::Error> {
-    let owari_attribute = e.try_get_attribute("owari")?;
-    Ok(owari_attribute.is_some())
+/// Parses the hand and its value in AGARI, `None` if there is no `ten`.
+fn parse_hora_info(tag: &Tag<'_>, aka_flag: bool, num_players: u8) -> Result<Option<HoraInfo>> {
+    let Some(ten) = tag.parse_list::<i32>("ten")? else {
+        return Ok(None);
+    };
+    let [fu, points, limit] = ten[..] else {
+        return Err(tag.invalid("ten", &tag.required("ten")?));
+    };
+    let limit = match limit {
+        0 => None,
+        1 => Some(Limit::Mangan),
+        2 => Some(Limit::Haneman),
+        3 => Some(Limit::Baiman),
+        4 => Some(Limit::Sanbaiman),
+        5 => Some(Limit::Yakuman),
+        _ => return Err(tag.invalid("ten", &tag.required("ten")?)),
+    };
+
+    let mut yaku = vec![];
+    for pair in tag.parse_list::<u8>("yaku")?.unwrap_or_default().chunks(2) {
+        let &[id, han] = pair else {
+            return Err(tag.invalid("yaku", &tag.required("yaku")?));
+        };
+        let name = YAKU_NAMES
+            .get(id as usize)
+            .ok_or_else(|| tag.invalid("yaku", &id.to_string()))?;
+        yaku.push(Yaku {
+            name: (*name).to_owned(),
+            han,
+        });
+    }
+    for id in tag.parse_list::<u8>("yakuman")?.unwrap_or_default() {
+        let name = YAKU_NAMES
+            .get(id as usize)
+            .ok_or_else(|| tag.invalid("yakuman", &id.to_string()))?;
+        yaku.push(Yaku {
+            name: (*name).to_owned(),
+            han: 13,
+        });
+    }
+
+    let who = tag.seat("who", num_players)?;
+    let machi = tag.parse::<u8>("machi")?;
+    let mut hai = tag.parse_list::<u8>("hai")?.unwrap_or_default();
+    let pos = hai
+        .iter()
+        .position(|&id| id == machi)
+        .ok_or_else(|| tag.invalid("machi", &machi.to_string()))?;
+    hai.remove(pos);
+    let mut tehai = hai
+        .into_iter()
+        .map(|id| tag.tile("hai", id, aka_flag))
+        .collect::<Result<Vec<_>>>()?;
+    tehai.sort_unstable();
+
+    // Melds are listed from the latest one.
+    let mut melds = vec![];
+    for meld in tag
+        .parse_list::<u16>("m")?
+        .unwrap_or_default()
+        .into_iter()
+        .rev()
+    {
+        let (call_type, caller, callee, tiles) =
+            decode_meld(tag, who, meld, aka_flag, num_players)?;
+        // Kita is listed along with melds in sanma.
+        if call_type != "Nukidora" {
+            melds.extend(meld_to_event(&call_type, caller, callee, &tiles));
+        }
+    }
+
+    Ok(Some(HoraInfo {
+        value: HandValue {
+            fu: fu as u8,
+            han: yaku.iter().map(|y| y.han).sum(),
+            points,
+            limit,
+            yaku,
+        },
+        pai: tag.tile("machi", machi, aka_flag)?,
+        tehai,
+        melds,
+    }))
 }
 
-pub fn parse_ryuukyoku<'a>(e: &BytesStart<'a>) -> Result<Option<[i32; 4]>, quick_xml::Error> {
-    let sc_attribute = e.try_get_attribute("sc")?;
-    let deltas = parse_deltas(sc_attribute)?;
-    Ok(deltas)
+/// Parses the final scores and points in `owari`, `None` if the game goes on.
+fn parse_owari(tag: &Tag<'_>, num_players: u8) -> Result<Option<GameResult>> {
+    let Some(owari) = tag.parse_list::<f64>("owari")? else {
+        return Ok(None);
+    };
+    if owari.len() < num_players as usize * 2 {
+        return Err(tag.invalid("owari", &tag.required("owari")?));
+    }
+    let (scores, points) = owari
+        .chunks_exact(2)
+        .take(num_players as usize)
+        .map(|pair| (pair[0] as i32 * 100, pair[1]))
+        .unzip();
+    Ok(Some(GameResult::with_points(scores, points)))
+}
+
+fn parse_ryuukyoku(
+    tag: &Tag<'_>,
+    aka_flag: bool,
+    num_players: u8,
+) -> Result<(Option<Vec<i32>>, RyukyokuInfo)> {
+    const HAI: [&str; 4] = ["hai0", "hai1", "hai2", "hai3"];
+
+    let reason = match tag.attr("type")?.as_deref() {
+        None => RyukyokuReason::Exhaustive,
+        Some("yao9") => RyukyokuReason::KyuushuKyuuhai,
+        Some("reach4") => RyukyokuReason::SuuchaRiichi,
+        Some("ron3") => RyukyokuReason::SanchaHou,
+        Some("kan4") => RyukyokuReason::SuukanSanra,
+        Some("kaze4") => RyukyokuReason::SuufonRenda,
+        Some("nm") => RyukyokuReason::NagashiMangan,
+        Some(other) => return Err(tag.invalid("type", other)),
+    };
+    let tehais = HAI
+        .into_iter()
+        .take(num_players as usize)
+        .map(|attr| {
+            tag.parse_list::<u8>(attr)?
+                .map(|ids| {
+                    let mut tehai = ids
+                        .into_iter()
+                        .map(|id| tag.tile(attr, id, aka_flag))
+                        .collect::<Result<Vec<_>>>()?;
+                    tehai.sort_unstable();
+                    Ok(tehai)
+                })
+                .transpose()
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // Hands are shown by those who are tenpai, except for 九種九牌.
+    let tenpai = tehais
+        .iter()
+        .map(|tehai| tehai.is_some() && reason != RyukyokuReason::KyuushuKyuuhai)
+        .collect();
+
+    let info = RyukyokuInfo {
+        reason,
+        tenpai,
+        tehais,
+    };
+    Ok((parse_deltas(tag, num_players)?, info))
+}
+
+/// Names of yaku indexed by their IDs in mjlog.
+#[rustfmt::skip]
+const YAKU_NAMES: [&str; 55] = [
+    "門前清自摸和", "立直", "一発", "槍槓", "嶺上開花", "海底摸月", "河底撈魚", "平和",
+    "断幺九", "一盃口", "自風 東", "自風 南", "自風 西", "自風 北", "場風 東", "場風 南",
+    "場風 西", "場風 北", "役牌 白", "役牌 發", "役牌 中", "両立直", "七対子", "混全帯幺九",
+    "一気通貫", "三色同順", "三色同刻", "三槓子", "対々和", "三暗刻", "小三元", "混老頭",
+    "二盃口", "純全帯幺九", "混一色", "清一色", "人和", "天和", "地和", "大三元", "四暗刻",
+    "四暗刻単騎", "字一色", "緑一色", "清老頭", "九蓮宝燈", "純正九蓮宝燈", "国士無双",
+    "国士無双１３面", "大四喜", "小四喜", "四槓子", "ドラ", "裏ドラ", "赤ドラ",
+];
+
+/// Turns the result of `parse_n` into a mjai event.
+fn meld_to_event(call_type: &str, caller: u8, callee: u8, tiles: &[Tile]) -> Option<Event> {
+    let event = match call_type {
+        "Chi" => Event::Chi {
+            actor: caller,
+            target: callee,
+            pai: tiles[0],
+            consumed: tiles[1..].try_into().unwrap(),
+        },
+        "Pon" => Event::Pon {
+            actor: caller,
+            target: callee,
+            pai: tiles[0],
+            consumed: tiles[1..].try_into().unwrap(),
+        },
+        "Kakan" => Event::Kakan {
+            actor: caller,
+            pai: tiles[0],
+            consumed: tiles[1..].try_into().unwrap(),
+        },
+        // The copy 0 is aka if any, which goes last.
+        "Ankan" => Event::Ankan {
+            actor: caller,
+            consumed: [tiles[1], tiles[2], tiles[3], tiles[0]],
+        },
+        "Minkan" => Event::Daiminkan {
+            actor: caller,
+            target: callee,
+            pai: tiles[0],
+            consumed: tiles[1..].try_into().unwrap(),
+        },
+        "Nukidora" => Event::Nukidora {
+            actor: caller,
+            pai: tiles[0],
+        },
+        _ => return None,
+    };
+    Some(event)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testdata;
+
+    #[test]
+    fn parse_mjlog_files() {
+        for (name, xml) in testdata::mjlogs() {
+            let events = parse_str(&xml).unwrap_or_else(|e| panic!("{name}: {e}"));
+            let Some(Event::StartGame { names, meta, .. }) = events.first() else {
+                panic!("{name}: no start_game");
+            };
+            assert_eq!(meta.dan.as_ref().map(Vec::len), Some(names.len()));
+            assert!(meta.ratingc.is_some());
+            // Logs of observed moves end in the middle of a game.
+            if let Some(Event::EndGame { info }) = events.last() {
+                let result = info.as_ref().expect("owari is parsed");
+                assert_eq!(result.ranks.len(), names.len());
+            }
+            assert!(events
+                .iter()
+                .any(|ev| matches!(ev, Event::StartKyoku { .. })));
+        }
+    }
+
+    #[test]
+    fn reach_accepted_timing() {
+        for name in [
+            "2010091009gm-00a9-0000-83af2648&tw=2.mjlog",
+            "double-ron.mjlog",
+        ] {
+            let xml = testdata::read(name);
+            let raw_log = mjlog_to_tenhou(&xml).unwrap();
+            let log = crate::tenhou::Log::try_from(raw_log).unwrap();
+            let expected = crate::tenhou_to_mjai(&log).unwrap();
+
+            // Tehais and tsumogiri may differ, but the order of events must not.
+            let kinds = |events: &[Event]| -> Vec<_> {
+                events
+                    .iter()
+                    .map(|ev| (std::mem::discriminant(ev), ev.actor()))
+                    .collect()
+            };
+            assert_eq!(kinds(&parse_str(&xml).unwrap()), kinds(&expected), "{name}");
+        }
+    }
+
+    #[test]
+    fn normalized() {
+        let options = ConvertOptions::normalized();
+        for name in [
+            "2010091009gm-00a9-0000-83af2648&tw=2.mjlog",
+            "double-ron.mjlog",
+        ] {
+            let xml = testdata::read(name);
+            let log = crate::tenhou::Log::try_from(mjlog_to_tenhou(&xml).unwrap()).unwrap();
+            let expected = crate::tenhou_to_mjai_with(&log, &options).unwrap();
+            let events = parse_str_with(&xml, &options).unwrap();
+            assert_eq!(events, expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn malformed_mjlog() {
+        let head = r#"<mjloggm ver="2.3"><GO type="169" lobby="0"/>"#;
+
+        let xml = format!(r#"{head}<REACH step="1"/></mjloggm>"#);
+        match parse_str(&xml) {
+            Err(MjlogError::MissingAttribute { tag, attr, offset }) => {
+                assert_eq!((tag.as_str(), attr), ("REACH", "who"));
+                assert_eq!(offset as usize, head.len());
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+
+        let xml = format!(r#"{head}<DORA hai="x"/></mjloggm>"#);
+        assert!(matches!(
+            parse_str(&xml),
+            Err(MjlogError::InvalidAttribute { attr: "hai", .. }),
+        ));
+
+        let xml = format!("{head}<T200/></mjloggm>");
+        assert!(matches!(
+            parse_str(&xml),
+            Err(MjlogError::InvalidTileTag { .. }),
+        ));
+
+        let xml = format!("{head}<T12");
+        assert!(matches!(parse_str(&xml), Err(MjlogError::Xml { .. })));
+    }
+
+    #[test]
+    fn seats_out_of_range() {
+        let head = r#"<mjloggm ver="2.3"><GO type="169" lobby="0"/>"#;
+        let cases = [
+            (r#"<REACH who="5" step="2"/>"#, "who"),
+            (r#"<REACH who="9" step="1"/>"#, "who"),
+            (r#"<N who="255" m="1"/>"#, "who"),
+            (r#"<N who="0" m="65280"/>"#, "m"),
+            (r#"<AGARI who="0" fromWho="4"/>"#, "fromWho"),
+        ];
+        for (body, expected) in cases {
+            let xml = format!("{head}{body}</mjloggm>");
+            for result in [parse_str(&xml).map(drop), mjlog_to_tenhou(&xml).map(drop)] {
+                match result {
+                    Err(MjlogError::InvalidAttribute { attr, .. }) => {
+                        assert_eq!(attr, expected, "{body}");
+                    }
+                    other => panic!("{body}: unexpected {other:?}"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn errors_tell_the_kyoku() {
+        let xml = testdata::read("double-ron.mjlog").replacen(
+            r#"<REACH who="1" step="1"/>"#,
+            r#"<REACH who="9" step="1"/>"#,
+            1,
+        );
+        for err in [
+            parse_str(&xml).unwrap_err(),
+            mjlog_to_tenhou(&xml).unwrap_err(),
+        ] {
+            assert!(
+                err.to_string()
+                    .starts_with(r#"at E1.0: invalid attribute who="9""#),
+                "{err}"
+            );
+        }
+    }
 }
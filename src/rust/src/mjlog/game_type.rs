@@ -0,0 +1,163 @@
+use crate::mjai::LobbyTier;
+
+/// Flags of the `type` attribute in the GO tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameType {
+    /// Played against humans rather than computers.
+    pub online: bool,
+    pub aka: bool,
+    pub kuitan: bool,
+    /// Hanchan if true, tonpuu otherwise.
+    pub hanchan: bool,
+    pub sanma: bool,
+    pub lobby_tier: LobbyTier,
+    /// 速 rule, where the time limits are shorter.
+    pub fast: bool,
+}
+
+/// What a log without the GO tag is read as, four players in hanchan without
+/// aka.
+impl Default for GameType {
+    fn default() -> Self {
+        Self::from_bits(Self::NO_AKA | Self::HANCHAN)
+    }
+}
+
+impl GameType {
+    const ONLINE: u16 = 0x01;
+    const NO_AKA: u16 = 0x02;
+    const NO_KUITAN: u16 = 0x04;
+    const HANCHAN: u16 = 0x08;
+    const SANMA: u16 = 0x10;
+    const TOKUJOU: u16 = 0x20;
+    const FAST: u16 = 0x40;
+    const JOUKYUU: u16 = 0x80;
+
+    #[must_use]
+    pub const fn from_bits(bits: u16) -> Self {
+        let lobby_tier = match (bits & Self::TOKUJOU != 0, bits & Self::JOUKYUU != 0) {
+            (true, true) => LobbyTier::Houou,
+            (true, false) => LobbyTier::Tokujou,
+            (false, true) => LobbyTier::Joukyuu,
+            (false, false) => LobbyTier::Ippan,
+        };
+        Self {
+            online: bits & Self::ONLINE != 0,
+            aka: bits & Self::NO_AKA == 0,
+            kuitan: bits & Self::NO_KUITAN == 0,
+            hanchan: bits & Self::HANCHAN != 0,
+            sanma: bits & Self::SANMA != 0,
+            lobby_tier,
+            fast: bits & Self::FAST != 0,
+        }
+    }
+
+    #[must_use]
+    pub const fn bits(self) -> u16 {
+        let tier = match self.lobby_tier {
+            LobbyTier::Ippan => 0,
+            LobbyTier::Joukyuu => Self::JOUKYUU,
+            LobbyTier::Tokujou => Self::TOKUJOU,
+            LobbyTier::Houou => Self::TOKUJOU | Self::JOUKYUU,
+        };
+        (self.online as u16 * Self::ONLINE)
+            | (!self.aka as u16 * Self::NO_AKA)
+            | (!self.kuitan as u16 * Self::NO_KUITAN)
+            | (self.hanchan as u16 * Self::HANCHAN)
+            | (self.sanma as u16 * Self::SANMA)
+            | tier
+            | (self.fast as u16 * Self::FAST)
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn num_players(self) -> u8 {
+        if self.sanma {
+            3
+        } else {
+            4
+        }
+    }
+
+    /// Returns the `kyoku_first` of `start_game`, 0 for hanchan and 4 for
+    /// tonpuu.
+    #[inline]
+    #[must_use]
+    pub const fn kyoku_first(self) -> u8 {
+        if self.hanchan {
+            0
+        } else {
+            4
+        }
+    }
+
+    /// Returns `ratingc` of tenhou.net/6.
+    #[inline]
+    #[must_use]
+    pub const fn ratingc(self) -> &'static str {
+        if self.sanma {
+            "PF3"
+        } else {
+            "PF4"
+        }
+    }
+
+    /// Returns the rule the way tenhou.net/6 shows it, e.g. "四鳳南喰赤".
+    #[must_use]
+    pub fn disp(self) -> String {
+        let mut disp = String::from(if self.sanma { '三' } else { '四' });
+        disp.push(self.lobby_tier.to_char());
+        disp.push(if self.hanchan { '南' } else { '東' });
+        if self.kuitan {
+            disp.push('喰');
+        }
+        if self.aka {
+            disp.push('赤');
+        }
+        if self.fast {
+            disp.push('速');
+        }
+        disp
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flags() {
+        // 四鳳南喰赤
+        let game_type = GameType::from_bits(169);
+        assert_eq!(
+            game_type,
+            GameType {
+                online: true,
+                aka: true,
+                kuitan: true,
+                hanchan: true,
+                sanma: false,
+                lobby_tier: LobbyTier::Houou,
+                fast: false,
+            },
+        );
+        assert_eq!(game_type.disp(), "四鳳南喰赤");
+        assert_eq!(game_type.kyoku_first(), 0);
+
+        // 三般東喰速, without aka
+        let game_type = GameType::from_bits(0x01 | 0x02 | 0x10 | 0x40);
+        assert!(!game_type.aka);
+        assert_eq!(game_type.num_players(), 3);
+        assert_eq!(game_type.kyoku_first(), 4);
+        assert_eq!(game_type.disp(), "三般東喰速");
+
+        let game_type = GameType::default();
+        assert!(!game_type.aka);
+        assert_eq!(game_type.num_players(), 4);
+        assert_eq!(game_type.kyoku_first(), 0);
+
+        for bits in 0..=0xff {
+            assert_eq!(GameType::from_bits(bits).bits(), bits);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use super::{
+    kyoku_num, meld_to_event, parse_agari, parse_dora, parse_go, parse_hora_info,
+    parse_init_others, parse_init_scores, parse_init_tehais, parse_mjloggm_version, parse_n,
+    parse_names, parse_owari, parse_player_meta, parse_reach, parse_ryuukyoku, parse_tile_tag,
+    read_event, GameType, MjlogError, Result, Tag,
+};
+use crate::mjai::Event;
+use crate::reverse::{KyokuBuilder, ReverseError};
+use crate::tenhou::{encode_sc, RawLog, Rule};
+use std::array;
+
+use quick_xml::events::Event as XmlEvent;
+use quick_xml::reader::Reader as XmlReader;
+
+/// Transform a mjlog XML into tenhou.net/6 format.
+///
+/// Unlike going through mjai, tsumogiri is told by the exact tile IDs, and
+/// `dan`, `rate`, `sx` and `lobby` are taken from the UN and GO tags.
+pub fn mjlog_to_tenhou(xml: &str) -> Result<RawLog> {
+    let mut init = None;
+    read_log(xml, &mut init).map_err(|e| e.in_kyoku(init))
+}
+
+/// Does [`mjlog_to_tenhou`], keeping the kyoku number and honba of the last
+/// INIT in `init`.
+fn read_log(xml: &str, init: &mut Option<(u8, u8)>) -> Result<RawLog> {
+    let mut reader = XmlReader::from_str(xml);
+    let mut buf = Vec::new();
+
+    let mut game_type = GameType::default();
+    let mut lobby = None;
+    let mut aka_flag = game_type.aka;
+    let mut num_players = game_type.num_players();
+    let mut names: Vec<String> = vec![];
+    let mut player_meta = (None, None, None);
+    let mut result = None;
+    let mut is_initialized = false;
+
+    let mut logs = vec![];
+    let mut builder: Option<KyokuBuilder> = None;
+    let mut last_draw: [Option<u8>; 4] = [None; 4];
+
+    loop {
+        let event = match read_event(&mut reader, &mut buf)? {
+            (XmlEvent::Eof, _) => break,
+            (XmlEvent::Start(e), offset) if e.name().as_ref() == b"mjloggm" => {
+                let ver = parse_mjloggm_version(&Tag { e, offset })?;
+                if ver != "2.3" {
+                    return Err(MjlogError::UnsupportedVersion(ver));
+                }
+                None
+            }
+            (XmlEvent::Empty(e), offset) => {
+                let tag = Tag { e, offset };
+                match tag.e.name().as_ref() {
+                    b"GO" => {
+                        (game_type, lobby) = parse_go(&tag)?;
+                        num_players = game_type.num_players();
+                        aka_flag = game_type.aka;
+                        None
+                    }
+                    b"UN" => {
+                        // Later UN tags are written on reconnection.
+                        if !is_initialized {
+                            names = parse_names(&tag, num_players)?;
+                            player_meta = parse_player_meta(&tag)?;
+                            is_initialized = true;
+                        }
+                        None
+                    }
+                    b"INIT" => {
+                        *init = None;
+                        let (bakaze, dora_marker, kyoku, honba, kyotaku, oya) =
+                            parse_init_others(&tag, aka_flag)?;
+                        *init = Some((kyoku_num(bakaze, kyoku), honba));
+                        let start_kyoku = Event::StartKyoku {
+                            bakaze,
+                            dora_marker,
+                            kyoku,
+                            honba,
+                            kyotaku,
+                            oya,
+                            scores: parse_init_scores(&tag, num_players)?,
+                            tehais: parse_init_tehais(&tag, aka_flag, num_players)?,
+                        };
+                        if let Some(b) = builder.replace(KyokuBuilder::start(&start_kyoku)?) {
+                            logs.push(b.finish());
+                        }
+                        last_draw = [None; 4];
+                        None
+                    }
+                    b"DORA" => Some(Event::Dora {
+                        dora_marker: parse_dora(&tag, aka_flag)?,
+                    }),
+                    b"N" => {
+                        let (call_type, caller, callee, tiles) =
+                            parse_n(&tag, aka_flag, num_players)?;
+                        meld_to_event(&call_type, caller, callee, &tiles)
+                    }
+                    b"REACH" => match parse_reach(&tag, num_players)? {
+                        (actor, 1) => Some(Event::Reach { actor }),
+                        _ => None,
+                    },
+                    b"AGARI" => {
+                        let (actor, target, ura_markers, deltas) =
+                            parse_agari(&tag, aka_flag, num_players)?;
+                        let info = parse_hora_info(&tag, aka_flag, num_players)?;
+                        result = parse_owari(&tag, num_players)?;
+                        Some(Event::Hora {
+                            actor,
+                            target,
+                            deltas,
+                            ura_markers,
+                            info: info.map(Box::new),
+                        })
+                    }
+                    b"RYUUKYOKU" => {
+                        let (deltas, info) = parse_ryuukyoku(&tag, aka_flag, num_players)?;
+                        result = parse_owari(&tag, num_players)?;
+                        Some(Event::Ryukyoku {
+                            deltas,
+                            info: Some(Box::new(info)),
+                        })
+                    }
+                    _ => match parse_tile_tag(&tag, aka_flag)? {
+                        Some((head @ b'T'..=b'W', id, pai)) => {
+                            let actor = head - b'T';
+                            last_draw[actor as usize] = Some(id);
+                            Some(Event::Tsumo { actor, pai })
+                        }
+                        Some((head, id, pai)) => {
+                            let actor = head - b'D';
+                            let tsumogiri = last_draw[actor as usize].take() == Some(id);
+                            Some(Event::Dahai {
+                                actor,
+                                pai,
+                                tsumogiri,
+                            })
+                        }
+                        None => None,
+                    },
+                }
+            }
+            _ => None,
+        };
+
+        if let Some(event) = event {
+            builder
+                .as_mut()
+                .ok_or_else(|| ReverseError::OutsideKyoku(event.clone()))?
+                .push(&event)?;
+        }
+        buf.clear();
+    }
+
+    if let Some(b) = builder.take() {
+        logs.push(b.finish());
+    }
+
+    if names.is_empty() {
+        names = (1..=num_players).map(|i| format!("player{i}")).collect();
+    }
+    // tenhou.net/6 leaves the fourth name empty in sanma.
+    let mut names = names.into_iter();
+    let names = array::from_fn(|_| names.next().unwrap_or_default());
+
+    let (dan, rate, sx) = player_meta;
+    Ok(RawLog {
+        logs,
+        names,
+        rule: rule_from_game_type(game_type),
+        ratingc: Some(game_type.ratingc().to_owned()),
+        lobby,
+        dan,
+        rate,
+        sx,
+        sc: result.as_ref().map(encode_sc),
+    })
+}
+
+/// Builds the rule in the way tenhou.net/6 shows it, e.g. "四鳳南喰赤".
+fn rule_from_game_type(game_type: GameType) -> Rule {
+    let aka = u8::from(game_type.aka);
+    Rule {
+        disp: game_type.disp(),
+        aka: 0,
+        // There is no 5m in sanma.
+        aka51: u8::from(game_type.aka && !game_type.sanma),
+        aka52: aka,
+        aka53: aka,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tenhou::Log;
+    use crate::{tenhou_to_mjai, testdata};
+
+    #[test]
+    fn convert_mjlog() {
+        for (name, xml) in testdata::mjlogs() {
+            let raw_log = mjlog_to_tenhou(&xml).unwrap_or_else(|e| panic!("{name}: {e}"));
+            assert_eq!(raw_log.rule.disp, "四鳳南喰赤");
+            assert!(raw_log.dan.as_ref().is_some_and(|dan| dan.len() == 4));
+
+            // Kyokus cut off in the middle cannot be converted any further.
+            if name.starts_with("obs-") {
+                continue;
+            }
+            let json_string = serde_json::to_string(&raw_log).unwrap();
+            let log = Log::from_json_str(&json_string).unwrap();
+            tenhou_to_mjai(&log).unwrap_or_else(|e| panic!("{name}: {e}"));
+        }
+    }
+
+    #[test]
+    fn missing_go() {
+        let xml = testdata::read("double-ron.mjlog");
+        let start = xml.find("<GO ").unwrap();
+        let end = start + xml[start..].find("/>").unwrap() + 2;
+        let xml = format!("{}{}", &xml[..start], &xml[end..]);
+
+        let start_game = |events: &[Event]| match &events[0] {
+            Event::StartGame {
+                names,
+                kyoku_first,
+                aka_flag,
+                ..
+            } => (names.len(), *kyoku_first, *aka_flag),
+            ev => panic!("unexpected {ev:?}"),
+        };
+        let events = crate::mjlog::parse_str(&xml).unwrap();
+        assert_eq!(start_game(&events), (4, 0, false));
+        let log = Log::try_from(mjlog_to_tenhou(&xml).unwrap()).unwrap();
+        assert_eq!(start_game(&tenhou_to_mjai(&log).unwrap()), (4, 0, false));
+    }
+
+    #[test]
+    fn missing_un() {
+        let log = Log::from_json_str(&testdata::read("sanma.json")).unwrap();
+        let xml = crate::mjai_to_mjlog(&tenhou_to_mjai(&log).unwrap()).unwrap();
+        let start = xml.find("<UN ").unwrap();
+        let end = start + xml[start..].find("/>").unwrap() + 2;
+        let xml = format!("{}{}", &xml[..start], &xml[end..]);
+
+        let raw_log = mjlog_to_tenhou(&xml).unwrap();
+        assert_eq!(raw_log.names, ["player1", "player2", "player3", ""]);
+    }
+}
@@ -0,0 +1,734 @@
+use super::{GameType, DAN_NAMES, YAKU_NAMES};
+use crate::mjai::{Event, GameResult, HandValue, Limit, LobbyTier};
+use crate::{tu8, Tile};
+use std::array;
+
+use thiserror::Error;
+use urlencoding::encode;
+
+#[derive(Debug, Error)]
+pub enum WriteError {
+    #[error("missing start_game event")]
+    MissingStartGame,
+
+    #[error("event outside of any kyoku: {0:?}")]
+    OutsideKyoku(Event),
+
+    #[error("invalid actor: {0:?}")]
+    InvalidActor(Event),
+
+    #[error("invalid bakaze: {0}")]
+    InvalidBakaze(Tile),
+
+    #[error("unknown tile cannot be written into mjlog")]
+    UnknownTile,
+
+    #[error("invalid tile ID: {0}")]
+    InvalidTileId(u8),
+
+    #[error("more than four copies of tile {0}")]
+    TileExhausted(Tile),
+
+    #[error("tile {pai} is not in the hand of actor {actor}")]
+    TileNotInHand { actor: u8, pai: Tile },
+
+    #[error("kakan without a preceding pon: actor {actor}, pai {pai}")]
+    KakanWithoutPon { actor: u8, pai: Tile },
+
+    #[error("nothing to call: {0:?}")]
+    NothingToCall(Event),
+}
+
+pub type Result<T> = std::result::Result<T, WriteError>;
+
+/// A meld that has been made public, kept to write the `m` attribute of
+/// `AGARI` and to upgrade pons into kakans.
+#[derive(Debug, Clone)]
+struct Meld {
+    code: u16,
+    ids: Vec<u8>,
+}
+
+/// Tracks the 136-tile IDs of a kyoku, since mjlog identifies each physical
+/// tile while mjai only tells the kind.
+#[derive(Debug)]
+struct KyokuState {
+    aka_flag: bool,
+    num_players: u8,
+    honba: u8,
+    kyotaku: u8,
+    scores: [i32; 4],
+
+    /// Bitmask of copies already seen for each tile kind.
+    used: [u8; 34],
+    hands: [Vec<u8>; 4],
+    melds: [Vec<Meld>; 4],
+    last_draw: [Option<u8>; 4],
+    last_discard: Option<(u8, u8)>,
+    dora_ids: Vec<u8>,
+    ura_ids: Option<Vec<u8>>,
+}
+
+/// Transform a mjai format log into mjlog (ver 2.3) XML.
+///
+/// The 136-tile IDs are assigned on the fly, with copy 0 of each 5 reserved
+/// for the aka tile when aka is enabled. Attributes that mjai does not carry,
+/// such as `SHUFFLE` and dice values, are not written.
+pub fn mjai_to_mjlog(events: &[Event]) -> Result<String> {
+    let mut events = events.iter();
+
+    let (names, kyoku_first, aka_flag, lobby_tier, meta) = match events.next() {
+        Some(Event::StartGame {
+            names,
+            kyoku_first,
+            aka_flag,
+            lobby_tier,
+            meta,
+        }) => (names, *kyoku_first, *aka_flag, *lobby_tier, meta),
+        _ => return Err(WriteError::MissingStartGame),
+    };
+
+    let num_players = names.len() as u8;
+
+    let game_type = GameType {
+        online: true,
+        aka: aka_flag,
+        kuitan: true,
+        hanchan: kyoku_first != 4,
+        sanma: num_players == 3,
+        lobby_tier: lobby_tier.unwrap_or(LobbyTier::Ippan),
+        fast: false,
+    }
+    .bits();
+
+    // The fourth name is left empty in sanma.
+    let names: [_; 4] = array::from_fn(|i| encode(names.get(i).map_or("", String::as_str)));
+    let dan = (0..4)
+        .map(|i| {
+            let dan = meta.dan.as_ref().and_then(|dan| dan.get(i));
+            let id = dan.and_then(|dan| DAN_NAMES.iter().position(|name| name == dan));
+            id.unwrap_or(0).to_string()
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    let rate = (0..4)
+        .map(|i| {
+            let rate = meta.rate.as_ref().and_then(|rate| rate.get(i));
+            format!("{:.2}", rate.copied().unwrap_or(1500.0))
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    let sx = (0..4)
+        .map(|i| {
+            let sx = meta.sx.as_ref().and_then(|sx| sx.get(i));
+            sx.map_or("C", String::as_str)
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    let lobby = meta.lobby.unwrap_or(0);
+
+    let mut tags = vec![
+        r#"<mjloggm ver="2.3">"#.to_owned(),
+        format!(r#"<GO type="{game_type}" lobby="{lobby}"/>"#),
+        format!(
+            r#"<UN n0="{}" n1="{}" n2="{}" n3="{}" dan="{dan}" rate="{rate}" sx="{sx}"/>"#,
+            names[0], names[1], names[2], names[3],
+        ),
+        r#"<TAIKYOKU oya="0"/>"#.to_owned(),
+    ];
+
+    let mut state: Option<KyokuState> = None;
+    let mut last_result: Option<(usize, [i32; 4])> = None;
+    let mut total = None;
+
+    for event in events {
+        if event.actor().is_some_and(|a| a >= num_players) {
+            return Err(WriteError::InvalidActor(event.clone()));
+        }
+
+        match event {
+            Event::None | Event::EndKyoku => (),
+
+            Event::StartKyoku {
+                bakaze,
+                dora_marker,
+                kyoku,
+                honba,
+                kyotaku,
+                oya,
+                scores,
+                tehais,
+            } => {
+                let bakaze_num = match bakaze.as_u8() {
+                    tu8!(E) => 0,
+                    tu8!(S) => 1,
+                    tu8!(W) => 2,
+                    tu8!(N) => 3,
+                    _ => return Err(WriteError::InvalidBakaze(*bakaze)),
+                };
+                total
+                    .get_or_insert_with(|| scores.iter().sum::<i32>() + i32::from(*kyotaku) * 1000);
+
+                let mut s = KyokuState {
+                    aka_flag,
+                    num_players,
+                    honba: *honba,
+                    kyotaku: *kyotaku,
+                    scores: array::from_fn(|i| scores.get(i).copied().unwrap_or_default()),
+                    used: [0; 34],
+                    hands: Default::default(),
+                    melds: Default::default(),
+                    last_draw: [None; 4],
+                    last_discard: None,
+                    dora_ids: vec![],
+                    ura_ids: None,
+                };
+                for (hand, tehai) in s.hands.iter_mut().zip(tehais) {
+                    *hand = tehai
+                        .iter()
+                        .map(|&t| alloc_id(&mut s.used, aka_flag, t))
+                        .collect::<Result<_>>()?;
+                }
+                let dora_id = alloc_id(&mut s.used, aka_flag, *dora_marker)?;
+                s.dora_ids.push(dora_id);
+
+                let mut attrs = vec![
+                    format!(
+                        r#"seed="{},{honba},{kyotaku},0,0,{dora_id}""#,
+                        bakaze_num * 4 + kyoku.saturating_sub(1) % 4,
+                    ),
+                    format!(r#"ten="{}""#, join_hundreds(&s.scores)),
+                    format!(r#"oya="{oya}""#),
+                ];
+                for (i, hand) in s.hands.iter().enumerate() {
+                    attrs.push(format!(r#"hai{i}="{}""#, join_ids(hand)));
+                }
+                tags.push(format!("<INIT {}/>", attrs.join(" ")));
+
+                state = Some(s);
+            }
+
+            Event::EndGame { info } => {
+                if let Some((idx, final_scores)) = last_result.take() {
+                    let computed;
+                    let result = if let Some(info) = info {
+                        &**info
+                    } else {
+                        let scores = final_scores[..num_players as usize].to_vec();
+                        computed = GameResult::from_scores(scores, total.unwrap_or_default());
+                        &computed
+                    };
+                    let owari = (0..4)
+                        .map(|i| {
+                            let score = result.scores.get(i).map_or(0, |s| s / 100);
+                            let point = result.points.get(i).copied().unwrap_or_default();
+                            format!("{score},{point:.1}")
+                        })
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    let tag = &mut tags[idx];
+                    tag.insert_str(tag.len() - 2, &format!(r#" owari="{owari}""#));
+                }
+            }
+
+            _ => {
+                let s = state
+                    .as_mut()
+                    .ok_or_else(|| WriteError::OutsideKyoku(event.clone()))?;
+                tags.push(s.write(event)?);
+                if matches!(event, Event::Hora { .. } | Event::Ryukyoku { .. }) {
+                    last_result = Some((tags.len() - 1, s.scores));
+                }
+            }
+        }
+    }
+
+    tags.push("</mjloggm>".to_owned());
+    Ok(tags.concat())
+}
+
+impl KyokuState {
+    fn write(&mut self, event: &Event) -> Result<String> {
+        let tag = match *event {
+            Event::Tsumo { actor, pai } => {
+                let id = alloc_id(&mut self.used, self.aka_flag, pai)?;
+                self.hands[actor as usize].push(id);
+                self.last_draw[actor as usize] = Some(id);
+                format!("<{}{id}/>", char::from(b"TUVW"[actor as usize]))
+            }
+
+            Event::Dahai {
+                actor,
+                pai,
+                tsumogiri,
+            } => {
+                let prefer = if tsumogiri {
+                    self.last_draw[actor as usize]
+                } else {
+                    None
+                };
+                let id = self.take_from_hand(actor, pai, prefer)?;
+                self.last_draw[actor as usize] = None;
+                self.last_discard = Some((actor, id));
+                format!("<{}{id}/>", char::from(b"DEFG"[actor as usize]))
+            }
+
+            Event::Chi {
+                actor,
+                target,
+                consumed,
+                ..
+            }
+            | Event::Pon {
+                actor,
+                target,
+                consumed,
+                ..
+            } => {
+                let called = self.take_last_discard(target, event)?;
+                let c0 = self.take_from_hand(actor, consumed[0], None)?;
+                let c1 = self.take_from_hand(actor, consumed[1], None)?;
+                let rel = relative_seat(actor, target, self.num_players);
+                let code = if matches!(event, Event::Chi { .. }) {
+                    encode_chi(rel, called, [c0, c1])
+                } else {
+                    encode_pon(rel, called, [c0, c1])
+                };
+                self.push_meld(actor, code, vec![called, c0, c1])
+            }
+
+            Event::Daiminkan {
+                actor,
+                target,
+                consumed,
+                ..
+            } => {
+                let called = self.take_last_discard(target, event)?;
+                let mut ids = vec![called];
+                for &t in &consumed {
+                    ids.push(self.take_from_hand(actor, t, None)?);
+                }
+                let code = encode_kan(relative_seat(actor, target, self.num_players), called);
+                self.push_meld(actor, code, ids)
+            }
+
+            Event::Kakan { actor, pai, .. } => {
+                let added = self.take_from_hand(actor, pai, None)?;
+                let meld = self.melds[actor as usize]
+                    .iter_mut()
+                    .find(|m| m.code & (1 << 3) != 0 && m.ids[0] / 4 == added / 4)
+                    .ok_or(WriteError::KakanWithoutPon { actor, pai })?;
+                meld.code = encode_kakan(meld.code, added);
+                meld.ids.push(added);
+                self.last_discard = Some((actor, added));
+                format!(r#"<N who="{actor}" m="{}"/>"#, meld.code)
+            }
+
+            Event::Ankan { actor, consumed } => {
+                let mut ids = vec![];
+                for &t in &consumed {
+                    ids.push(self.take_from_hand(actor, t, None)?);
+                }
+                let code = encode_kan(0, ids.iter().copied().min().unwrap_or_default());
+                self.push_meld(actor, code, ids)
+            }
+
+            Event::Nukidora { actor, pai } => {
+                let id = self.take_from_hand(actor, pai, None)?;
+                format!(r#"<N who="{actor}" m="{}"/>"#, encode_nukidora(id))
+            }
+
+            Event::Dora { dora_marker } => {
+                let id = alloc_id(&mut self.used, self.aka_flag, dora_marker)?;
+                self.dora_ids.push(id);
+                format!(r#"<DORA hai="{id}"/>"#)
+            }
+
+            Event::Reach { actor } => format!(r#"<REACH who="{actor}" step="1"/>"#),
+            Event::ReachAccepted { actor } => {
+                self.scores[actor as usize] -= 1000;
+                self.kyotaku += 1;
+                format!(
+                    r#"<REACH who="{actor}" ten="{}" step="2"/>"#,
+                    join_hundreds(&self.scores),
+                )
+            }
+
+            Event::Hora {
+                actor,
+                target,
+                ref deltas,
+                ref ura_markers,
+                ref info,
+            } => {
+                let mut hai = self.hands[actor as usize].clone();
+                if actor != target {
+                    let (_, id) = self
+                        .last_discard
+                        .ok_or_else(|| WriteError::NothingToCall(event.clone()))?;
+                    hai.push(id);
+                }
+                hai.sort_unstable();
+                let machi = if actor == target {
+                    self.last_draw[actor as usize].unwrap_or_default()
+                } else {
+                    self.last_discard.map_or(0, |(_, id)| id)
+                };
+
+                let mut attrs = vec![
+                    format!(r#"ba="{},{}""#, self.honba, self.kyotaku),
+                    format!(r#"hai="{}""#, join_ids(&hai)),
+                ];
+                let melds = &self.melds[actor as usize];
+                if !melds.is_empty() {
+                    let m = melds
+                        .iter()
+                        .rev()
+                        .map(|m| m.code.to_string())
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    attrs.push(format!(r#"m="{m}""#));
+                }
+                attrs.push(format!(r#"machi="{machi}""#));
+                if let Some(info) = info {
+                    attrs.extend(encode_hand_value(&info.value).unwrap_or_default());
+                }
+                attrs.push(format!(r#"doraHai="{}""#, join_ids(&self.dora_ids)));
+                if self.ura_ids.is_none() {
+                    if let Some(ura_markers) = ura_markers.as_ref().filter(|u| !u.is_empty()) {
+                        self.ura_ids = Some(
+                            ura_markers
+                                .iter()
+                                .map(|&t| alloc_id(&mut self.used, self.aka_flag, t))
+                                .collect::<Result<_>>()?,
+                        );
+                    }
+                }
+                if let Some(ura_ids) = &self.ura_ids {
+                    attrs.push(format!(r#"doraHaiUra="{}""#, join_ids(ura_ids)));
+                }
+                attrs.push(format!(r#"who="{actor}" fromWho="{target}""#));
+                attrs.push(format!(
+                    r#"sc="{}""#,
+                    self.settle(deltas.as_deref().unwrap_or_default())
+                ));
+                format!("<AGARI {}/>", attrs.join(" "))
+            }
+
+            Event::Ryukyoku {
+                ref deltas,
+                ref info,
+            } => {
+                let mut attrs = vec![];
+                if let Some(ty) = info.as_ref().and_then(|info| info.reason.mjlog_type()) {
+                    attrs.push(format!(r#"type="{ty}""#));
+                }
+                attrs.push(format!(r#"ba="{},{}""#, self.honba, self.kyotaku));
+                attrs.push(format!(
+                    r#"sc="{}""#,
+                    self.settle(deltas.as_deref().unwrap_or_default())
+                ));
+                if let Some(info) = info {
+                    for (actor, tehai) in info.tehais.iter().enumerate() {
+                        if tehai.is_some() {
+                            let mut hai = self.hands[actor].clone();
+                            hai.sort_unstable();
+                            attrs.push(format!(r#"hai{actor}="{}""#, join_ids(&hai)));
+                        }
+                    }
+                }
+                format!("<RYUUKYOKU {}/>", attrs.join(" "))
+            }
+
+            Event::None
+            | Event::StartGame { .. }
+            | Event::StartKyoku { .. }
+            | Event::EndKyoku
+            | Event::EndGame { .. } => String::new(),
+        };
+
+        Ok(tag)
+    }
+
+    /// Applies the deltas and returns the `sc` attribute.
+    fn settle(&mut self, deltas: &[i32]) -> String {
+        // The fourth seat is written with 0 in sanma.
+        let deltas: [i32; 4] = array::from_fn(|i| deltas.get(i).copied().unwrap_or(0));
+        let sc = self
+            .scores
+            .iter()
+            .zip(deltas)
+            .map(|(s, d)| format!("{},{}", s / 100, d / 100))
+            .collect::<Vec<_>>()
+            .join(",");
+        self.scores
+            .iter_mut()
+            .zip(deltas)
+            .for_each(|(s, d)| *s += d);
+        sc
+    }
+
+    fn push_meld(&mut self, actor: u8, code: u16, ids: Vec<u8>) -> String {
+        self.melds[actor as usize].push(Meld { code, ids });
+        format!(r#"<N who="{actor}" m="{code}"/>"#)
+    }
+
+    fn take_last_discard(&mut self, target: u8, event: &Event) -> Result<u8> {
+        match self.last_discard.take() {
+            Some((actor, id)) if actor == target => Ok(id),
+            _ => Err(WriteError::NothingToCall(event.clone())),
+        }
+    }
+
+    /// Removes a tile of the given kind from the hand, preferring `prefer` if
+    /// it matches, otherwise anything but the last drawn tile.
+    fn take_from_hand(&mut self, actor: u8, pai: Tile, prefer: Option<u8>) -> Result<u8> {
+        let aka_flag = self.aka_flag;
+        let last_draw = self.last_draw[actor as usize];
+        let hand = &mut self.hands[actor as usize];
+        let tiles = hand
+            .iter()
+            .map(|&id| id_to_tile(id, aka_flag))
+            .collect::<Result<Vec<_>>>()?;
+
+        let pos = prefer
+            .and_then(|id| hand.iter().position(|&h| h == id))
+            .filter(|&i| tiles[i] == pai)
+            .or_else(|| {
+                hand.iter()
+                    .zip(&tiles)
+                    .position(|(&h, &t)| t == pai && Some(h) != last_draw)
+            })
+            .or_else(|| tiles.iter().position(|&t| t == pai))
+            // See `alloc_id` for when a non-aka 5 takes copy 0.
+            .or_else(|| tiles.iter().position(|t| t.deaka() == pai))
+            .ok_or(WriteError::TileNotInHand { actor, pai })?;
+        Ok(hand.remove(pos))
+    }
+}
+
+/// Assigns a free 136-tile ID to the tile.
+///
+/// A non-aka 5 falls back to copy 0 when the other three are taken, which only
+/// happens in made-up logs and will be read back as aka.
+fn alloc_id(used: &mut [u8; 34], aka_flag: bool, tile: Tile) -> Result<u8> {
+    if tile.is_unknown() {
+        return Err(WriteError::UnknownTile);
+    }
+    let kind = tile.deaka().as_u8();
+    let mask = &mut used[kind as usize];
+
+    let has_aka = aka_flag && matches!(kind, tu8!(5m) | tu8!(5p) | tu8!(5s));
+    let copy = if tile.is_aka() {
+        (*mask & 1 == 0).then_some(0)
+    } else {
+        (u8::from(has_aka)..4)
+            .chain(0..u8::from(has_aka))
+            .find(|&c| *mask & (1 << c) == 0)
+    }
+    .ok_or(WriteError::TileExhausted(tile))?;
+
+    *mask |= 1 << copy;
+    Ok(kind * 4 + copy)
+}
+
+fn id_to_tile(id: u8, aka_flag: bool) -> Result<Tile> {
+    let tile = Tile::try_from(id / 4).map_err(|_| WriteError::InvalidTileId(id))?;
+    if aka_flag && (id & 3) == 0 {
+        Ok(tile.akaize())
+    } else {
+        Ok(tile)
+    }
+}
+
+/// Returns 3 for kamicha, 2 for toimen and 1 for shimocha, which is how mjlog
+/// encodes the target of a meld. In sanma, kamicha is 2 instead.
+#[inline]
+const fn relative_seat(actor: u8, target: u8, num_players: u8) -> u8 {
+    (target + num_players - actor) % num_players
+}
+
+/// The inverse of `parse_chi`.
+fn encode_chi(rel: u8, called: u8, consumed: [u8; 2]) -> u16 {
+    let mut ids = [called, consumed[0], consumed[1]];
+    ids.sort_unstable();
+    let called_idx = ids.iter().position(|&id| id == called).unwrap_or(0) as u16;
+
+    let kind = u16::from(ids[0] / 4);
+    let base = (kind / 9) * 7 + kind % 9;
+    ((base * 3 + called_idx) << 10)
+        | (u16::from(ids[0] % 4) << 3)
+        | (u16::from(ids[1] % 4) << 5)
+        | (u16::from(ids[2] % 4) << 7)
+        | (1 << 2)
+        | u16::from(rel)
+}
+
+/// The inverse of `parse_pon`.
+fn encode_pon(rel: u8, called: u8, consumed: [u8; 2]) -> u16 {
+    let mut ids = [called, consumed[0], consumed[1]];
+    ids.sort_unstable();
+    let called_idx = ids.iter().position(|&id| id == called).unwrap_or(0) as u16;
+    let unused = (0..4)
+        .find(|&c| ids.iter().all(|&id| id % 4 != c))
+        .unwrap_or(0);
+
+    let kind = u16::from(ids[0] / 4);
+    ((kind * 3 + called_idx) << 9) | (u16::from(unused) << 5) | (1 << 3) | u16::from(rel)
+}
+
+/// The inverse of `parse_kakan`, upgrading the code of a pon.
+const fn encode_kakan(pon_code: u16, added: u8) -> u16 {
+    (pon_code & 0xfe03) | (((added % 4) as u16) << 5) | (1 << 4)
+}
+
+/// The inverse of `parse_kan`, where `rel` is 0 for ankan.
+const fn encode_kan(rel: u8, id: u8) -> u16 {
+    ((id as u16) << 8) | rel as u16
+}
+
+/// The inverse of `parse_nukidora`.
+const fn encode_nukidora(id: u8) -> u16 {
+    ((id as u16) << 8) | (1 << 5)
+}
+
+fn join_ids(ids: &[u8]) -> String {
+    ids.iter().map(u8::to_string).collect::<Vec<_>>().join(",")
+}
+
+/// Writes `ten`, `yaku` and `yakuman` of AGARI, `None` if some yaku has no
+/// ID in mjlog, e.g. those named in English.
+fn encode_hand_value(value: &HandValue) -> Option<Vec<String>> {
+    let limit = match value.limit {
+        None => 0,
+        Some(Limit::Mangan) => 1,
+        Some(Limit::Haneman) => 2,
+        Some(Limit::Baiman) => 3,
+        Some(Limit::Sanbaiman) => 4,
+        Some(Limit::Yakuman) => 5,
+    };
+    let mut attrs = vec![format!(r#"ten="{},{},{limit}""#, value.fu, value.points)];
+
+    let mut yaku = vec![];
+    let mut yakuman = vec![];
+    for y in &value.yaku {
+        let id = YAKU_NAMES.iter().position(|&name| name == y.name)?;
+        if limit == 5 && y.han == 13 {
+            yakuman.push(id.to_string());
+        } else {
+            yaku.extend([id.to_string(), y.han.to_string()]);
+        }
+    }
+    if !yaku.is_empty() {
+        attrs.push(format!(r#"yaku="{}""#, yaku.join(",")));
+    }
+    if !yakuman.is_empty() {
+        attrs.push(format!(r#"yakuman="{}""#, yakuman.join(",")));
+    }
+    Some(attrs)
+}
+
+fn join_hundreds(scores: &[i32; 4]) -> String {
+    scores
+        .iter()
+        .map(|s| (s / 100).to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{parse_chi, parse_kakan, parse_kan, parse_pon, parse_str_with};
+    use super::*;
+    use crate::tenhou::Log;
+    use crate::tenhou_to_mjai_with;
+    use crate::testdata::{self, FOUR_PLAIN_FIVES};
+    use crate::ConvertOptions;
+
+    #[test]
+    fn meld_codes() {
+        // chi 3m with 1m 2m from kamicha
+        let code = encode_chi(3, 9, [1, 6]);
+        assert_eq!(parse_chi(code), vec![9, 1, 6]);
+
+        // pon 8p from toimen
+        let code = encode_pon(2, 64, [65, 67]);
+        assert_eq!(parse_pon(code), vec![64, 65, 67]);
+        let code = encode_pon(1, 67, [64, 65]);
+        assert_eq!(parse_pon(code), vec![67, 64, 65]);
+
+        // daiminkan 9s from shimocha
+        let code = encode_kan(1, 105);
+        assert_eq!(parse_kan(code), vec![105, 104, 106, 107]);
+        assert_eq!(code & 0x3, 1);
+        // ankan N
+        assert_eq!(parse_kan(encode_kan(0, 120)), vec![120, 121, 122, 123]);
+
+        // kakan 5mr onto a pon of plain 5m
+        let code = encode_kakan(encode_pon(3, 17, [18, 19]), 16);
+        assert_eq!(parse_kakan(code), vec![16, 17, 18, 19]);
+        let code = encode_kakan(encode_pon(1, 16, [17, 19]), 18);
+        assert_eq!(parse_kakan(code), vec![18, 16, 17, 19]);
+
+        // real codes taken from inst/mjlog
+        assert_eq!(encode_chi(3, 83, [79, 86]), 47_487);
+        assert_eq!(encode_chi(3, 48, [42, 45]), 26_679);
+        assert_eq!(encode_pon(3, 132, [133, 134]), 50_795);
+        assert_eq!(encode_pon(1, 125, [126, 127]), 47_625);
+        assert_eq!(encode_kakan(47_625, 124), 47_625 - (1 << 3) + (1 << 4));
+    }
+
+    /// Rewrites what mjlog cannot carry: `start_game` meta that mjlog
+    /// always records, the order of `consumed`, and hand values whose yaku
+    /// have no ID in mjlog.
+    fn comparable(events: &[Event]) -> Vec<Event> {
+        let mut events = events.to_vec();
+        for event in &mut events {
+            match event {
+                Event::StartGame {
+                    lobby_tier, meta, ..
+                } => {
+                    lobby_tier.get_or_insert(LobbyTier::Ippan);
+                    **meta = Default::default();
+                }
+                Event::Chi { consumed, .. } | Event::Pon { consumed, .. } => consumed.sort(),
+                Event::Daiminkan { consumed, .. } | Event::Kakan { consumed, .. } => {
+                    consumed.sort();
+                }
+                Event::Ankan { consumed, .. } => consumed.sort(),
+                Event::Hora { info, .. }
+                    if info
+                        .as_ref()
+                        .is_some_and(|info| encode_hand_value(&info.value).is_none()) =>
+                {
+                    *info = None;
+                }
+                _ => (),
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn round_trip() {
+        let options = ConvertOptions::normalized();
+        for (name, json_string) in testdata::tenhou_logs() {
+            let log = Log::from_json_str(&json_string).unwrap();
+            let events = tenhou_to_mjai_with(&log, &options).unwrap();
+
+            let xml = mjai_to_mjlog(&events).unwrap_or_else(|e| panic!("{name}: {e}"));
+            let parsed = parse_str_with(&xml, &options).unwrap_or_else(|e| panic!("{name}: {e}"));
+            let (expected, parsed) = (comparable(&events), comparable(&parsed));
+            // One of the four plain 5s is read back as aka. See `alloc_id`.
+            if name == FOUR_PLAIN_FIVES {
+                let deaka = |ev: &Event| serde_json::to_string(ev).unwrap().replace("r\"", "\"");
+                assert_eq!(
+                    expected.iter().map(deaka).collect::<Vec<_>>(),
+                    parsed.iter().map(deaka).collect::<Vec<_>>(),
+                );
+            } else {
+                assert_eq!(expected, parsed, "{name}");
+            }
+        }
+    }
+}
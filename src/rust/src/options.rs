@@ -0,0 +1,171 @@
+use crate::{Event, Tile};
+
+/// Options shared by [`tenhou_to_mjai_with`](crate::tenhou_to_mjai_with) and
+/// [`mjlog::parse_str_with`](crate::mjlog::parse_str_with).
+///
+/// The default keeps each source as recorded. [`ConvertOptions::normalized`]
+/// makes a game produce the same mjai from either source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConvertOptions {
+    /// Sort `tehais` of `start_kyoku`. mjlog lists haipai in the order dealt.
+    pub sort_tehais: bool,
+    pub tsumogiri: Tsumogiri,
+    pub ura_markers: UraMarkers,
+    /// Set `fu` of limit hands to 0. tenhou.net/6 does not record it.
+    pub limit_fu: bool,
+}
+
+/// How `tsumogiri` of `dahai` is decided.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Tsumogiri {
+    /// Only when the tile just drawn is discarded.
+    #[default]
+    Exact,
+    /// Whenever the discard is the same tile as the last draw of the actor,
+    /// even after melds. This is how mjlog used to be read.
+    SameTile,
+}
+
+/// Which `hora` events carry `ura_markers`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UraMarkers {
+    /// tenhou.net/6 reveals them for every hora, mjlog only where the
+    /// doraHaiUra attribute exists.
+    #[default]
+    AsRecorded,
+    /// Only for winners whose reach was accepted, `None` otherwise.
+    Reached,
+    /// For every hora, empty if nobody reveals them in the kyoku.
+    All,
+}
+
+impl ConvertOptions {
+    /// Options with which mjlog and tenhou.net/6 give identical mjai.
+    #[must_use]
+    pub const fn normalized() -> Self {
+        Self {
+            sort_tehais: true,
+            tsumogiri: Tsumogiri::Exact,
+            ura_markers: UraMarkers::All,
+            limit_fu: true,
+        }
+    }
+
+    /// Rewrites events converted with the default options according to the
+    /// options.
+    pub(crate) fn apply(self, events: &mut [Event]) {
+        let mut last_draw = [None; 4];
+        let mut reached = [false; 4];
+        let mut kyoku_ura: Option<Vec<Tile>> = None;
+
+        for i in 0..events.len() {
+            if let Event::Hora {
+                info: Some(info), ..
+            } = &mut events[i]
+            {
+                if self.limit_fu && info.value.limit.is_some() {
+                    info.value.fu = 0;
+                }
+            }
+            match &mut events[i] {
+                Event::StartKyoku { tehais, .. } => {
+                    if self.sort_tehais {
+                        for tehai in tehais.iter_mut() {
+                            tehai.sort_unstable();
+                        }
+                    }
+                    last_draw = [None; 4];
+                    reached = [false; 4];
+                    kyoku_ura = None;
+                }
+                &mut Event::Tsumo { actor, pai } => last_draw[actor as usize] = Some(pai),
+                Event::Dahai {
+                    actor,
+                    pai,
+                    tsumogiri,
+                } if self.tsumogiri == Tsumogiri::SameTile => {
+                    *tsumogiri = last_draw[*actor as usize] == Some(*pai);
+                }
+                &mut Event::ReachAccepted { actor } => reached[actor as usize] = true,
+                Event::Hora {
+                    actor, ura_markers, ..
+                } => match self.ura_markers {
+                    UraMarkers::AsRecorded => (),
+                    UraMarkers::Reached => {
+                        if !reached[*actor as usize] {
+                            *ura_markers = None;
+                        }
+                    }
+                    UraMarkers::All => {
+                        // On double ron only one of the winners may reveal them.
+                        let ura = kyoku_ura.get_or_insert_with(|| {
+                            events[i..]
+                                .iter()
+                                .take_while(|ev| !matches!(ev, Event::StartKyoku { .. }))
+                                .find_map(|ev| match ev {
+                                    Event::Hora {
+                                        ura_markers: Some(ura),
+                                        ..
+                                    } if !ura.is_empty() => Some(ura.clone()),
+                                    _ => None,
+                                })
+                                .unwrap_or_default()
+                        });
+                        let ura = Some(ura.clone());
+                        if let Event::Hora { ura_markers, .. } = &mut events[i] {
+                            *ura_markers = ura;
+                        }
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tenhou::Log;
+    use crate::{tenhou_to_mjai_with, testdata};
+
+    #[test]
+    fn options() {
+        let log = Log::from_json_str(&testdata::read("double_ron.json")).unwrap();
+        let exact = tenhou_to_mjai_with(&log, &ConvertOptions::default()).unwrap();
+        let options = ConvertOptions {
+            sort_tehais: true,
+            tsumogiri: Tsumogiri::SameTile,
+            ura_markers: UraMarkers::Reached,
+            limit_fu: false,
+        };
+        let events = tenhou_to_mjai_with(&log, &options).unwrap();
+        assert_eq!(exact.len(), events.len());
+
+        let mut reached = [false; 4];
+        for (a, b) in exact.iter().zip(&events) {
+            match (a, b) {
+                (Event::StartKyoku { .. }, Event::StartKyoku { tehais, .. }) => {
+                    assert!(tehais.iter().all(|t| t.is_sorted()));
+                    reached = [false; 4];
+                }
+                (&Event::ReachAccepted { actor }, _) => reached[actor as usize] = true,
+                (
+                    Event::Dahai {
+                        tsumogiri: true, ..
+                    },
+                    Event::Dahai { tsumogiri, .. },
+                ) => assert!(tsumogiri),
+                (
+                    Event::Hora { .. },
+                    Event::Hora {
+                        actor, ura_markers, ..
+                    },
+                ) => {
+                    assert_eq!(ura_markers.is_some(), reached[*actor as usize]);
+                }
+                _ => (),
+            }
+        }
+    }
+}
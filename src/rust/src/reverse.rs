@@ -0,0 +1,484 @@
+use crate::mjai::{Event, HandValue, Limit, RyukyokuInfo, RyukyokuReason};
+use crate::tenhou::{
+    encode_sc, ActionItem, KyokuMeta, RawKyoku, RawLog, ResultItem, Rule, TenhouTile,
+};
+use crate::{t, tu8, Tile};
+use std::{array, mem};
+
+use serde_json::Value;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ReverseError {
+    #[error("missing start_game event")]
+    MissingStartGame,
+
+    #[error("event outside of any kyoku: {0:?}")]
+    OutsideKyoku(Event),
+
+    #[error("invalid actor: {0:?}")]
+    InvalidActor(Event),
+
+    #[error("invalid bakaze: {0}")]
+    InvalidBakaze(Tile),
+
+    #[error("kakan without a preceding pon: actor {actor}, pai {pai}")]
+    KakanWithoutPon { actor: u8, pai: Tile },
+}
+
+pub type Result<T> = std::result::Result<T, ReverseError>;
+
+/// Regroups the events of one kyoku into per-seat action tables.
+#[derive(Debug)]
+pub(crate) struct KyokuBuilder {
+    meta: KyokuMeta,
+    num_players: u8,
+    scoreboard: [i32; 4],
+    dora_indicators: Vec<Tile>,
+    ura_indicators: Option<Vec<Tile>>,
+    haipais: Vec<[Tile; 13]>,
+    takes: [Vec<ActionItem>; 4],
+    discards: [Vec<ActionItem>; 4],
+    results: Vec<ResultItem>,
+
+    /// The pons of each actor with their targets, used for encoding kakans.
+    pons: [Vec<(Tile, u8)>; 4],
+    reach_declared: [bool; 4],
+}
+
+/// Transform a mjai format log into tenhou.net/6 format.
+///
+/// This is the inverse of [`tenhou_to_mjai`](crate::tenhou_to_mjai), so the
+/// returned log serializes to the JSON that tenhou.net/6 accepts.
+pub fn mjai_to_tenhou(events: &[Event]) -> Result<RawLog> {
+    let mut events = events.iter();
+
+    let (names, kyoku_first, aka_flag, lobby_tier, meta) = match events.next() {
+        Some(Event::StartGame {
+            names,
+            kyoku_first,
+            aka_flag,
+            lobby_tier,
+            meta,
+        }) => (
+            names.clone(),
+            *kyoku_first,
+            *aka_flag,
+            *lobby_tier,
+            (**meta).clone(),
+        ),
+        _ => return Err(ReverseError::MissingStartGame),
+    };
+
+    let num_players = names.len() as u8;
+    let mut logs = vec![];
+    let mut builder: Option<KyokuBuilder> = None;
+    let mut result = None;
+
+    for event in events {
+        if event.actor().is_some_and(|a| a >= num_players) {
+            return Err(ReverseError::InvalidActor(event.clone()));
+        }
+
+        match event {
+            Event::None => (),
+            Event::EndGame { info } => result = info.as_deref().map(encode_sc),
+
+            Event::StartKyoku { .. } => {
+                if let Some(b) = builder.take() {
+                    logs.push(b.finish());
+                }
+                builder = Some(KyokuBuilder::start(event)?);
+            }
+
+            Event::EndKyoku => {
+                if let Some(b) = builder.take() {
+                    logs.push(b.finish());
+                }
+            }
+
+            _ => {
+                let b = builder
+                    .as_mut()
+                    .ok_or_else(|| ReverseError::OutsideKyoku(event.clone()))?;
+                b.push(event)?;
+            }
+        }
+    }
+
+    if let Some(b) = builder.take() {
+        logs.push(b.finish());
+    }
+
+    let mut disp = String::from(if num_players == 3 { '三' } else { '四' });
+    if let Some(tier) = lobby_tier {
+        disp.push(tier.to_char());
+    }
+    disp.push(if kyoku_first == 4 { '東' } else { '南' });
+    disp.push('喰');
+    if aka_flag {
+        disp.push('赤');
+    }
+
+    // tenhou.net/6 always has four names, the last one is empty in sanma.
+    let mut names = names.into_iter();
+    let names = array::from_fn(|_| names.next().unwrap_or_default());
+
+    Ok(RawLog {
+        logs,
+        names,
+        rule: Rule {
+            disp,
+            aka: u8::from(aka_flag),
+            ..Default::default()
+        },
+        ratingc: meta.ratingc,
+        lobby: meta.lobby,
+        dan: meta.dan,
+        rate: meta.rate,
+        sx: meta.sx,
+        sc: result,
+    })
+}
+
+impl KyokuBuilder {
+    /// Starts a kyoku from a `StartKyoku` event.
+    pub(crate) fn start(event: &Event) -> Result<Self> {
+        let Event::StartKyoku {
+            bakaze,
+            dora_marker,
+            kyoku,
+            honba,
+            kyotaku,
+            ref scores,
+            ref tehais,
+            ..
+        } = *event
+        else {
+            return Err(ReverseError::OutsideKyoku(event.clone()));
+        };
+
+        let bakaze_num = match bakaze.as_u8() {
+            tu8!(E) => 0,
+            tu8!(S) => 1,
+            tu8!(W) => 2,
+            tu8!(N) => 3,
+            _ => return Err(ReverseError::InvalidBakaze(bakaze)),
+        };
+
+        Ok(Self {
+            meta: KyokuMeta {
+                kyoku_num: bakaze_num * 4 + kyoku.saturating_sub(1) % 4,
+                honba,
+                kyotaku,
+            },
+            num_players: tehais.len() as u8,
+            scoreboard: array::from_fn(|i| scores.get(i).copied().unwrap_or_default()),
+            dora_indicators: vec![dora_marker],
+            ura_indicators: None,
+            haipais: tehais.clone(),
+            takes: Default::default(),
+            discards: Default::default(),
+            results: vec![],
+            pons: Default::default(),
+            reach_declared: [false; 4],
+        })
+    }
+
+    pub(crate) fn push(&mut self, event: &Event) -> Result<()> {
+        match *event {
+            Event::Tsumo { actor, pai } => {
+                self.takes[actor as usize].push(ActionItem::Tile(pai));
+            }
+
+            Event::Dahai {
+                actor,
+                pai,
+                tsumogiri,
+            } => {
+                let discards = &mut self.discards[actor as usize];
+                if mem::take(&mut self.reach_declared[actor as usize]) {
+                    let id = if tsumogiri {
+                        60
+                    } else {
+                        TenhouTile::from(pai) as u8
+                    };
+                    discards.push(ActionItem::Naki(format!("r{id:02}")));
+                } else if tsumogiri {
+                    discards.push(ActionItem::Tsumogiri(60));
+                } else {
+                    discards.push(ActionItem::Tile(pai));
+                }
+            }
+
+            Event::Chi {
+                actor,
+                pai,
+                consumed,
+                ..
+            } => {
+                let naki = encode_naki('c', pai, &consumed, 0);
+                self.takes[actor as usize].push(ActionItem::Naki(naki));
+            }
+
+            Event::Pon {
+                actor,
+                target,
+                pai,
+                consumed,
+            } => {
+                let pos = naki_pos(actor, target, self.num_players);
+                let naki = encode_naki('p', pai, &consumed, pos);
+                self.takes[actor as usize].push(ActionItem::Naki(naki));
+                self.pons[actor as usize].push((pai.deaka(), target));
+            }
+
+            Event::Daiminkan {
+                actor,
+                target,
+                pai,
+                consumed,
+            } => {
+                let pos = match naki_pos(actor, target, self.num_players) {
+                    2 => 3,
+                    pos => pos,
+                };
+                let naki = encode_naki('m', pai, &consumed, pos);
+                self.takes[actor as usize].push(ActionItem::Naki(naki));
+                // tenhou.net/6 puts a placeholder in the discard table for
+                // every daiminkan.
+                self.discards[actor as usize].push(ActionItem::Tile(t!(?)));
+            }
+
+            Event::Kakan {
+                actor,
+                pai,
+                consumed,
+            } => {
+                let target = self.pons[actor as usize]
+                    .iter()
+                    .find(|&&(p, _)| p == pai.deaka())
+                    .map(|&(_, target)| target)
+                    .ok_or(ReverseError::KakanWithoutPon { actor, pai })?;
+                let pos = naki_pos(actor, target, self.num_players);
+                let naki = encode_naki('k', pai, &consumed, pos);
+                self.discards[actor as usize].push(ActionItem::Naki(naki));
+            }
+
+            Event::Ankan { actor, consumed } => {
+                let naki = encode_naki('a', consumed[3], &consumed[..3], 3);
+                self.discards[actor as usize].push(ActionItem::Naki(naki));
+            }
+
+            Event::Nukidora { actor, pai } => {
+                let naki = format!("f{:02}", TenhouTile::from(pai) as u8);
+                self.discards[actor as usize].push(ActionItem::Naki(naki));
+            }
+
+            Event::Dora { dora_marker } => {
+                self.dora_indicators.push(dora_marker);
+            }
+
+            Event::Reach { actor } => {
+                self.reach_declared[actor as usize] = true;
+            }
+
+            Event::Hora {
+                actor,
+                target,
+                ref deltas,
+                ref ura_markers,
+                ref info,
+            } => {
+                if self.results.is_empty() {
+                    self.results.push(ResultItem::Status("和了".to_owned()));
+                }
+                if self.ura_indicators.is_none() {
+                    self.ura_indicators.clone_from(ura_markers);
+                }
+                self.results
+                    .push(ResultItem::ScoreDeltas(pad_deltas(deltas.as_deref())));
+                let mut detail = vec![Value::from(actor), Value::from(target), Value::from(actor)];
+                if let Some(info) = info {
+                    detail.push(Value::from(self.point_text(
+                        &info.value,
+                        actor,
+                        target,
+                        deltas.as_deref(),
+                    )));
+                    detail.extend(info.value.yaku.iter().map(|yaku| {
+                        let han = if info.value.limit == Some(Limit::Yakuman) && yaku.han == 13 {
+                            "役満".to_owned()
+                        } else {
+                            format!("{}飜", yaku.han)
+                        };
+                        Value::from(format!("{}({han})", yaku.name))
+                    }));
+                }
+                self.results.push(ResultItem::HoraDetail(detail));
+            }
+
+            Event::Ryukyoku {
+                ref deltas,
+                ref info,
+            } => {
+                let status = match info.as_deref() {
+                    Some(RyukyokuInfo {
+                        reason: RyukyokuReason::Exhaustive,
+                        tenpai,
+                        ..
+                    }) if tenpai.iter().all(|&t| t) => "全員聴牌",
+                    Some(RyukyokuInfo {
+                        reason: RyukyokuReason::Exhaustive,
+                        tenpai,
+                        ..
+                    }) if !tenpai.contains(&true) => "全員不聴",
+                    Some(info) => info.reason.to_str(),
+                    None => "流局",
+                };
+                self.results.push(ResultItem::Status(status.to_owned()));
+                if let Some(deltas) = deltas {
+                    self.results
+                        .push(ResultItem::ScoreDeltas(pad_deltas(Some(deltas))));
+                }
+            }
+
+            Event::ReachAccepted { .. }
+            | Event::None
+            | Event::StartGame { .. }
+            | Event::StartKyoku { .. }
+            | Event::EndKyoku
+            | Event::EndGame { .. } => (),
+        };
+
+        Ok(())
+    }
+
+    /// Writes the point text, such as "30符1飜1000点" or "満貫2000-4000点".
+    ///
+    /// Tsumo payments are taken back from `deltas` as `value` only has the
+    /// total.
+    fn point_text(
+        &self,
+        value: &HandValue,
+        actor: u8,
+        target: u8,
+        deltas: Option<&[i32]>,
+    ) -> String {
+        let head = match value.limit {
+            Some(limit) => limit.to_str().to_owned(),
+            None => format!("{}符{}飜", value.fu, value.han),
+        };
+
+        let oya = self.meta.kyoku_num % 4;
+        let honba = i32::from(self.meta.honba) * 100;
+        let paid = |seat: u8| {
+            deltas
+                .and_then(|d| d.get(seat as usize))
+                .map(|d| -d - honba)
+        };
+        let ko = (0..self.num_players).find(|&s| s != actor && s != oya);
+        let payment = match (actor == target, actor == oya) {
+            (true, true) => ko.and_then(paid).map(|each| format!("{each}点∀")),
+            (true, false) => ko
+                .and_then(paid)
+                .zip(paid(oya))
+                .map(|(ko, oya)| format!("{ko}-{oya}点")),
+            _ => None,
+        };
+        let payment = payment.unwrap_or_else(|| format!("{}点", value.points));
+        format!("{head}{payment}")
+    }
+
+    pub(crate) fn finish(self) -> RawKyoku {
+        let mut haipais = self.haipais.into_iter().map(Vec::from);
+        let [haipai_0, haipai_1, haipai_2, haipai_3] =
+            array::from_fn(|_| haipais.next().unwrap_or_default());
+        let [takes_0, takes_1, takes_2, takes_3] = self.takes;
+        let [discards_0, discards_1, discards_2, discards_3] = self.discards;
+
+        RawKyoku {
+            meta: self.meta,
+            scoreboard: self.scoreboard,
+            dora_indicators: self.dora_indicators,
+            ura_indicators: self.ura_indicators.unwrap_or_default(),
+            haipai_0,
+            takes_0,
+            discards_0,
+            haipai_1,
+            takes_1,
+            discards_1,
+            haipai_2,
+            takes_2,
+            discards_2,
+            haipai_3,
+            takes_3,
+            discards_3,
+            results: self.results,
+        }
+    }
+}
+
+/// Writes `deltas` the way tenhou.net/6 does, four of them even in sanma.
+fn pad_deltas(deltas: Option<&[i32]>) -> [i32; 4] {
+    let deltas = deltas.unwrap_or_default();
+    array::from_fn(|i| deltas.get(i).copied().unwrap_or(0))
+}
+
+/// Returns where the called tile goes in a naki string: 0 for kamicha, 1 for
+/// toimen and 2 for shimocha.
+#[inline]
+const fn naki_pos(actor: u8, target: u8, num_players: u8) -> usize {
+    match (target + num_players - actor) % num_players {
+        1 => 2,
+        2 if num_players == 4 => 1,
+        _ => 0,
+    }
+}
+
+/// Encodes a naki in tenhou.net/6 style, where the marker and the called tile
+/// are inserted before the `pos`-th consumed tile, e.g. `"12p1212"` for a pon
+/// of 2m from toimen.
+fn encode_naki(marker: char, pai: Tile, consumed: &[Tile], pos: usize) -> String {
+    let mut parts: Vec<_> = consumed
+        .iter()
+        .map(|&tile| format!("{:02}", TenhouTile::from(tile) as u8))
+        .collect();
+    parts.insert(
+        pos.min(parts.len()),
+        format!("{marker}{:02}", TenhouTile::from(pai) as u8),
+    );
+    parts.concat()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tenhou::Log;
+    use crate::{tenhou_to_mjai, testdata};
+
+    #[test]
+    fn naki_string() {
+        assert_eq!(encode_naki('p', t!(2m), &t![2m, 2m], 1), "12p1212");
+        assert_eq!(encode_naki('m', t!(3m), &t![3m, 3m, 3m], 3), "131313m13");
+        assert_eq!(encode_naki('c', t!(7p), &t![5pr, 6p], 0), "c275226");
+        assert_eq!(encode_naki('a', t!(W), &t![W, W, W], 3), "434343a43");
+    }
+
+    #[test]
+    fn round_trip() {
+        for (name, json_string) in testdata::tenhou_logs() {
+            let log = Log::from_json_str(&json_string).unwrap();
+            let events = tenhou_to_mjai(&log).unwrap();
+
+            let raw_log = mjai_to_tenhou(&events).unwrap();
+            let json_string = serde_json::to_string(&raw_log).unwrap();
+            let log = Log::from_json_str(&json_string).unwrap();
+            assert_eq!(
+                tenhou_to_mjai(&log).unwrap(),
+                events,
+                "round trip failed for {name}",
+            );
+        }
+    }
+}
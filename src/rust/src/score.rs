@@ -0,0 +1,1201 @@
+//! Yaku, fu and points of winning hands under the rules of tenhou.
+//!
+//! Open tanyao, red fives and stacked yakuman count, while kiriage mangan and
+//! double yakuman for a single yaku do not. In sanma a tsumo is paid without
+//! the share of the missing seat.
+
+use crate::machi::WaitShape;
+use crate::mjai::{Event, HandValue, Limit, Yaku};
+use crate::shanten::to_counts;
+use crate::state::{GameState, StateError};
+use crate::{t, Tile};
+
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ScoreError {
+    #[error("the hand is not complete")]
+    NotComplete,
+
+    #[error("the hand has no yaku")]
+    NoYaku,
+
+    #[error("hora not following a tsumo, dahai or kan")]
+    NoWinningTile,
+
+    #[error("{0} is not a wind")]
+    NotAWind(Tile),
+
+    #[error("event {index}: {source}")]
+    Hora { index: usize, source: Box<Self> },
+
+    #[error(transparent)]
+    Replay(#[from] StateError),
+}
+
+pub type Result<T> = std::result::Result<T, ScoreError>;
+
+/// What is known about a win besides the tiles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conditions {
+    pub bakaze: Tile,
+    /// The seat wind of the winner, `E` for the oya.
+    pub jikaze: Tile,
+    pub tsumo: bool,
+    pub reach: bool,
+    /// Reach declared with the first discard before any call.
+    pub double_reach: bool,
+    pub ippatsu: bool,
+    /// Won with the last tile of the wall, 海底摸月 or 河底撈魚.
+    pub haitei: bool,
+    pub rinshan: bool,
+    pub chankan: bool,
+    /// Tsumo on the first draw before any call, 天和 or 地和.
+    pub first_draw: bool,
+    pub dora_markers: Vec<Tile>,
+    /// Only counted with reach.
+    pub ura_markers: Vec<Tile>,
+    pub nukidoras: u8,
+    /// The player who called the last set of 大三元 or 大四喜 from another
+    /// (責任払い).
+    pub pao: Option<u8>,
+    /// 3 for sanma.
+    pub num_players: u8,
+}
+
+impl Default for Conditions {
+    fn default() -> Self {
+        Self {
+            bakaze: t!(E),
+            jikaze: t!(E),
+            tsumo: false,
+            reach: false,
+            double_reach: false,
+            ippatsu: false,
+            haitei: false,
+            rinshan: false,
+            chankan: false,
+            first_draw: false,
+            dora_markers: vec![],
+            ura_markers: vec![],
+            nukidoras: 0,
+            pao: None,
+            num_players: 4,
+        }
+    }
+}
+
+/// Who wins from whom at which table, for [`Score::deltas`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settlement {
+    pub actor: u8,
+    /// The same as `actor` for tsumo.
+    pub target: u8,
+    pub oya: u8,
+    pub num_players: u8,
+    pub honba: u8,
+    pub kyotaku: u8,
+}
+
+/// How the points of a hora are paid, without honba and kyotaku.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Payment {
+    Ron(i32),
+    /// `oya` is 0 when the winner is the oya.
+    Tsumo {
+        ko: i32,
+        oya: i32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Score {
+    pub value: HandValue,
+    pub payment: Payment,
+    /// The player liable for the yakuman, from [`Conditions::pao`].
+    pub pao: Option<u8>,
+}
+
+impl Score {
+    /// Point changes of each seat, with honba and kyotaku going to the
+    /// winner.
+    #[must_use]
+    pub fn deltas(&self, settlement: Settlement) -> Vec<i32> {
+        let Settlement {
+            actor,
+            target,
+            oya,
+            num_players,
+            honba,
+            kyotaku,
+        } = settlement;
+        let mut deltas = vec![0; num_players as usize];
+        let honba = i32::from(honba) * 100;
+        // The liable player pays for one yakuman on behalf of the others.
+        let yakuman = i32::from(self.value.han / 13).max(1);
+        let liable = self.value.points / yakuman;
+
+        match self.payment {
+            Payment::Ron(points) => {
+                let mut paid = points + honba * 3;
+                if let Some(pao) = self.pao.filter(|&p| p != target) {
+                    deltas[pao as usize] -= liable / 2;
+                    paid -= liable / 2;
+                }
+                deltas[target as usize] -= paid;
+            }
+            Payment::Tsumo { ko, oya: oya_pays } => {
+                for seat in (0..num_players).filter(|&s| s != actor) {
+                    let share = if seat == oya { oya_pays } else { ko };
+                    deltas[seat as usize] -= match self.pao {
+                        Some(_) => share - share / yakuman,
+                        None => share + honba,
+                    };
+                }
+                if let Some(pao) = self.pao {
+                    deltas[pao as usize] -= liable + honba * i32::from(num_players - 1);
+                }
+            }
+        }
+
+        deltas[actor as usize] = -deltas.iter().sum::<i32>() + i32::from(kyotaku) * 1000;
+        deltas
+    }
+}
+
+/// Scores a winning hand.
+///
+/// `tehai` is the closed hand without the winning tile `pai`, `melds` are the
+/// `chi`, `pon`, `daiminkan`, `kakan` and `ankan` events of the winner. Of the
+/// ways the hand can be read, the one worth the most points is taken.
+pub fn score(tehai: &[Tile], melds: &[Event], pai: Tile, cond: &Conditions) -> Result<Score> {
+    for wind in [cond.bakaze, cond.jikaze] {
+        if !(t!(E).as_usize()..=t!(N).as_usize()).contains(&wind.as_usize()) {
+            return Err(ScoreError::NotAWind(wind));
+        }
+    }
+    let win = pai.deaka().as_usize();
+    let mut closed = to_counts(tehai);
+    closed[win] += 1;
+
+    let meld_blocks: Vec<_> = melds.iter().filter_map(Block::from_meld).collect();
+    let meld_tiles: Vec<_> = melds.iter().flat_map(meld_tiles).collect();
+    let mut all = closed;
+    for t in &meld_tiles {
+        all[t.deaka().as_usize()] += 1;
+    }
+    let aka = tehai
+        .iter()
+        .chain([&pai])
+        .chain(&meld_tiles)
+        .filter(|t| t.is_aka())
+        .count() as u8;
+
+    let hand = Hand {
+        cond,
+        win,
+        closed,
+        all,
+        aka,
+        menzen: meld_blocks.iter().all(|b| !b.open),
+        no_melds: melds.is_empty(),
+    };
+
+    let mut readings = vec![];
+    if hand.no_melds {
+        readings.extend(hand.kokushi());
+        readings.extend(hand.chiitoitsu());
+    }
+    let mut counts = closed;
+    let need = 4_usize.saturating_sub(meld_blocks.len());
+    for (pair, closed_blocks) in decompose(&mut counts, need) {
+        for (i, wait) in waits(&closed_blocks, pair, win) {
+            let mut blocks = meld_blocks.clone();
+            blocks.extend(closed_blocks.iter().enumerate().map(|(j, &b)| {
+                // A koutsu completed by ron is not concealed.
+                let concealed = !(Some(j) == i && wait == WaitShape::Shanpon && !cond.tsumo);
+                Block { concealed, ..b }
+            }));
+            readings.push(hand.standard(&blocks, pair, wait));
+        }
+    }
+    if readings.is_empty() {
+        return Err(ScoreError::NotComplete);
+    }
+
+    readings
+        .into_iter()
+        .filter_map(|(yaku, fu)| hand.finish(yaku, fu))
+        .max_by_key(|s| (s.value.points, s.value.han, s.value.fu))
+        .ok_or(ScoreError::NoYaku)
+}
+
+/// The score of a `hora` event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HoraScore {
+    /// Index of the `hora` in the events.
+    pub index: usize,
+    pub pai: Tile,
+    pub conditions: Conditions,
+    pub score: Score,
+    /// What the `hora` should carry as `deltas`.
+    pub deltas: Vec<i32>,
+}
+
+/// Replays `events` and scores each `hora` in them.
+///
+/// On double ron, honba and kyotaku go to the first winner.
+pub fn score_horas(events: &[Event]) -> Result<Vec<HoraScore>> {
+    let mut state = GameState::default();
+    let mut ret = vec![];
+    let mut kyoku = KyokuFlags::default();
+
+    for (index, event) in events.iter().enumerate() {
+        match *event {
+            Event::StartKyoku { honba, .. } => {
+                kyoku = KyokuFlags {
+                    honba,
+                    first_turn: [true; 4],
+                    ..Default::default()
+                };
+            }
+            Event::Hora {
+                actor,
+                target,
+                ref ura_markers,
+                ..
+            } => {
+                let hora = kyoku
+                    .score(&state, events, index, actor, target, ura_markers.as_deref())
+                    .map_err(|e| ScoreError::Hora {
+                        index,
+                        source: Box::new(e),
+                    })?;
+                ret.push(hora);
+                kyoku.honba = 0;
+            }
+            _ => kyoku.update(&state, event),
+        }
+        state.update(event)?;
+    }
+    Ok(ret)
+}
+
+/// What [`score_horas`] follows in a kyoku besides [`GameState`].
+#[derive(Debug, Default)]
+struct KyokuFlags {
+    honba: u8,
+    /// Nobody has called in the kyoku.
+    called: bool,
+    /// Before the first discard of each seat.
+    first_turn: [bool; 4],
+    double_reach: [bool; 4],
+    ippatsu: [bool; 4],
+    /// A kan or kita has been made and the next draw is from the dead wall.
+    rinshan_next: [bool; 4],
+    rinshan: [bool; 4],
+    pao: [Option<u8>; 4],
+}
+
+impl KyokuFlags {
+    fn update(&mut self, state: &GameState, event: &Event) {
+        // Left for the replay to report.
+        if event.actor().is_some_and(|a| a >= 4) {
+            return;
+        }
+        match *event {
+            Event::Tsumo { actor, .. } => {
+                let a = actor as usize;
+                self.rinshan[a] = std::mem::take(&mut self.rinshan_next[a]);
+            }
+            Event::Dahai { actor, .. } => {
+                let a = actor as usize;
+                self.first_turn[a] = false;
+                self.ippatsu[a] = false;
+                self.rinshan[a] = false;
+            }
+            Event::Reach { actor } => {
+                self.double_reach[actor as usize] = self.first_turn[actor as usize] && !self.called;
+            }
+            Event::ReachAccepted { actor } => self.ippatsu[actor as usize] = true,
+            Event::Chi { .. } | Event::Pon { .. } => {
+                self.called = true;
+                self.ippatsu = [false; 4];
+                self.check_pao(state, event);
+            }
+            Event::Daiminkan { actor, .. }
+            | Event::Kakan { actor, .. }
+            | Event::Ankan { actor, .. } => {
+                self.called = true;
+                self.ippatsu = [false; 4];
+                self.rinshan_next[actor as usize] = true;
+                self.check_pao(state, event);
+            }
+            Event::Nukidora { actor, .. } => self.rinshan_next[actor as usize] = true,
+            _ => (),
+        }
+    }
+
+    /// Sets pao when a pon or daiminkan completes the third dragon or the
+    /// fourth wind set of the actor.
+    fn check_pao(&mut self, state: &GameState, event: &Event) {
+        let (Event::Pon {
+            actor, target, pai, ..
+        }
+        | Event::Daiminkan {
+            actor, target, pai, ..
+        }) = *event
+        else {
+            return;
+        };
+        let Some(player) = state.players.get(actor as usize) else {
+            return;
+        };
+        let sets = |dragon: bool| {
+            player
+                .melds
+                .iter()
+                .filter_map(Block::from_meld)
+                .filter(|b| b.kind != Kind::Shuntsu && is_dragon(b.tile) == dragon && b.tile >= 27)
+                .count()
+        };
+        let pai = pai.as_usize();
+        // The state does not have the call yet.
+        if (is_dragon(pai) && sets(true) == 2) || (is_wind(pai) && sets(false) == 3) {
+            self.pao[actor as usize] = Some(target);
+        }
+    }
+
+    fn score(
+        &self,
+        state: &GameState,
+        events: &[Event],
+        index: usize,
+        actor: u8,
+        target: u8,
+        ura_markers: Option<&[Tile]>,
+    ) -> Result<HoraScore> {
+        let a = actor as usize;
+        let player = state.players.get(a).ok_or(ScoreError::NoWinningTile)?;
+        let num_players = state.players.len() as u8;
+
+        // Other winners of a double ron come in between.
+        let last = events[..index]
+            .iter()
+            .rev()
+            .find(|ev| !matches!(ev, Event::Hora { .. } | Event::Dora { .. }));
+        let (pai, chankan) = match last {
+            Some(&Event::Tsumo { pai, .. }) if actor == target => (pai, false),
+            Some(&Event::Dahai { pai, .. } | &Event::Nukidora { pai, .. }) => (pai, false),
+            Some(&Event::Kakan { pai, .. }) => (pai, true),
+            // Only kokushi can ron on an ankan.
+            Some(&Event::Ankan { consumed, .. }) => (consumed[0], false),
+            _ => return Err(ScoreError::NoWinningTile),
+        };
+
+        let tsumo = actor == target;
+        let mut tehai = player.tehai.clone();
+        if tsumo {
+            let idx = tehai
+                .iter()
+                .position(|&t| t == pai)
+                .ok_or(ScoreError::NoWinningTile)?;
+            tehai.remove(idx);
+        }
+
+        let seat = (actor + num_players - state.oya) % num_players;
+        let conditions = Conditions {
+            bakaze: state.bakaze,
+            jikaze: Tile::try_from(t!(E).as_usize() + seat as usize).unwrap_or_default(),
+            tsumo,
+            reach: player.reached,
+            double_reach: player.reached && self.double_reach[a],
+            ippatsu: player.reached && self.ippatsu[a],
+            haitei: state.tiles_left == 0 && !self.rinshan[a] && !chankan,
+            rinshan: tsumo && self.rinshan[a],
+            chankan,
+            first_draw: tsumo && self.first_turn[a] && !self.called,
+            dora_markers: state.dora_markers.clone(),
+            ura_markers: ura_markers.unwrap_or_default().to_vec(),
+            nukidoras: player.nukidoras,
+            pao: self.pao[a],
+            num_players,
+        };
+
+        let score = score(&tehai, &player.melds, pai, &conditions)?;
+        let deltas = score.deltas(Settlement {
+            actor,
+            target,
+            oya: state.oya,
+            num_players,
+            honba: self.honba,
+            kyotaku: state.kyotaku,
+        });
+        Ok(HoraScore {
+            index,
+            pai,
+            conditions,
+            score,
+            deltas,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Shuntsu,
+    Koutsu,
+    Kantsu,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Block {
+    kind: Kind,
+    /// The lowest tile, as an index of the 34 kinds.
+    tile: usize,
+    /// Made with a call from another player.
+    open: bool,
+    /// 暗刻 or 暗槓.
+    concealed: bool,
+}
+
+impl Block {
+    fn from_meld(event: &Event) -> Option<Self> {
+        let (kind, tile, open) = match *event {
+            Event::Chi { pai, consumed, .. } => {
+                let tile = consumed.iter().chain([&pai]).map(|t| t.deaka()).min()?;
+                (Kind::Shuntsu, tile, true)
+            }
+            Event::Pon { pai, .. } => (Kind::Koutsu, pai, true),
+            Event::Daiminkan { pai, .. } | Event::Kakan { pai, .. } => (Kind::Kantsu, pai, true),
+            Event::Ankan { consumed, .. } => (Kind::Kantsu, consumed[0], false),
+            _ => return None,
+        };
+        Some(Self {
+            kind,
+            tile: tile.deaka().as_usize(),
+            open,
+            concealed: !open,
+        })
+    }
+
+    const fn contains_yaokyuu(self) -> bool {
+        match self.kind {
+            Kind::Shuntsu => matches!(self.tile % 9, 0 | 6),
+            _ => is_yaokyuu(self.tile),
+        }
+    }
+}
+
+fn meld_tiles(event: &Event) -> Vec<Tile> {
+    match event {
+        Event::Chi { pai, consumed, .. } | Event::Pon { pai, consumed, .. } => {
+            std::iter::once(*pai).chain(*consumed).collect()
+        }
+        Event::Daiminkan { pai, consumed, .. } | Event::Kakan { pai, consumed, .. } => {
+            std::iter::once(*pai).chain(*consumed).collect()
+        }
+        Event::Ankan { consumed, .. } => consumed.to_vec(),
+        _ => vec![],
+    }
+}
+
+/// Splits closed tiles into a pair and `need` mentsu in every possible way.
+fn decompose(counts: &mut [u8; 34], need: usize) -> Vec<(usize, Vec<Block>)> {
+    let mut ret = vec![];
+    for pair in 0..34 {
+        if counts[pair] < 2 {
+            continue;
+        }
+        counts[pair] -= 2;
+        let mut blocks = Vec::with_capacity(need);
+        find_mentsu(counts, 0, &mut blocks, &mut |blocks| {
+            if blocks.len() == need {
+                ret.push((pair, blocks.to_vec()));
+            }
+        });
+        counts[pair] += 2;
+    }
+    ret
+}
+
+fn find_mentsu(
+    counts: &mut [u8; 34],
+    mut i: usize,
+    blocks: &mut Vec<Block>,
+    found: &mut dyn FnMut(&[Block]),
+) {
+    while i < 34 && counts[i] == 0 {
+        i += 1;
+    }
+    if i == 34 {
+        found(blocks);
+        return;
+    }
+
+    let closed = |kind, tile| Block {
+        kind,
+        tile,
+        open: false,
+        concealed: true,
+    };
+    if counts[i] >= 3 {
+        counts[i] -= 3;
+        blocks.push(closed(Kind::Koutsu, i));
+        find_mentsu(counts, i, blocks, found);
+        blocks.pop();
+        counts[i] += 3;
+    }
+    if i < 27 && i % 9 < 7 && counts[i + 1] > 0 && counts[i + 2] > 0 {
+        for c in &mut counts[i..i + 3] {
+            *c -= 1;
+        }
+        blocks.push(closed(Kind::Shuntsu, i));
+        find_mentsu(counts, i, blocks, found);
+        blocks.pop();
+        for c in &mut counts[i..i + 3] {
+            *c += 1;
+        }
+    }
+}
+
+/// Shapes in which `win` completes `closed`, which holds it, into a pair and
+/// `need` mentsu.
+pub(crate) fn wait_shapes(closed: &[u8; 34], need: usize, win: usize) -> Vec<WaitShape> {
+    let mut counts = *closed;
+    let mut shapes = vec![];
+    for (pair, blocks) in decompose(&mut counts, need) {
+        for (_, shape) in waits(&blocks, pair, win) {
+            if !shapes.contains(&shape) {
+                shapes.push(shape);
+            }
+        }
+    }
+    shapes
+}
+
+/// Ways the winning tile completes a reading, with the index of the closed
+/// block it completes, `None` for the pair.
+fn waits(blocks: &[Block], pair: usize, win: usize) -> Vec<(Option<usize>, WaitShape)> {
+    let mut ret = vec![];
+    if pair == win {
+        ret.push((None, WaitShape::Tanki));
+    }
+    for (i, b) in blocks.iter().enumerate() {
+        let wait = match b.kind {
+            Kind::Koutsu if b.tile == win => WaitShape::Shanpon,
+            Kind::Shuntsu if b.tile + 1 == win => WaitShape::Kanchan,
+            Kind::Shuntsu if b.tile == win && b.tile % 9 == 6 => WaitShape::Penchan,
+            Kind::Shuntsu if b.tile + 2 == win && b.tile % 9 == 0 => WaitShape::Penchan,
+            Kind::Shuntsu if b.tile == win || b.tile + 2 == win => WaitShape::Ryanmen,
+            _ => continue,
+        };
+        ret.push((Some(i), wait));
+    }
+    ret
+}
+
+const WINDS: [&str; 4] = ["東", "南", "西", "北"];
+const DRAGONS: [&str; 3] = ["白", "發", "中"];
+
+const fn is_yaokyuu(tile: usize) -> bool {
+    tile >= 27 || matches!(tile % 9, 0 | 8)
+}
+
+const fn is_wind(tile: usize) -> bool {
+    tile >= 27 && tile < 31
+}
+
+const fn is_dragon(tile: usize) -> bool {
+    tile >= 31 && tile < 34
+}
+
+fn yaku(name: &str, han: u8) -> Yaku {
+    Yaku {
+        name: name.to_owned(),
+        han,
+    }
+}
+
+/// A winning hand and its conditions, to be read in different ways.
+struct Hand<'a> {
+    cond: &'a Conditions,
+    win: usize,
+    /// The closed tiles with the winning tile.
+    closed: [u8; 34],
+    /// Every tile, those in melds included.
+    all: [u8; 34],
+    aka: u8,
+    /// No chi, pon, daiminkan or kakan.
+    menzen: bool,
+    no_melds: bool,
+}
+
+impl Hand<'_> {
+    fn kokushi(&self) -> Option<(Vec<Yaku>, u8)> {
+        let (kinds, pairs) = (0..34)
+            .filter(|&i| is_yaokyuu(i))
+            .fold((0, 0), |(k, p), i| {
+                (
+                    k + u8::from(self.closed[i] > 0),
+                    p + u8::from(self.closed[i] == 2),
+                )
+            });
+        if kinds != 13 || pairs != 1 {
+            return None;
+        }
+        let mut yakuman = self.first_draw_yakuman();
+        // Waiting on all 13 kinds.
+        if self.closed[self.win] == 2 {
+            yakuman.push(yaku("国士無双１３面", 13));
+        } else {
+            yakuman.push(yaku("国士無双", 13));
+        }
+        Some((yakuman, 0))
+    }
+
+    fn chiitoitsu(&self) -> Option<(Vec<Yaku>, u8)> {
+        if self.closed.iter().any(|&c| c != 0 && c != 2) {
+            return None;
+        }
+        let mut yakuman = self.first_draw_yakuman();
+        if self.all[..27].iter().all(|&c| c == 0) {
+            yakuman.push(yaku("字一色", 13));
+        }
+        if !yakuman.is_empty() {
+            return Some((yakuman, 25));
+        }
+
+        let mut yaku_list = self.situational();
+        self.push_tile_yaku(&mut yaku_list);
+        yaku_list.push(yaku("七対子", 2));
+        Some((yaku_list, 25))
+    }
+
+    /// Yaku and fu of four mentsu and a pair.
+    fn standard(&self, blocks: &[Block], pair: usize, wait: WaitShape) -> (Vec<Yaku>, u8) {
+        let cond = self.cond;
+        let count = |f: &dyn Fn(&Block) -> bool| blocks.iter().filter(|b| f(b)).count();
+        let sets = |f: &dyn Fn(usize) -> bool| count(&|b| b.kind != Kind::Shuntsu && f(b.tile));
+        let shuntsu = count(&|b| b.kind == Kind::Shuntsu);
+        let kantsu = count(&|b| b.kind == Kind::Kantsu);
+        let ankou = count(&|b| b.kind != Kind::Shuntsu && b.concealed);
+        let winds = sets(&is_wind);
+        let dragons = sets(&is_dragon);
+        let jikaze = cond.jikaze.as_usize();
+        let bakaze = cond.bakaze.as_usize();
+
+        let fu = self.fu(blocks, pair, wait);
+
+        let mut yakuman = self.first_draw_yakuman();
+        if ankou == 4 {
+            if wait == WaitShape::Tanki {
+                yakuman.push(yaku("四暗刻単騎", 13));
+            } else {
+                yakuman.push(yaku("四暗刻", 13));
+            }
+        }
+        if dragons == 3 {
+            yakuman.push(yaku("大三元", 13));
+        }
+        if winds == 4 {
+            yakuman.push(yaku("大四喜", 13));
+        } else if winds == 3 && is_wind(pair) {
+            yakuman.push(yaku("小四喜", 13));
+        }
+        if self.all[..27].iter().all(|&c| c == 0) {
+            yakuman.push(yaku("字一色", 13));
+        }
+        let green = [t!(2s), t!(3s), t!(4s), t!(6s), t!(8s), t!(F)].map(Tile::as_usize);
+        if (0..34).all(|i| self.all[i] == 0 || green.contains(&i)) {
+            yakuman.push(yaku("緑一色", 13));
+        }
+        if (0..34).all(|i| self.all[i] == 0 || (is_yaokyuu(i) && i < 27)) {
+            yakuman.push(yaku("清老頭", 13));
+        }
+        if kantsu == 4 {
+            yakuman.push(yaku("四槓子", 13));
+        }
+        if let Some(name) = self.chuuren() {
+            yakuman.push(yaku(name, 13));
+        }
+        if !yakuman.is_empty() {
+            return (yakuman, fu);
+        }
+
+        let pinfu = self.menzen
+            && shuntsu == 4
+            && wait == WaitShape::Ryanmen
+            && !is_dragon(pair)
+            && pair != jikaze
+            && pair != bakaze;
+
+        let mut yaku_list = self.situational();
+        if pinfu {
+            yaku_list.push(yaku("平和", 1));
+        }
+        self.push_tile_yaku(&mut yaku_list);
+
+        let mut peikou = 0;
+        if self.menzen {
+            let mut seen: Vec<usize> = vec![];
+            for b in blocks.iter().filter(|b| b.kind == Kind::Shuntsu) {
+                if let Some(pos) = seen.iter().position(|&t| t == b.tile) {
+                    seen.remove(pos);
+                    peikou += 1;
+                } else {
+                    seen.push(b.tile);
+                }
+            }
+        }
+        if peikou == 1 {
+            yaku_list.push(yaku("一盃口", 1));
+        }
+
+        for b in blocks.iter().filter(|b| b.kind != Kind::Shuntsu) {
+            if b.tile == jikaze {
+                yaku_list.push(yaku(&format!("自風 {}", WINDS[jikaze - 27]), 1));
+            }
+            if b.tile == bakaze {
+                yaku_list.push(yaku(&format!("場風 {}", WINDS[bakaze - 27]), 1));
+            }
+        }
+        for b in blocks
+            .iter()
+            .filter(|b| b.kind != Kind::Shuntsu && is_dragon(b.tile))
+        {
+            yaku_list.push(yaku(&format!("役牌 {}", DRAGONS[b.tile - 31]), 1));
+        }
+
+        let open_less = |han: u8| if self.menzen { han } else { han - 1 };
+        let all_yaokyuu = blocks.iter().all(|b| b.contains_yaokyuu()) && is_yaokyuu(pair);
+        let has_honor = pair >= 27 || blocks.iter().any(|b| b.tile >= 27);
+        if all_yaokyuu && shuntsu > 0 && has_honor {
+            yaku_list.push(yaku("混全帯幺九", open_less(2)));
+        }
+        let has_shuntsu = |tile: usize| {
+            blocks
+                .iter()
+                .any(|b| b.kind == Kind::Shuntsu && b.tile == tile)
+        };
+        if (0..3).any(|s| (0..3).all(|n| has_shuntsu(s * 9 + n * 3))) {
+            yaku_list.push(yaku("一気通貫", open_less(2)));
+        }
+        if (0..7).any(|n| (0..3).all(|s| has_shuntsu(s * 9 + n))) {
+            yaku_list.push(yaku("三色同順", open_less(2)));
+        }
+        let has_set = |tile: usize| {
+            blocks
+                .iter()
+                .any(|b| b.kind != Kind::Shuntsu && b.tile == tile)
+        };
+        if (0..9).any(|n| (0..3).all(|s| has_set(s * 9 + n))) {
+            yaku_list.push(yaku("三色同刻", 2));
+        }
+        if kantsu == 3 {
+            yaku_list.push(yaku("三槓子", 2));
+        }
+        if shuntsu == 0 {
+            yaku_list.push(yaku("対々和", 2));
+        }
+        if ankou == 3 {
+            yaku_list.push(yaku("三暗刻", 2));
+        }
+        if dragons == 2 && is_dragon(pair) {
+            yaku_list.push(yaku("小三元", 2));
+        }
+        if peikou == 2 {
+            yaku_list.push(yaku("二盃口", 3));
+        }
+        if all_yaokyuu && shuntsu > 0 && !has_honor {
+            yaku_list.push(yaku("純全帯幺九", open_less(3)));
+        }
+
+        (yaku_list, fu)
+    }
+
+    fn fu(&self, blocks: &[Block], pair: usize, wait: WaitShape) -> u8 {
+        let cond = self.cond;
+        let jikaze = cond.jikaze.as_usize();
+        let bakaze = cond.bakaze.as_usize();
+
+        let mut fu = 20;
+        for b in blocks.iter().filter(|b| b.kind != Kind::Shuntsu) {
+            let mut f = 2;
+            if is_yaokyuu(b.tile) {
+                f *= 2;
+            }
+            if b.concealed {
+                f *= 2;
+            }
+            if b.kind == Kind::Kantsu {
+                f *= 4;
+            }
+            fu += f;
+        }
+        // 連風牌 counts twice.
+        fu += 2 * (u8::from(is_dragon(pair)) + u8::from(pair == jikaze) + u8::from(pair == bakaze));
+        if matches!(
+            wait,
+            WaitShape::Kanchan | WaitShape::Penchan | WaitShape::Tanki
+        ) {
+            fu += 2;
+        }
+
+        if fu == 20 && self.menzen && blocks.iter().all(|b| b.kind == Kind::Shuntsu) {
+            // 平和
+            return if cond.tsumo { 20 } else { 30 };
+        }
+        if self.menzen && !cond.tsumo {
+            fu += 10;
+        }
+        if cond.tsumo {
+            fu += 2;
+        }
+        // An open hand with no fu is rounded up to 30.
+        fu.div_ceil(10).max(3) * 10
+    }
+
+    /// Yaku from how the hand is won.
+    fn situational(&self) -> Vec<Yaku> {
+        let cond = self.cond;
+        let mut ret = vec![];
+        if self.menzen && cond.tsumo {
+            ret.push(yaku("門前清自摸和", 1));
+        }
+        if cond.double_reach {
+            ret.push(yaku("両立直", 2));
+        } else if cond.reach {
+            ret.push(yaku("立直", 1));
+        }
+        if cond.ippatsu {
+            ret.push(yaku("一発", 1));
+        }
+        if cond.chankan {
+            ret.push(yaku("槍槓", 1));
+        }
+        if cond.rinshan {
+            ret.push(yaku("嶺上開花", 1));
+        }
+        if cond.haitei && cond.tsumo {
+            ret.push(yaku("海底摸月", 1));
+        }
+        if cond.haitei && !cond.tsumo {
+            ret.push(yaku("河底撈魚", 1));
+        }
+        ret
+    }
+
+    /// 断幺九, 混老頭, 混一色 and 清一色, which only depend on the tiles.
+    fn push_tile_yaku(&self, yaku_list: &mut Vec<Yaku>) {
+        let present = |i: usize| self.all[i] > 0;
+        let open_less = |han: u8| if self.menzen { han } else { han - 1 };
+        if (0..34).all(|i| !present(i) || !is_yaokyuu(i)) {
+            yaku_list.push(yaku("断幺九", 1));
+        }
+        // Without honors it is 清老頭, a yakuman.
+        let honors = (27..34).any(present);
+        if (0..34).all(|i| !present(i) || is_yaokyuu(i)) && honors {
+            yaku_list.push(yaku("混老頭", 2));
+        }
+        let suits = (0..3).filter(|&s| (s * 9..s * 9 + 9).any(present)).count();
+        if suits == 1 && honors {
+            yaku_list.push(yaku("混一色", open_less(3)));
+        } else if suits == 1 {
+            yaku_list.push(yaku("清一色", open_less(6)));
+        }
+    }
+
+    fn first_draw_yakuman(&self) -> Vec<Yaku> {
+        if !self.cond.first_draw {
+            vec![]
+        } else if self.cond.jikaze == t!(E) {
+            vec![yaku("天和", 13)]
+        } else {
+            vec![yaku("地和", 13)]
+        }
+    }
+
+    /// 九蓮宝燈 or 純正九蓮宝燈 when waiting on all nine tiles.
+    fn chuuren(&self) -> Option<&'static str> {
+        if !self.no_melds {
+            return None;
+        }
+        let suit = (0..3).find(|&s| self.closed[s * 9..s * 9 + 9].iter().sum::<u8>() == 14)?;
+        let counts = &self.closed[suit * 9..suit * 9 + 9];
+        let base = [3, 1, 1, 1, 1, 1, 1, 1, 3];
+        if counts.iter().zip(base).any(|(&c, b)| c < b) {
+            return None;
+        }
+        if counts[self.win - suit * 9] == base[self.win - suit * 9] + 1 {
+            Some("純正九蓮宝燈")
+        } else {
+            Some("九蓮宝燈")
+        }
+    }
+
+    /// Adds dora and works out the points, `None` if there is no yaku.
+    fn finish(&self, mut yaku_list: Vec<Yaku>, fu: u8) -> Option<Score> {
+        let cond = self.cond;
+        let yakuman = yaku_list.iter().filter(|y| y.han >= 13).count() as u8;
+        if yaku_list.is_empty() {
+            return None;
+        }
+
+        if yakuman == 0 {
+            let dora = |markers: &[Tile]| -> u8 {
+                markers
+                    .iter()
+                    .map(|&m| {
+                        let dora = dora_of(m, cond.num_players);
+                        let kita = if dora == t!(N) { cond.nukidoras } else { 0 };
+                        self.all[dora.as_usize()] + kita
+                    })
+                    .sum()
+            };
+            let dora_count = dora(&cond.dora_markers);
+            if dora_count > 0 {
+                yaku_list.push(yaku("ドラ", dora_count));
+            }
+            let ura = if cond.reach {
+                dora(&cond.ura_markers)
+            } else {
+                0
+            };
+            if ura > 0 {
+                yaku_list.push(yaku("裏ドラ", ura));
+            }
+            if self.aka > 0 {
+                yaku_list.push(yaku("赤ドラ", self.aka));
+            }
+            if cond.nukidoras > 0 {
+                yaku_list.push(yaku("抜きドラ", cond.nukidoras));
+            }
+        }
+
+        let han: u8 = yaku_list.iter().map(|y| y.han).sum();
+        let (base, limit) = if yakuman > 0 {
+            (8000 * i32::from(yakuman), Some(Limit::Yakuman))
+        } else {
+            match han {
+                13.. => (8000, Some(Limit::Yakuman)),
+                11..=12 => (6000, Some(Limit::Sanbaiman)),
+                8..=10 => (4000, Some(Limit::Baiman)),
+                6..=7 => (3000, Some(Limit::Haneman)),
+                5 => (2000, Some(Limit::Mangan)),
+                _ => {
+                    let base = i32::from(fu) << (han + 2);
+                    if base >= 2000 {
+                        (2000, Some(Limit::Mangan))
+                    } else {
+                        (base, None)
+                    }
+                }
+            }
+        };
+
+        let ceil100 = |x: i32| (x + 99) / 100 * 100;
+        let oya = cond.jikaze == t!(E);
+        let n = i32::from(cond.num_players);
+        let (payment, points) = match (cond.tsumo, oya) {
+            (false, true) => (Payment::Ron(ceil100(base * 6)), ceil100(base * 6)),
+            (false, false) => (Payment::Ron(ceil100(base * 4)), ceil100(base * 4)),
+            (true, true) => {
+                let ko = ceil100(base * 2);
+                (Payment::Tsumo { ko, oya: 0 }, ko * (n - 1))
+            }
+            (true, false) => {
+                let (ko, oya) = (ceil100(base), ceil100(base * 2));
+                (Payment::Tsumo { ko, oya }, ko * (n - 2) + oya)
+            }
+        };
+
+        let liable = ["大三元", "大四喜"];
+        let pao = cond
+            .pao
+            .filter(|_| yaku_list.iter().any(|y| liable.contains(&y.name.as_str())));
+
+        Some(Score {
+            value: HandValue {
+                fu,
+                han,
+                points,
+                limit,
+                yaku: yaku_list,
+            },
+            payment,
+            pao,
+        })
+    }
+}
+
+/// The dora indicated by `marker`, 9m after 1m in sanma.
+fn dora_of(marker: Tile, num_players: u8) -> Tile {
+    let marker = marker.deaka();
+    if num_players == 3 && marker == t!(1m) {
+        t!(9m)
+    } else {
+        marker.next()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tenhou::Log;
+    use crate::testdata::{self, KYOTAKU_LEFT_OUT, NOT_A_HAND};
+    use crate::{mjlog, tenhou_to_mjai};
+
+    fn tiles(s: &str) -> Vec<Tile> {
+        s.split_whitespace().map(|t| t.parse().unwrap()).collect()
+    }
+
+    /// Checks every hora against what the log records.
+    fn check(events: &[Event], name: &str) {
+        let horas = score_horas(events).unwrap_or_else(|e| panic!("{name}: {e}"));
+        for hora in horas {
+            let Event::Hora {
+                actor,
+                deltas,
+                info,
+                ..
+            } = &events[hora.index]
+            else {
+                unreachable!();
+            };
+            let value = &hora.score.value;
+            if let Some(mut deltas) = deltas.clone() {
+                let mut expected = hora.deltas;
+                if KYOTAKU_LEFT_OUT.contains(&name) {
+                    expected[*actor as usize] = 0;
+                    deltas[*actor as usize] = 0;
+                }
+                assert_eq!(expected, deltas, "{name}: {value:?}");
+            }
+            let Some(info) = info else {
+                continue;
+            };
+            let recorded = &info.value;
+            assert_eq!(value.points, recorded.points, "{name}: {value:?}");
+            assert_eq!(value.han, recorded.han, "{name}: {value:?}");
+            assert_eq!(value.limit, recorded.limit, "{name}: {value:?}");
+            if recorded.fu != 0 {
+                assert_eq!(value.fu, recorded.fu, "{name}: {value:?}");
+            }
+            // mjlog lists dora of 0 han, and some logs are in English.
+            let mut yaku: Vec<_> = value.yaku.iter().map(|y| (&y.name, y.han)).collect();
+            let mut expected: Vec<_> = recorded
+                .yaku
+                .iter()
+                .filter(|y| y.han > 0)
+                .map(|y| (&y.name, y.han))
+                .collect();
+            if expected.iter().all(|(name, _)| !name.is_ascii()) {
+                yaku.sort_unstable();
+                expected.sort_unstable();
+                assert_eq!(yaku, expected, "{name}");
+            }
+        }
+    }
+
+    #[test]
+    fn hands() {
+        let ron = Conditions::default();
+        let s = score(&tiles("1m 9m 1p 9p 1s 9s E S W N P F C"), &[], t!(C), &ron).unwrap();
+        assert_eq!(s.value.yaku, [yaku("国士無双１３面", 13)]);
+        assert_eq!(s.payment, Payment::Ron(48000));
+
+        let tsumo = Conditions {
+            jikaze: t!(S),
+            tsumo: true,
+            ..Default::default()
+        };
+        let tehai = tiles("2m 3m 4m 5p 6p 7p 3s 4s 6s 7s 8s 9m 9m");
+        let s = score(&tehai, &[], t!(5s), &tsumo).unwrap();
+        assert_eq!((s.value.fu, s.value.han), (20, 2));
+        assert_eq!(s.payment, Payment::Tsumo { ko: 400, oya: 700 });
+        let settlement = Settlement {
+            actor: 1,
+            target: 1,
+            oya: 0,
+            num_players: 4,
+            honba: 1,
+            kyotaku: 1,
+        };
+        assert_eq!(s.deltas(settlement), [-800, 2800, -500, -500]);
+
+        // 二盃口 is worth more than 七対子.
+        let tehai = tiles("1p 1p 2p 2p 3p 3p 6s 6s 7s 7s 8s 8s N");
+        let s = score(&tehai, &[], t!(N), &ron).unwrap();
+        assert_eq!(s.value.fu, 40);
+        assert!(s.value.yaku.contains(&yaku("二盃口", 3)));
+
+        let pon = |pai: &str| Event::Pon {
+            actor: 0,
+            target: 2,
+            pai: pai.parse().unwrap(),
+            consumed: [pai.parse().unwrap(); 2],
+        };
+        let melds = [pon("P"), pon("F"), pon("C")];
+        let cond = Conditions {
+            pao: Some(2),
+            ..Default::default()
+        };
+        let s = score(&tiles("1m 1m 2m 3m"), &melds, t!(1m), &cond).unwrap();
+        assert_eq!(s.value.yaku, [yaku("大三元", 13)]);
+        let settlement = Settlement {
+            actor: 0,
+            target: 3,
+            honba: 0,
+            kyotaku: 0,
+            ..settlement
+        };
+        assert_eq!(s.deltas(settlement), [48000, 0, -24000, -24000]);
+
+        // More melds than a hand can have are read as four.
+        let melds = [pon("P"), pon("F"), pon("C"), pon("E"), pon("S")];
+        let err = score(&[], &melds, t!(1m), &cond);
+        assert_eq!(err, Err(ScoreError::NotComplete));
+
+        let err = score(
+            &tiles("1m 2m 4m 5m 6m 7p 8p 9p 2s 3s 4s E E"),
+            &[],
+            t!(3m),
+            &ron,
+        );
+        assert_eq!(err, Err(ScoreError::NoYaku));
+        let err = score(
+            &tiles("1m 2m 4m 5m 6m 7p 8p 9p 2s 3s 4s E E"),
+            &[],
+            t!(7m),
+            &ron,
+        );
+        assert_eq!(err, Err(ScoreError::NotComplete));
+
+        let cond = Conditions {
+            bakaze: t!(P),
+            ..Default::default()
+        };
+        let err = score(
+            &tiles("1m 1m 1m 2m 3m 4m 5m 6m 7m 8m 9m 9m 9m"),
+            &[],
+            t!(5m),
+            &cond,
+        );
+        assert_eq!(err, Err(ScoreError::NotAWind(t!(P))));
+    }
+
+    #[test]
+    fn archives() {
+        for (name, json) in testdata::tenhou_logs() {
+            if name == NOT_A_HAND {
+                continue;
+            }
+            let log = Log::from_json_str(&json).unwrap();
+            check(&tenhou_to_mjai(&log).unwrap(), &name);
+        }
+        for (name, xml) in testdata::mjlogs() {
+            check(&mjlog::parse_str(&xml).unwrap(), &name);
+        }
+    }
+}
@@ -0,0 +1,298 @@
+//! Shanten numbers and effective tiles (ukeire) of hands.
+//!
+//! Hands are given as counts of each of the 34 tile kinds, see [`to_counts`].
+//! `num_melds` is the number of chi, pon and kan already made, each of which
+//! takes the place of a mentsu.
+
+use crate::{t, Tile};
+
+/// Counts each tile kind in `tiles`, red fives as normal fives. Unknown tiles
+/// are left out.
+#[must_use]
+pub fn to_counts(tiles: &[Tile]) -> [u8; 34] {
+    let mut counts = [0; 34];
+    for t in tiles {
+        if let Some(c) = counts.get_mut(t.deaka().as_usize()) {
+            *c += 1;
+        }
+    }
+    counts
+}
+
+/// The smallest of the standard, chiitoitsu and kokushi shanten numbers, -1
+/// for a complete hand. The latter two only count for closed hands.
+#[must_use]
+pub fn shanten(counts: &[u8; 34], num_melds: u8) -> i8 {
+    let standard = standard_shanten(counts, num_melds);
+    if num_melds > 0 {
+        return standard;
+    }
+    standard
+        .min(chiitoitsu_shanten(counts))
+        .min(kokushi_shanten(counts))
+}
+
+/// The shanten number of four mentsu and a pair.
+///
+/// Like most calculators, a hand waiting only on a tile it holds all four of
+/// is still counted as tenpai.
+#[must_use]
+pub fn standard_shanten(counts: &[u8; 34], num_melds: u8) -> i8 {
+    let mut groups: [Vec<(u8, u8)>; 4] = Default::default();
+    for (i, group) in groups.iter_mut().enumerate() {
+        *group = group_blocks(counts, i);
+    }
+
+    let mut best = best_shanten(&groups, num_melds, false);
+    for (i, &c) in counts.iter().enumerate() {
+        if c < 2 {
+            continue;
+        }
+        let mut counts = *counts;
+        counts[i] -= 2;
+        let g = i / 9;
+        let mut with_pair = groups.clone();
+        with_pair[g] = group_blocks(&counts, g);
+        best = best.min(best_shanten(&with_pair, num_melds, true));
+    }
+    best
+}
+
+/// The shanten number of seven distinct pairs.
+#[must_use]
+pub fn chiitoitsu_shanten(counts: &[u8; 34]) -> i8 {
+    let pairs = counts.iter().filter(|&&c| c >= 2).count() as i8;
+    let kinds = counts.iter().filter(|&&c| c >= 1).count() as i8;
+    6 - pairs + (7 - kinds).max(0)
+}
+
+/// The shanten number of kokushi musou.
+#[must_use]
+pub fn kokushi_shanten(counts: &[u8; 34]) -> i8 {
+    let yaokyuu = (0..34_u8)
+        .filter_map(|i| Tile::try_from(i).ok())
+        .filter(|t| t.is_yaokyuu())
+        .map(|t| counts[t.as_usize()]);
+    let (kinds, has_pair) = yaokyuu.fold((0, false), |(kinds, has_pair), c| {
+        (kinds + i8::from(c > 0), has_pair || c >= 2)
+    });
+    13 - kinds - i8::from(has_pair)
+}
+
+/// Tiles which lower the shanten number when drawn, and how many of them are
+/// left unseen by the hand.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ukeire {
+    pub tiles: Vec<Tile>,
+    pub count: u8,
+}
+
+/// The effective tiles of a hand of 13 tiles less three for each meld.
+#[must_use]
+pub fn ukeire(counts: &[u8; 34], num_melds: u8) -> Ukeire {
+    let current = shanten(counts, num_melds);
+    let mut ret = Ukeire::default();
+    for i in 0..34 {
+        if counts[i] >= 4 {
+            continue;
+        }
+        let mut drawn = *counts;
+        drawn[i] += 1;
+        if shanten(&drawn, num_melds) < current {
+            ret.tiles.push(Tile::try_from(i).unwrap_or_default());
+            ret.count += 4 - counts[i];
+        }
+    }
+    ret
+}
+
+/// What a hand would be after discarding `pai`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscardCandidate {
+    pub pai: Tile,
+    pub shanten: i8,
+    pub ukeire: Ukeire,
+}
+
+/// The shanten number and effective tiles after each possible discard from a
+/// hand of 14 tiles less three for each meld, best first.
+#[must_use]
+pub fn discard_candidates(counts: &[u8; 34], num_melds: u8) -> Vec<DiscardCandidate> {
+    let mut candidates: Vec<_> = (0..34)
+        .filter(|&i| counts[i] > 0)
+        .map(|i| {
+            let mut discarded = *counts;
+            discarded[i] -= 1;
+            DiscardCandidate {
+                pai: Tile::try_from(i).unwrap_or_default(),
+                shanten: shanten(&discarded, num_melds),
+                ukeire: ukeire(&discarded, num_melds),
+            }
+        })
+        .collect();
+    candidates.sort_by_key(|c| (c.shanten, u8::MAX - c.ukeire.count));
+    candidates
+}
+
+/// Best (mentsu, taatsu) combinations of one group: manzu, pinzu, souzu or
+/// honors.
+fn group_blocks(counts: &[u8; 34], group: usize) -> Vec<(u8, u8)> {
+    if group == 3 {
+        let honors = &counts[t!(E).as_usize()..];
+        let blocks = honors.iter().fold((0, 0), |(m, t), &c| match c {
+            3.. => (m + 1, t),
+            2 => (m, t + 1),
+            _ => (m, t),
+        });
+        return vec![blocks];
+    }
+
+    let mut suit = [0; 9];
+    suit.copy_from_slice(&counts[group * 9..group * 9 + 9]);
+    let mut found = vec![];
+    search_suit(&mut suit, 0, 0, 0, &mut found);
+
+    // Only keep combinations that are not worse in both ways than another.
+    found.sort_unstable_by(|a, b| b.cmp(a));
+    found.dedup();
+    let mut frontier: Vec<(u8, u8)> = vec![];
+    for (m, t) in found {
+        if frontier.iter().all(|&(fm, ft)| ft < t || fm < m) {
+            frontier.push((m, t));
+        }
+    }
+    frontier
+}
+
+fn search_suit(c: &mut [u8; 9], mut i: usize, m: u8, t: u8, found: &mut Vec<(u8, u8)>) {
+    while i < 9 && c[i] == 0 {
+        i += 1;
+    }
+    if i == 9 {
+        found.push((m, t));
+        return;
+    }
+
+    if c[i] >= 3 {
+        c[i] -= 3;
+        search_suit(c, i, m + 1, t, found);
+        c[i] += 3;
+    }
+    if i < 7 && c[i + 1] > 0 && c[i + 2] > 0 {
+        c[i] -= 1;
+        c[i + 1] -= 1;
+        c[i + 2] -= 1;
+        search_suit(c, i, m + 1, t, found);
+        c[i] += 1;
+        c[i + 1] += 1;
+        c[i + 2] += 1;
+    }
+    if c[i] >= 2 {
+        c[i] -= 2;
+        search_suit(c, i, m, t + 1, found);
+        c[i] += 2;
+    }
+    for gap in [1, 2] {
+        if i + gap < 9 && c[i + gap] > 0 {
+            c[i] -= 1;
+            c[i + gap] -= 1;
+            search_suit(c, i, m, t + 1, found);
+            c[i] += 1;
+            c[i + gap] += 1;
+        }
+    }
+    // Leave one as an isolated tile.
+    c[i] -= 1;
+    search_suit(c, i, m, t, found);
+    c[i] += 1;
+}
+
+fn best_shanten(groups: &[Vec<(u8, u8)>; 4], num_melds: u8, has_pair: bool) -> i8 {
+    let mut combined = vec![(num_melds, 0)];
+    for group in groups {
+        let mut next = Vec::with_capacity(combined.len() * group.len());
+        for &(m, t) in &combined {
+            for &(gm, gt) in group {
+                next.push((m + gm, t + gt));
+            }
+        }
+        combined = next;
+    }
+
+    combined
+        .into_iter()
+        .map(|(m, t)| {
+            let m = m.min(4);
+            let t = t.min(4 - m);
+            8 - 2 * m as i8 - t as i8 - i8::from(has_pair)
+        })
+        .min()
+        .unwrap_or(8)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Reads hands written like "123m456p789s1122z", where 1z-7z are E S W N
+    /// P F C.
+    fn hand(s: &str) -> [u8; 34] {
+        let mut counts = [0; 34];
+        let mut nums = vec![];
+        for ch in s.chars() {
+            match ch {
+                '0'..='9' => nums.push(ch as usize - '0' as usize),
+                _ => {
+                    let base = match ch {
+                        'm' => 0,
+                        'p' => 9,
+                        's' => 18,
+                        _ => 27,
+                    };
+                    for n in nums.drain(..) {
+                        counts[base + n - 1] += 1;
+                    }
+                }
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn shanten_numbers() {
+        assert_eq!(shanten(&hand("123m456p789s1122z"), 0), 0);
+        assert_eq!(shanten(&hand("123m456p789s11222z"), 0), -1);
+        assert_eq!(shanten(&hand("1133557799m11p1z"), 0), 0);
+        assert_eq!(standard_shanten(&hand("1133557799m11p1z"), 0), 3);
+        assert_eq!(shanten(&hand("19m19p19s1234567z"), 0), 0);
+        assert_eq!(shanten(&hand("19m19p19s11234567z"), 0), -1);
+        assert_eq!(shanten(&hand("147m258p369s1234z"), 0), 6);
+        assert_eq!(standard_shanten(&hand("147m258p369s1234z"), 0), 8);
+        assert_eq!(shanten(&hand("123m45p11z"), 2), 0);
+        assert_eq!(shanten(&hand("11z"), 4), -1);
+        assert_eq!(shanten(&hand("1z"), 4), 0);
+        // Five blocks do not count more than four.
+        assert_eq!(shanten(&hand("12m45m78m12p457p9s1z"), 0), 4);
+    }
+
+    #[test]
+    fn effective_tiles() {
+        let u = ukeire(&hand("123m456p789s1122z"), 0);
+        assert_eq!(u.tiles, [t!(E), t!(S)]);
+        assert_eq!(u.count, 4);
+
+        let u = ukeire(&hand("19m19p19s1234567z"), 0);
+        assert_eq!(u.tiles.len(), 13);
+        assert_eq!(u.count, 39);
+
+        let u = ukeire(&hand("123m45p11z"), 2);
+        assert_eq!(u.tiles, [t!(3p), t!(6p)]);
+        assert_eq!(u.count, 8);
+
+        let candidates = discard_candidates(&hand("123m456p789s11223z"), 0);
+        assert_eq!(candidates[0].pai, t!(W));
+        assert_eq!(candidates[0].shanten, 0);
+        assert_eq!(candidates[0].ukeire.count, 4);
+        assert!(candidates[1..].iter().all(|c| c.shanten > 0));
+    }
+}
@@ -0,0 +1,393 @@
+//! Replays mjai events to follow the state of a game.
+
+use crate::mjai::Event;
+use crate::{shanten, Tile};
+
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum StateError {
+    #[error("event outside of any kyoku: {0:?}")]
+    OutsideKyoku(Event),
+
+    #[error("invalid actor: {0:?}")]
+    InvalidActor(Event),
+
+    #[error("{pai} is not in the hand of actor {actor}")]
+    TileNotInHand { actor: u8, pai: Tile },
+
+    #[error("call of a tile which is not the last discard of the target: {0:?}")]
+    InvalidCall(Event),
+
+    #[error("kakan without a preceding pon: actor {actor}, pai {pai}")]
+    KakanWithoutPon { actor: u8, pai: Tile },
+
+    #[error("tsumo from an exhausted wall: {0:?}")]
+    WallExhausted(Event),
+
+    #[error("event index {index} is out of {len} events")]
+    IndexOutOfRange { index: usize, len: usize },
+}
+
+pub type Result<T> = std::result::Result<T, StateError>;
+
+/// The state of a game after some events.
+///
+/// The state is kept after `end_kyoku` until the next `start_kyoku`, so the
+/// hands at the end of a kyoku can still be seen.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GameState {
+    pub bakaze: Tile,
+    /// Counts from 1.
+    pub kyoku: u8,
+    pub honba: u8,
+    pub kyotaku: u8,
+    pub oya: u8,
+    pub scores: Vec<i32>,
+    pub dora_markers: Vec<Tile>,
+    /// Tiles left to draw in the wall, rinshan draws included.
+    pub tiles_left: u8,
+    /// One for each seat, three in sanma.
+    pub players: Vec<PlayerState>,
+    in_kyoku: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlayerState {
+    /// The closed hand, sorted.
+    pub tehai: Vec<Tile>,
+    /// `chi`, `pon`, `daiminkan`, `kakan` and `ankan` events in the order of
+    /// calls. A `kakan` takes the place of its `pon`.
+    pub melds: Vec<Event>,
+    pub river: Vec<Discard>,
+    /// Kita declared in sanma.
+    pub nukidoras: u8,
+    /// Whether the reach has been accepted.
+    pub reached: bool,
+    reach_declared: bool,
+}
+
+/// A tile in a river.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Discard {
+    pub pai: Tile,
+    pub tsumogiri: bool,
+    /// Discarded with a reach declaration.
+    pub reach: bool,
+    /// Taken by another player with chi, pon or daiminkan.
+    pub called: bool,
+}
+
+impl GameState {
+    /// Replays `events` up to and including the one at `index`.
+    pub fn at(events: &[Event], index: usize) -> Result<Self> {
+        let events = events.get(..=index).ok_or(StateError::IndexOutOfRange {
+            index,
+            len: events.len(),
+        })?;
+        let mut state = Self::default();
+        for event in events {
+            state.update(event)?;
+        }
+        Ok(state)
+    }
+
+    /// Applies one event.
+    pub fn update(&mut self, event: &Event) -> Result<()> {
+        match event {
+            Event::None | Event::StartGame { .. } | Event::EndGame { .. } => return Ok(()),
+            Event::StartKyoku {
+                bakaze,
+                dora_marker,
+                kyoku,
+                honba,
+                kyotaku,
+                oya,
+                scores,
+                tehais,
+            } => {
+                let num_players = tehais.len() as u8;
+                // Sanma goes without 2m-8m.
+                let wall = if num_players == 3 { 108 } else { 136 };
+                *self = Self {
+                    bakaze: *bakaze,
+                    kyoku: *kyoku,
+                    honba: *honba,
+                    kyotaku: *kyotaku,
+                    oya: *oya,
+                    scores: scores.clone(),
+                    dora_markers: vec![*dora_marker],
+                    // The dead wall keeps 14 tiles.
+                    tiles_left: wall - 14 - 13 * num_players,
+                    players: tehais
+                        .iter()
+                        .map(|tehai| {
+                            let mut tehai = tehai.to_vec();
+                            tehai.sort_unstable();
+                            PlayerState {
+                                tehai,
+                                ..Default::default()
+                            }
+                        })
+                        .collect(),
+                    in_kyoku: true,
+                };
+                return Ok(());
+            }
+            _ => (),
+        }
+
+        if !self.in_kyoku {
+            return Err(StateError::OutsideKyoku(event.clone()));
+        }
+        if event
+            .actor()
+            .is_some_and(|a| a as usize >= self.players.len())
+        {
+            return Err(StateError::InvalidActor(event.clone()));
+        }
+
+        match *event {
+            Event::Tsumo { actor, pai } => {
+                self.tiles_left = self
+                    .tiles_left
+                    .checked_sub(1)
+                    .ok_or_else(|| StateError::WallExhausted(event.clone()))?;
+                self.player(actor).insert(pai);
+            }
+            Event::Dahai {
+                actor,
+                pai,
+                tsumogiri,
+            } => {
+                let player = self.player(actor);
+                player.remove(actor, pai)?;
+                player.river.push(Discard {
+                    pai,
+                    tsumogiri,
+                    reach: std::mem::take(&mut player.reach_declared),
+                    called: false,
+                });
+            }
+
+            Event::Chi {
+                actor,
+                target,
+                pai,
+                consumed: ref c,
+            }
+            | Event::Pon {
+                actor,
+                target,
+                pai,
+                consumed: ref c,
+            } => self.call(event, actor, target, pai, c)?,
+            Event::Daiminkan {
+                actor,
+                target,
+                pai,
+                consumed: ref c,
+            } => self.call(event, actor, target, pai, c)?,
+            Event::Kakan { actor, pai, .. } => {
+                let player = self.player(actor);
+                let pon = player.melds.iter().position(
+                    |m| matches!(*m, Event::Pon { pai: p, .. } if p.deaka() == pai.deaka()),
+                );
+                let pon = pon.ok_or(StateError::KakanWithoutPon { actor, pai })?;
+                player.remove(actor, pai)?;
+                player.melds[pon] = event.clone();
+            }
+            Event::Ankan {
+                actor,
+                consumed: ref c,
+            } => {
+                let player = self.player(actor);
+                for &pai in c {
+                    player.remove(actor, pai)?;
+                }
+                player.melds.push(event.clone());
+            }
+            Event::Dora { dora_marker } => self.dora_markers.push(dora_marker),
+            Event::Nukidora { actor, pai } => {
+                let player = self.player(actor);
+                player.remove(actor, pai)?;
+                player.nukidoras += 1;
+            }
+
+            Event::Reach { actor } => self.player(actor).reach_declared = true,
+            Event::ReachAccepted { actor } => {
+                self.player(actor).reached = true;
+                if let Some(score) = self.scores.get_mut(actor as usize) {
+                    *score -= 1000;
+                }
+                self.kyotaku += 1;
+            }
+
+            Event::Hora { ref deltas, .. } => {
+                self.apply_deltas(deltas.as_deref());
+                // On double ron only the first winner takes them.
+                self.kyotaku = 0;
+            }
+            Event::Ryukyoku { ref deltas, .. } => self.apply_deltas(deltas.as_deref()),
+            Event::EndKyoku => self.in_kyoku = false,
+
+            Event::None
+            | Event::StartGame { .. }
+            | Event::StartKyoku { .. }
+            | Event::EndGame { .. } => (),
+        }
+
+        Ok(())
+    }
+
+    fn player(&mut self, actor: u8) -> &mut PlayerState {
+        &mut self.players[actor as usize]
+    }
+
+    fn call(
+        &mut self,
+        event: &Event,
+        actor: u8,
+        target: u8,
+        pai: Tile,
+        consumed: &[Tile],
+    ) -> Result<()> {
+        let discard = self
+            .players
+            .get_mut(target as usize)
+            .filter(|_| target != actor)
+            .and_then(|p| p.river.last_mut())
+            .filter(|d| d.pai == pai && !d.called)
+            .ok_or_else(|| StateError::InvalidCall(event.clone()))?;
+        discard.called = true;
+
+        let player = self.player(actor);
+        for &pai in consumed {
+            player.remove(actor, pai)?;
+        }
+        player.melds.push(event.clone());
+        Ok(())
+    }
+
+    fn apply_deltas(&mut self, deltas: Option<&[i32]>) {
+        for (score, d) in self.scores.iter_mut().zip(deltas.unwrap_or_default()) {
+            *score += d;
+        }
+    }
+}
+
+impl PlayerState {
+    /// The shanten number of the hand, see [`shanten`](crate::shanten::shanten).
+    #[must_use]
+    pub fn shanten(&self) -> i8 {
+        shanten::shanten(&shanten::to_counts(&self.tehai), self.melds.len() as u8)
+    }
+
+    fn insert(&mut self, pai: Tile) {
+        let idx = self.tehai.partition_point(|&t| t <= pai);
+        self.tehai.insert(idx, pai);
+    }
+
+    fn remove(&mut self, actor: u8, pai: Tile) -> Result<()> {
+        let idx = self
+            .tehai
+            .iter()
+            .position(|&t| t == pai)
+            .ok_or(StateError::TileNotInHand { actor, pai })?;
+        self.tehai.remove(idx);
+        Ok(())
+    }
+}
+
+/// Replays `events`, returning the state after each of them.
+pub fn replay(events: &[Event]) -> Result<Vec<GameState>> {
+    let mut state = GameState::default();
+    events
+        .iter()
+        .map(|event| {
+            state.update(event)?;
+            Ok(state.clone())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tenhou::Log;
+    use crate::{mjlog, tenhou_to_mjai, testdata};
+
+    /// Checks the replayed hands against what hora and ryukyoku reveal.
+    fn check(events: &[Event], name: &str) {
+        let mut state = GameState::default();
+        for event in events {
+            state
+                .update(event)
+                .unwrap_or_else(|e| panic!("{name}: {e}"));
+            match event {
+                Event::Hora {
+                    actor,
+                    target,
+                    info: Some(info),
+                    ..
+                } => {
+                    let mut tehai = state.players[*actor as usize].tehai.clone();
+                    if actor == target {
+                        assert_eq!(state.players[*actor as usize].shanten(), -1, "{name}");
+                        let idx = tehai.iter().position(|&t| t == info.pai).unwrap();
+                        tehai.remove(idx);
+                    }
+                    assert_eq!(tehai, info.tehai, "{name}");
+                    assert_eq!(state.players[*actor as usize].melds, info.melds, "{name}");
+                }
+                Event::Ryukyoku {
+                    info: Some(info), ..
+                } => {
+                    for (player, shown) in state.players.iter().zip(&info.tehais) {
+                        if let Some(shown) = shown {
+                            let mut shown = shown.clone();
+                            shown.sort_unstable();
+                            assert_eq!(player.tehai, shown, "{name}");
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn replay_testdata() {
+        for (name, json) in testdata::tenhou_logs() {
+            let log = Log::from_json_str(&json).unwrap();
+            check(&tenhou_to_mjai(&log).unwrap(), &name);
+        }
+        for (name, xml) in testdata::mjlogs() {
+            check(&mjlog::parse_str(&xml).unwrap(), &name);
+        }
+    }
+
+    #[test]
+    fn snapshots() {
+        let log = Log::from_json_str(&testdata::read("ryukyoku.json")).unwrap();
+        let events = tenhou_to_mjai(&log).unwrap();
+        let states = replay(&events).unwrap();
+        assert_eq!(states.len(), events.len());
+
+        let index = events
+            .iter()
+            .position(|ev| matches!(ev, Event::Ryukyoku { .. }))
+            .unwrap();
+        let state = GameState::at(&events, index).unwrap();
+        assert_eq!(state, states[index]);
+        assert_eq!(state.tiles_left, 0);
+        let total = |s: &GameState| s.scores.iter().sum::<i32>() + i32::from(s.kyotaku) * 1000;
+        assert_eq!(total(&state), total(&states[1]));
+        for player in &state.players {
+            // A kan takes four tiles but is made up for with a rinshan draw.
+            assert_eq!(player.tehai.len() + player.melds.len() * 3, 13);
+        }
+
+        GameState::at(&events, events.len()).unwrap_err();
+    }
+}
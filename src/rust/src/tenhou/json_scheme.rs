@@ -1,38 +1,42 @@
-use super::TenhouTile;
-use crate::{KyokuFilter, Tile};
+use super::{ParseError, TenhouTile};
+use crate::mjai::GameResult;
+use crate::{KyokuSelector, Tile};
 
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Number, Value};
 use serde_tuple::{Deserialize_tuple as DeserializeTuple, Serialize_tuple as SerializeTuple};
 use serde_with::{serde_as, FromInto};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RawLog {
     #[serde(rename = "log")]
-    pub(super) logs: Vec<RawKyoku>,
+    pub(crate) logs: Vec<RawKyoku>,
     #[serde(rename = "name")]
-    pub(super) names: [String; 4],
-    pub(super) rule: Rule,
+    pub(crate) names: [String; 4],
+    pub(crate) rule: Rule,
 
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub(super) ratingc: Option<String>,
+    pub(crate) ratingc: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub(super) lobby: Option<i32>,
+    pub(crate) lobby: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub(super) dan: Option<Vec<String>>,
+    pub(crate) dan: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub(super) rate: Option<Vec<f64>>,
+    pub(crate) rate: Option<Vec<f64>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub(super) sx: Option<Vec<String>>,
+    pub(crate) sx: Option<Vec<String>>,
+    /// Final score and points of each seat in turn, four seats even in sanma.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) sc: Option<Vec<Number>>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct RawPartialLog<'a> {
     #[serde(flatten)]
-    pub(super) parent: &'a RawLog,
+    pub(crate) parent: &'a RawLog,
 
     #[serde(rename = "log")]
-    pub(super) logs: &'a [RawKyoku],
+    pub(crate) logs: &'a [RawKyoku],
 }
 
 /// An item corresponding to each elements in "配牌", "取" and "出".
@@ -47,35 +51,36 @@ pub enum ActionItem {
 
 #[serde_as]
 #[derive(Debug, Clone, SerializeTuple, DeserializeTuple)]
-pub(super) struct RawKyoku {
-    pub(super) meta: KyokuMeta,
-    pub(super) scoreboard: [i32; 4],
+pub(crate) struct RawKyoku {
+    pub(crate) meta: KyokuMeta,
+    pub(crate) scoreboard: [i32; 4],
     #[serde_as(as = "Vec<FromInto<TenhouTile>>")]
-    pub(super) dora_indicators: Vec<Tile>,
+    pub(crate) dora_indicators: Vec<Tile>,
     #[serde_as(as = "Vec<FromInto<TenhouTile>>")]
-    pub(super) ura_indicators: Vec<Tile>,
+    pub(crate) ura_indicators: Vec<Tile>,
 
-    #[serde_as(as = "[FromInto<TenhouTile>; 13]")]
-    pub(super) haipai_0: [Tile; 13],
-    pub(super) takes_0: Vec<ActionItem>,
-    pub(super) discards_0: Vec<ActionItem>,
+    #[serde_as(as = "Vec<FromInto<TenhouTile>>")]
+    pub(crate) haipai_0: Vec<Tile>,
+    pub(crate) takes_0: Vec<ActionItem>,
+    pub(crate) discards_0: Vec<ActionItem>,
 
-    #[serde_as(as = "[FromInto<TenhouTile>; 13]")]
-    pub(super) haipai_1: [Tile; 13],
-    pub(super) takes_1: Vec<ActionItem>,
-    pub(super) discards_1: Vec<ActionItem>,
+    #[serde_as(as = "Vec<FromInto<TenhouTile>>")]
+    pub(crate) haipai_1: Vec<Tile>,
+    pub(crate) takes_1: Vec<ActionItem>,
+    pub(crate) discards_1: Vec<ActionItem>,
 
-    #[serde_as(as = "[FromInto<TenhouTile>; 13]")]
-    pub(super) haipai_2: [Tile; 13],
-    pub(super) takes_2: Vec<ActionItem>,
-    pub(super) discards_2: Vec<ActionItem>,
+    #[serde_as(as = "Vec<FromInto<TenhouTile>>")]
+    pub(crate) haipai_2: Vec<Tile>,
+    pub(crate) takes_2: Vec<ActionItem>,
+    pub(crate) discards_2: Vec<ActionItem>,
 
-    #[serde_as(as = "[FromInto<TenhouTile>; 13]")]
-    pub(super) haipai_3: [Tile; 13],
-    pub(super) takes_3: Vec<ActionItem>,
-    pub(super) discards_3: Vec<ActionItem>,
+    // This seat is left empty in sanma.
+    #[serde_as(as = "Vec<FromInto<TenhouTile>>")]
+    pub(crate) haipai_3: Vec<Tile>,
+    pub(crate) takes_3: Vec<ActionItem>,
+    pub(crate) discards_3: Vec<ActionItem>,
 
-    pub(super) results: Vec<ResultItem>,
+    pub(crate) results: Vec<ResultItem>,
 }
 
 #[derive(Debug, Clone, SerializeTuple, DeserializeTuple)]
@@ -87,7 +92,7 @@ pub struct KyokuMeta {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
-pub(super) enum ResultItem {
+pub(crate) enum ResultItem {
     Status(String),
     ScoreDeltas([i32; 4]),
     HoraDetail(Vec<Value>),
@@ -95,12 +100,29 @@ pub(super) enum ResultItem {
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(default)]
-pub(super) struct Rule {
-    pub(super) disp: String,
-    pub(super) aka: u8,
-    pub(super) aka51: u8,
-    pub(super) aka52: u8,
-    pub(super) aka53: u8,
+pub(crate) struct Rule {
+    pub(crate) disp: String,
+    pub(crate) aka: u8,
+    pub(crate) aka51: u8,
+    pub(crate) aka52: u8,
+    pub(crate) aka53: u8,
+}
+
+/// Writes `sc` of the final standings, padding the fourth seat in sanma.
+pub(crate) fn encode_sc(result: &GameResult) -> Vec<Number> {
+    (0..4)
+        .flat_map(|i| {
+            let score = result.scores.get(i).copied().unwrap_or_default();
+            let point = result.points.get(i).copied().unwrap_or_default();
+            // tenhou writes whole points without the fraction.
+            let point = if point.fract() == 0.0 {
+                Number::from(point as i64)
+            } else {
+                Number::from_f64(point).unwrap_or_else(|| Number::from(0))
+            };
+            [Number::from(score), point]
+        })
+        .collect()
 }
 
 impl RawLog {
@@ -121,10 +143,16 @@ impl RawLog {
             });
     }
 
+    /// Keeps only the kyokus `selector` selects, leaving the log as is if
+    /// the selector fails to parse it.
     #[inline]
-    pub fn filter_kyokus(&mut self, kyoku_filter: &KyokuFilter) {
-        self.logs
-            .retain(|l| kyoku_filter.test(l.meta.kyoku_num, l.meta.honba));
+    pub fn filter_kyokus<S: KyokuSelector + ?Sized>(
+        &mut self,
+        selector: &S,
+    ) -> Result<(), ParseError> {
+        let mut selected = selector.select_raw_kyokus(self)?.into_iter();
+        self.logs.retain(|_| selected.next().unwrap_or(false));
+        Ok(())
     }
 
     /// Split one raw tenhou.net/6 log into many by kyokus.
@@ -146,13 +174,13 @@ impl RawLog {
 
     #[inline]
     #[must_use]
-    pub fn is_empty(&self) -> bool {
+    pub const fn is_empty(&self) -> bool {
         self.logs.is_empty()
     }
 
     #[inline]
     #[must_use]
-    pub fn len(&self) -> usize {
+    pub const fn len(&self) -> usize {
         self.logs.len()
     }
 }
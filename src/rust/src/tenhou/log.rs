@@ -1,8 +1,10 @@
 use super::json_scheme::{ActionItem, KyokuMeta, RawLog, ResultItem};
-use crate::{KyokuFilter, Tile};
+use crate::kyoku_filter::kyoku_label;
+use crate::mjai::{GameMeta, GameResult, HandValue, Limit, LobbyTier, RyukyokuReason, Yaku};
+use crate::{KyokuSelector, Tile};
 
 use serde::Serialize;
-use serde_json::{self as json, Value};
+use serde_json::{self as json, Number, Value};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -12,18 +14,46 @@ pub enum ParseError {
         #[from]
         source: json::Error,
     },
-    #[error("not four-player game")]
-    NotFourPlayer,
+    #[error("invalid haipai: at {} for actor {actor}", kyoku_label(*.kyoku, *.honba))]
+    InvalidHaipai { kyoku: u8, honba: u8, actor: u8 },
+    #[error("{0} does not exist in sanma")]
+    InvalidSanmaTile(Tile),
     #[error("invalid hora detail")]
     InvalidHoraDetail,
+    #[error("at {}: {source}", kyoku_label(*.kyoku, *.honba))]
+    InKyoku {
+        kyoku: u8,
+        honba: u8,
+        source: Box<Self>,
+    },
+}
+
+impl ParseError {
+    /// Tells the kyoku where the error is from, unless it already does.
+    fn in_kyoku(self, kyoku: u8, honba: u8) -> Self {
+        match self {
+            Self::InvalidSanmaTile(_) | Self::InvalidHoraDetail => Self::InKyoku {
+                kyoku,
+                honba,
+                source: Box::new(self),
+            },
+            _ => self,
+        }
+    }
 }
 
 /// The overview structure of log in tenhou.net/6 format.
 #[derive(Debug, Clone)]
 pub struct Log {
-    pub names: [String; 4],
+    /// Three names for sanma, four otherwise.
+    pub names: Vec<String>,
+    pub num_players: u8,
     pub game_length: GameLength,
     pub has_aka: bool,
+    pub lobby_tier: Option<LobbyTier>,
+    pub meta: GameMeta,
+    /// Final standings recorded in `sc`.
+    pub result: Option<GameResult>,
     pub kyokus: Vec<Kyoku>,
 }
 
@@ -40,14 +70,23 @@ pub struct Kyoku {
     pub scoreboard: [i32; 4],
     pub dora_indicators: Vec<Tile>,
     pub ura_indicators: Vec<Tile>,
-    pub action_tables: [ActionTable; 4],
+    /// One table for each seat, three in sanma.
+    pub action_tables: Vec<ActionTable>,
     pub end_status: EndStatus,
 }
 
 #[derive(Debug, Clone)]
 pub enum EndStatus {
-    Hora { details: Vec<HoraDetail> },
-    Ryukyoku { score_deltas: [i32; 4] },
+    Hora {
+        details: Vec<HoraDetail>,
+    },
+    Ryukyoku {
+        score_deltas: [i32; 4],
+        /// `None` if the status text is unknown.
+        reason: Option<RyukyokuReason>,
+        /// Told from the status text and `score_deltas`, one for each seat.
+        tenpai: Vec<bool>,
+    },
 }
 
 #[derive(Debug, Clone, Default)]
@@ -55,6 +94,8 @@ pub struct HoraDetail {
     pub who: u8,
     pub target: u8,
     pub score_deltas: [i32; 4],
+    /// `None` if the point text is missing.
+    pub value: Option<HandValue>,
 }
 
 /// A group of "配牌", "取" and "出", describing a player's
@@ -75,9 +116,9 @@ impl Log {
     }
 
     #[inline]
-    pub fn filter_kyokus(&mut self, kyoku_filter: &KyokuFilter) {
-        self.kyokus
-            .retain(|l| kyoku_filter.test(l.meta.kyoku_num, l.meta.honba));
+    pub fn filter_kyokus<S: KyokuSelector + ?Sized>(&mut self, selector: &S) {
+        let mut selected = selector.select_kyokus(self).into_iter();
+        self.kyokus.retain(|_| selected.next().unwrap_or(false));
     }
 }
 
@@ -86,50 +127,85 @@ impl TryFrom<RawLog> for Log {
 
     fn try_from(raw_log: RawLog) -> Result<Self, Self::Error> {
         let RawLog {
-            logs, names, rule, ..
+            logs,
+            names,
+            rule,
+            ratingc,
+            lobby,
+            dan,
+            rate,
+            sx,
+            sc,
         } = raw_log;
 
-        if rule.disp.contains('三') || rule.disp.contains("3-Player") {
-            return Err(ParseError::NotFourPlayer);
-        }
+        let num_players = if rule.disp.contains('三') || rule.disp.contains("3-Player") {
+            3
+        } else {
+            4
+        };
         let game_length = if rule.disp.contains('東') || rule.disp.contains("East") {
             GameLength::Tonpuu
         } else {
             GameLength::Hanchan
         };
         let has_aka = rule.aka + rule.aka51 + rule.aka52 + rule.aka53 > 0;
+        let lobby_tier = rule.disp.chars().find_map(|c| match c {
+            '般' => Some(LobbyTier::Ippan),
+            '上' => Some(LobbyTier::Joukyuu),
+            '特' => Some(LobbyTier::Tokujou),
+            '鳳' => Some(LobbyTier::Houou),
+            _ => None,
+        });
 
         let mut kyokus = Vec::with_capacity(logs.len());
         for log in logs {
+            let (kyoku_num, honba) = (log.meta.kyoku_num, log.meta.honba);
+            let raw_tables = [
+                (log.haipai_0, log.takes_0, log.discards_0),
+                (log.haipai_1, log.takes_1, log.discards_1),
+                (log.haipai_2, log.takes_2, log.discards_2),
+                (log.haipai_3, log.takes_3, log.discards_3),
+            ];
+            let mut action_tables = Vec::with_capacity(num_players);
+            for (actor, (haipai, takes, discards)) in
+                raw_tables.into_iter().take(num_players).enumerate()
+            {
+                let haipai: [Tile; 13] =
+                    haipai.try_into().map_err(|_| ParseError::InvalidHaipai {
+                        kyoku: log.meta.kyoku_num,
+                        honba: log.meta.honba,
+                        actor: actor as u8,
+                    })?;
+                if num_players == 3 {
+                    let takes = takes.iter().filter_map(|take| match *take {
+                        ActionItem::Tile(pai) => Some(pai),
+                        _ => None,
+                    });
+                    if let Some(pai) = haipai
+                        .into_iter()
+                        .chain(takes)
+                        .find(|t| t.is_sanma_excluded())
+                    {
+                        return Err(ParseError::InvalidSanmaTile(pai).in_kyoku(kyoku_num, honba));
+                    }
+                }
+                action_tables.push(ActionTable {
+                    haipai,
+                    takes,
+                    discards,
+                });
+            }
+
             let mut kyoku = Kyoku {
                 meta: log.meta,
                 scoreboard: log.scoreboard,
                 dora_indicators: log.dora_indicators,
                 ura_indicators: log.ura_indicators,
-                action_tables: [
-                    ActionTable {
-                        haipai: log.haipai_0,
-                        takes: log.takes_0,
-                        discards: log.discards_0,
-                    },
-                    ActionTable {
-                        haipai: log.haipai_1,
-                        takes: log.takes_1,
-                        discards: log.discards_1,
-                    },
-                    ActionTable {
-                        haipai: log.haipai_2,
-                        takes: log.takes_2,
-                        discards: log.discards_2,
-                    },
-                    ActionTable {
-                        haipai: log.haipai_3,
-                        takes: log.takes_3,
-                        discards: log.discards_3,
-                    },
-                ],
+                action_tables,
                 end_status: EndStatus::Ryukyoku {
                     score_deltas: [0; 4], // default
+                    reason: None,
+                    tenpai: vec![false; num_players],
                 },
             };
 
@@ -143,17 +219,29 @@ impl TryFrom<RawLog> for Log {
                             let who = if let Some(Value::Number(n)) = who_target_tuple.first() {
                                 n.as_u64().unwrap_or(0) as u8
                             } else {
-                                return Err(ParseError::InvalidHoraDetail);
+                                return Err(
+                                    ParseError::InvalidHoraDetail.in_kyoku(kyoku_num, honba)
+                                );
                             };
                             let target = if let Some(Value::Number(n)) = who_target_tuple.get(1) {
                                 n.as_u64().unwrap_or(0) as u8
                             } else {
-                                return Err(ParseError::InvalidHoraDetail);
+                                return Err(
+                                    ParseError::InvalidHoraDetail.in_kyoku(kyoku_num, honba)
+                                );
+                            };
+                            let value = match who_target_tuple.get(3..) {
+                                Some([Value::String(text), yaku @ ..]) => Some(
+                                    parse_hand_value(text, yaku, num_players)
+                                        .map_err(|e| e.in_kyoku(kyoku_num, honba))?,
+                                ),
+                                _ => None,
                             };
                             let hora_detail = HoraDetail {
                                 score_deltas: *score_deltas,
                                 who,
                                 target,
+                                value,
                             };
                             details.push(hora_detail);
                         }
@@ -166,7 +254,36 @@ impl TryFrom<RawLog> for Log {
                         } else {
                             [0; 4]
                         };
-                    kyoku.end_status = EndStatus::Ryukyoku { score_deltas };
+                    let (reason, tenpai) = match status_text.as_str() {
+                        "全員聴牌" => (Some(RyukyokuReason::Exhaustive), true),
+                        "全員不聴" => (Some(RyukyokuReason::Exhaustive), false),
+                        status => {
+                            let reason = [
+                                RyukyokuReason::Exhaustive,
+                                RyukyokuReason::KyuushuKyuuhai,
+                                RyukyokuReason::SuuchaRiichi,
+                                RyukyokuReason::SanchaHou,
+                                RyukyokuReason::SuukanSanra,
+                                RyukyokuReason::SuufonRenda,
+                                RyukyokuReason::NagashiMangan,
+                            ]
+                            .into_iter()
+                            .find(|r| r.to_str() == status);
+                            (reason, reason == Some(RyukyokuReason::SuuchaRiichi))
+                        }
+                    };
+                    // Those who are tenpai get paid on exhaustive draws.
+                    let tenpai = (0..num_players)
+                        .map(|i| {
+                            tenpai
+                                || reason == Some(RyukyokuReason::Exhaustive) && score_deltas[i] > 0
+                        })
+                        .collect();
+                    kyoku.end_status = EndStatus::Ryukyoku {
+                        score_deltas,
+                        reason,
+                        tenpai,
+                    };
                 }
             }
 
@@ -174,10 +291,237 @@ impl TryFrom<RawLog> for Log {
         }
 
         Ok(Self {
-            names,
+            names: names.into_iter().take(num_players).collect(),
+            num_players: num_players as u8,
             game_length,
             has_aka,
+            lobby_tier,
+            meta: GameMeta {
+                lobby,
+                ratingc,
+                dan: dan.map(|v| v.into_iter().take(num_players).collect()),
+                rate: rate.map(|v| v.into_iter().take(num_players).collect()),
+                sx: sx.map(|v| v.into_iter().take(num_players).collect()),
+            },
+            result: sc.and_then(|sc| parse_sc(&sc, num_players)),
             kyokus,
         })
     }
 }
+
+/// Reads the final scores and points in `sc`, `None` if they are incomplete.
+fn parse_sc(sc: &[Number], num_players: usize) -> Option<GameResult> {
+    let (scores, points) = sc
+        .chunks_exact(2)
+        .take(num_players)
+        .map(|pair| Some((pair[0].as_i64()? as i32, pair[1].as_f64()?)))
+        .collect::<Option<(Vec<_>, Vec<_>)>>()?;
+    (scores.len() == num_players).then(|| GameResult::with_points(scores, points))
+}
+
+/// Parses the point text such as "30符1飜1000点" or "満貫2000-4000点" along
+/// with yaku strings such as "立直(1飜)".
+fn parse_hand_value(
+    text: &str,
+    yaku: &[Value],
+    num_players: usize,
+) -> Result<HandValue, ParseError> {
+    // 三倍満 has to be tested before 倍満.
+    let limit = [
+        Limit::Sanbaiman,
+        Limit::Baiman,
+        Limit::Haneman,
+        Limit::Mangan,
+        Limit::Yakuman,
+    ]
+    .into_iter()
+    .find(|limit| text.contains(limit.to_str()));
+    let fu = text
+        .split_once('符')
+        .and_then(|(fu, _)| fu.parse().ok())
+        .unwrap_or(0);
+
+    // Tsumo is written as "ko-oya", or "each∀" by the oya.
+    let head = &text[..text.find('点').ok_or(ParseError::InvalidHoraDetail)?];
+    let payment = head
+        .rsplit(|c: char| !c.is_ascii_digit() && c != '-')
+        .next()
+        .unwrap_or_default();
+    let mut parts = payment.split('-').map(str::parse::<i32>);
+    let points = match (parts.next(), parts.next()) {
+        (Some(Ok(ron)), None) if text.contains('∀') => ron * (num_players as i32 - 1),
+        (Some(Ok(ron)), None) => ron,
+        (Some(Ok(ko)), Some(Ok(oya))) => ko * (num_players as i32 - 2) + oya,
+        _ => return Err(ParseError::InvalidHoraDetail),
+    };
+
+    let yaku = yaku
+        .iter()
+        .map(|item| {
+            let s = item.as_str().ok_or(ParseError::InvalidHoraDetail)?;
+            let (name, han) = s
+                .strip_suffix(')')
+                .and_then(|s| s.rsplit_once('('))
+                .ok_or(ParseError::InvalidHoraDetail)?;
+            let han = if han == "役満" {
+                13
+            } else {
+                han.trim_end_matches('飜')
+                    .parse()
+                    .map_err(|_| ParseError::InvalidHoraDetail)?
+            };
+            Ok(Yaku {
+                name: name.to_owned(),
+                han,
+            })
+        })
+        .collect::<Result<Vec<_>, ParseError>>()?;
+
+    Ok(HandValue {
+        fu,
+        han: yaku.iter().map(|y| y.han).sum(),
+        points,
+        limit,
+        yaku,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{mjai_to_mjlog, mjai_to_tenhou, mjlog_to_tenhou, tenhou_to_mjai, testdata, Event};
+
+    #[test]
+    fn hand_value() {
+        let yaku = [Value::from("自風 南(1飜)"), Value::from("ドラ(2飜)")];
+        let value = parse_hand_value("30符3飜1000-2000点", &yaku, 4).unwrap();
+        assert_eq!((value.fu, value.han, value.points), (30, 3, 4000));
+        assert_eq!(value.limit, None);
+        assert_eq!(value.yaku[0].name, "自風 南");
+
+        let value = parse_hand_value("30符4飜3900点∀", &[], 4).unwrap();
+        assert_eq!(value.points, 11700);
+        let value = parse_hand_value("満貫2000-4000点", &[], 3).unwrap();
+        assert_eq!((value.fu, value.points), (0, 6000));
+        assert_eq!(value.limit, Some(Limit::Mangan));
+
+        let yaku = [Value::from("四槓子(役満)")];
+        let value = parse_hand_value("役満32000点", &yaku, 4).unwrap();
+        assert_eq!((value.han, value.limit), (13, Some(Limit::Yakuman)));
+        let value = parse_hand_value("三倍満24000点", &[], 4).unwrap();
+        assert_eq!(value.limit, Some(Limit::Sanbaiman));
+
+        parse_hand_value("満貫", &[], 4).unwrap_err();
+        parse_hand_value("30符1飜1000点", &[Value::from("立直")], 4).unwrap_err();
+    }
+
+    #[test]
+    fn ryukyoku() {
+        let info = |name: &str| {
+            let log = Log::from_json_str(&testdata::read(name)).unwrap();
+            tenhou_to_mjai(&log)
+                .unwrap()
+                .into_iter()
+                .find_map(|ev| match ev {
+                    Event::Ryukyoku { info, .. } => info,
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        let exhaustive = info("ryukyoku.json");
+        assert_eq!(exhaustive.reason, RyukyokuReason::Exhaustive);
+        assert_eq!(exhaustive.tenpai, [true, true, false, false]);
+        assert!(exhaustive.tehais[0].as_ref().is_some_and(|t| !t.is_empty()));
+        assert_eq!(exhaustive.tehais[2], None);
+
+        let four_reach = info("four_reach.json");
+        assert_eq!(four_reach.reason, RyukyokuReason::SuuchaRiichi);
+        assert!(four_reach.tehais.iter().all(Option::is_some));
+
+        let kyushu = info("kyushukyuhai.json");
+        assert_eq!(kyushu.reason, RyukyokuReason::KyuushuKyuuhai);
+        assert_eq!(kyushu.tenpai, [false; 4]);
+        assert_eq!(kyushu.tehais.iter().flatten().count(), 1);
+    }
+
+    #[test]
+    fn game_result() {
+        let end_game = |log: &Log| match tenhou_to_mjai(log).unwrap().pop() {
+            Some(Event::EndGame { info: Some(info) }) => *info,
+            ev => panic!("unexpected {ev:?}"),
+        };
+
+        for name in ["ranked_game.json", "suukantsu_1.json"] {
+            let mut log = Log::from_json_str(&testdata::read(name)).unwrap();
+            let recorded = end_game(&log);
+            assert_eq!(log.result.as_ref(), Some(&recorded));
+
+            // Without sc, the same standings are told from the last kyoku.
+            log.result = None;
+            assert_eq!(end_game(&log), recorded, "{name}");
+        }
+
+        let log = Log::from_json_str(&testdata::read("ranked_game.json")).unwrap();
+        let result = log.result.unwrap();
+        assert_eq!(result.scores, [21_100, 55_000, -4_900, 28_800]);
+        assert_eq!(result.ranks, [3, 1, 4, 2]);
+        assert_eq!(result.points, [-19., 65., -55., 9.]);
+    }
+
+    #[test]
+    fn errors_tell_the_kyoku() {
+        let json = testdata::read("double_ron.json");
+        let err = Log::from_json_str(&json.replace('点', "")).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::InKyoku {
+                ref source,
+                ..
+            } if matches!(**source, ParseError::InvalidHoraDetail)
+        ));
+
+        let json = testdata::read("ryukyoku.json");
+        let log = Log::from_json_str(&json.replace("c151416", "c1514xx")).unwrap();
+        let err = tenhou_to_mjai(&log).unwrap_err();
+        assert!(
+            err.to_string().starts_with("at E1.1: invalid tile"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn sanma() {
+        let json_string = testdata::read("sanma.json");
+        let log = Log::from_json_str(&json_string).unwrap();
+        let events = tenhou_to_mjai(&log).unwrap();
+        assert!(events.contains(&Event::Nukidora {
+            actor: 0,
+            pai: "N".parse().unwrap(),
+        }));
+        // One delta for each of the three seats.
+        assert!(events.iter().any(|ev| matches!(
+            ev,
+            Event::Ryukyoku { deltas: Some(d), .. } if d.len() == 3
+        )));
+
+        // Seats and naki strings come back as they are written.
+        let expected: Value = json::from_str(&json_string).unwrap();
+        let raw_log = mjai_to_tenhou(&events).unwrap();
+        assert_eq!(json::to_value(&raw_log.logs).unwrap(), expected["log"]);
+
+        let xml = mjai_to_mjlog(&events).unwrap();
+        assert!(xml.contains(r#"<N who="0" m=""#));
+        let raw_log = mjlog_to_tenhou(&xml).unwrap();
+        assert_eq!(json::to_value(&raw_log.logs).unwrap(), expected["log"]);
+        assert_eq!(raw_log.rule.disp, "三般南喰赤");
+        assert_eq!(raw_log.rule.aka51, 0);
+
+        let json_string = serde_json::to_string(&raw_log).unwrap();
+        let log = Log::from_json_str(&json_string).unwrap();
+        assert_eq!(log.meta.ratingc.as_deref(), Some("PF3"));
+        assert_eq!(log.meta.dan.as_ref().map(Vec::len), Some(3));
+        // sanma.json has no metadata, which the writer fills with defaults.
+        assert_eq!(tenhou_to_mjai(&log).unwrap()[1..], events[1..]);
+    }
+}
@@ -1,9 +1,10 @@
+//! Reads logs in tenhou.net/6 format.
+
 mod json_scheme;
 mod log;
 mod tile;
 
-// pub use json_scheme::{ActionItem, KyokuMeta, RawLog, RawPartialLog};
-// pub use log::{ActionTable, EndStatus, GameLength, HoraDetail, Kyoku, Log};
-pub use json_scheme::ActionItem;
-pub use log::{EndStatus, Kyoku, Log};
+pub(crate) use json_scheme::{encode_sc, RawKyoku, ResultItem, Rule};
+pub use json_scheme::{ActionItem, KyokuMeta, RawLog, RawPartialLog};
+pub use log::{ActionTable, EndStatus, GameLength, HoraDetail, Kyoku, Log, ParseError};
 pub(crate) use tile::TenhouTile;
@@ -0,0 +1,59 @@
+//! Logs in inst/testdata and inst/mjlog shared by tests.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Trimmed logs whose deltas leave out the kyotaku of accepted reaches.
+pub const KYOTAKU_LEFT_OUT: [&str; 2] = ["confusing_nakis_1.json", "confusing_nakis_2.json"];
+/// A trimmed log whose hora is a furiten ron of an incomplete hand.
+pub const NOT_A_HAND: &str = "confusing_nakis_2.json";
+/// A made-up log with four plain 5s while aka is enabled.
+pub const FOUR_PLAIN_FIVES: &str = "confusing_nakis_2.json";
+
+/// Returns the path of a fixture, looked up in inst/mjlog for mjlog and in
+/// inst/testdata otherwise.
+fn path(name: &str) -> PathBuf {
+    let dir = if name.ends_with(".mjlog") {
+        "mjlog"
+    } else {
+        "testdata"
+    };
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../../inst")
+        .join(dir)
+        .join(name)
+}
+
+pub fn read(name: &str) -> String {
+    fs::read_to_string(path(name)).unwrap_or_else(|e| panic!("{name}: {e}"))
+}
+
+/// Names and contents of the tenhou.net/6 logs, sorted by name.
+pub fn tenhou_logs() -> Vec<(String, String)> {
+    read_all("testdata", "json")
+}
+
+/// Names and contents of the mjlogs, sorted by name.
+pub fn mjlogs() -> Vec<(String, String)> {
+    read_all("mjlog", "mjlog")
+}
+
+fn read_all(dir: &str, extension: &str) -> Vec<(String, String)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../../inst")
+        .join(dir);
+    let mut names: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    names.sort_unstable();
+    names
+        .into_iter()
+        .map(|name| {
+            let content = read(&name);
+            (name, content)
+        })
+        .collect()
+}
@@ -56,6 +56,13 @@ impl Tile {
         self.0 as usize
     }
 
+    /// Returns the mjai string of the tile, e.g. "5mr".
+    #[inline]
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        MJAI_PAI_STRINGS[self.0 as usize]
+    }
+
     #[inline]
     #[must_use]
     pub const fn deaka(self) -> Self {
@@ -99,6 +106,13 @@ impl Tile {
         )
     }
 
+    /// Returns true for 2m-8m, which are removed from the wall in sanma.
+    #[inline]
+    #[must_use]
+    pub const fn is_sanma_excluded(self) -> bool {
+        matches_tu8!(self.0, 2m | 3m | 4m | 5m | 6m | 7m | 8m | 5mr)
+    }
+
     #[inline]
     #[must_use]
     pub const fn is_unknown(self) -> bool {
@@ -211,9 +225,7 @@ impl fmt::Debug for Tile {
 
 impl fmt::Display for Tile {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // SAFETY: `Tile` is in-bound iff it is constructed safely.
-        let s = unsafe { MJAI_PAI_STRINGS.get_unchecked(self.0 as usize) };
-        f.write_str(s)
+        f.write_str(self.as_str())
     }
 }
 
@@ -306,7 +318,7 @@ pub fn tile_set_eq(a: &[Tile], b: &[Tile], ignore_aka: bool) -> bool {
 
     a_bits == b_bits
 }
-/**
+/*
  * End
  */
 
@@ -0,0 +1,511 @@
+use crate::ledger::ledger;
+use crate::machi::{Furiten, MachiTracker};
+use crate::mjai::Event;
+use crate::state::{GameState, StateError};
+use crate::{tu8, Tile};
+use std::mem;
+
+use thiserror::Error;
+
+/// A broken invariant found by [`validate`].
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("event {index}: {kind}")]
+pub struct Violation {
+    /// The index of the event where it is found.
+    pub index: usize,
+    pub kind: ViolationKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ViolationKind {
+    #[error("{count} tiles of {pai} in a kyoku")]
+    TooManyTiles { pai: Tile, count: u8 },
+
+    #[error("{count} of {pai} in a kyoku while aka_flag is {aka_flag}")]
+    AkaMismatch {
+        pai: Tile,
+        count: u8,
+        aka_flag: bool,
+    },
+
+    #[error("actor {actor} holds {size} tiles, counting 3 for each meld")]
+    HandSize { actor: u8, size: usize },
+
+    #[error("actor {actor} moves out of turn")]
+    OutOfTurn { actor: u8 },
+
+    #[error("actor {actor} calls from {target}, who did not just discard")]
+    NakiTarget { actor: u8, target: u8 },
+
+    #[error("{dora_markers} dora markers for {kans} kans")]
+    DoraCount { dora_markers: usize, kans: usize },
+
+    #[error("deltas sum up to {sum}, expected {expected}")]
+    DeltaSum { sum: i32, expected: i32 },
+
+    #[error("scores {actual:?} differ from {expected:?} at the end of the last kyoku")]
+    ScoreMismatch {
+        expected: Vec<i32>,
+        actual: Vec<i32>,
+    },
+
+    #[error("kyotaku {actual} differs from {expected} at the end of the last kyoku")]
+    KyotakuMismatch { expected: u8, actual: u8 },
+
+    #[error("actor {actor} wins by ron in {furiten:?} furiten")]
+    FuritenRon { actor: u8, furiten: Furiten },
+
+    #[error(transparent)]
+    Replay(#[from] StateError),
+}
+
+/// Checks structural invariants of a converted game.
+///
+/// The checks are tile conservation, hand sizes, turn order, naki targets,
+/// dora markers against kans, deltas summing up to the kyotaku taken, ron
+/// without furiten, and scores carried over between kyokus. After an event
+/// that cannot be replayed at all, the rest of the kyoku is skipped.
+#[must_use]
+pub fn validate(events: &[Event]) -> Vec<Violation> {
+    let mut v = Validator::default();
+    for (index, event) in events.iter().enumerate() {
+        v.index = index;
+        v.check(event);
+    }
+    v.finish_kyoku();
+
+    // Scores carried over are followed by the ledger.
+    for drift in ledger(events).drifts {
+        if !matches!(events[drift.index], Event::StartKyoku { .. }) {
+            continue;
+        }
+        v.index = drift.index;
+        if drift.expected != drift.actual {
+            v.push(ViolationKind::ScoreMismatch {
+                expected: drift.expected,
+                actual: drift.actual,
+            });
+        }
+        if drift.expected_kyotaku != drift.actual_kyotaku {
+            v.push(ViolationKind::KyotakuMismatch {
+                expected: drift.expected_kyotaku,
+                actual: drift.actual_kyotaku,
+            });
+        }
+    }
+    v.violations.sort_by_key(|v| v.index);
+    v.violations
+}
+
+#[derive(Default)]
+struct Validator {
+    index: usize,
+    violations: Vec<Violation>,
+    aka_flag: Option<bool>,
+    state: GameState,
+    machi: MachiTracker,
+    in_kyoku: bool,
+    broken: bool,
+
+    /// Tiles seen in the kyoku, indexed by `Tile::as_usize`.
+    counts: Vec<u8>,
+    ura_seen: bool,
+    kans: usize,
+    /// Kans since the last dahai and in the turn before.
+    recent_kans: [usize; 2],
+    /// Who has to discard next.
+    turn: Option<u8>,
+    /// Who draws next.
+    next_tsumo: Option<u8>,
+    /// The discard that can be called.
+    last_dahai: Option<u8>,
+}
+
+impl Validator {
+    fn push(&mut self, kind: ViolationKind) {
+        self.violations.push(Violation {
+            index: self.index,
+            kind,
+        });
+    }
+
+    fn count(&mut self, tiles: &[Tile]) {
+        for t in tiles {
+            if let Some(count) = self.counts.get_mut(t.as_usize()) {
+                *count += 1;
+            }
+        }
+    }
+
+    fn check(&mut self, event: &Event) {
+        match event {
+            Event::StartGame { aka_flag, .. } => self.aka_flag = Some(*aka_flag),
+            Event::StartKyoku {
+                dora_marker,
+                oya,
+                tehais,
+                ..
+            } => {
+                self.finish_kyoku();
+                self.in_kyoku = true;
+                self.broken = false;
+                self.counts = vec![0; 38];
+                self.ura_seen = false;
+                self.kans = 0;
+                self.recent_kans = [0; 2];
+                self.turn = None;
+                self.next_tsumo = Some(*oya);
+                self.last_dahai = None;
+                self.count(&[*dora_marker]);
+                for tehai in tehais {
+                    self.count(tehai);
+                }
+            }
+            _ if self.broken => return,
+            _ => self.check_turn(event),
+        }
+
+        let kyotaku = self.state.kyotaku;
+        if let Err(e) = self.state.update(event) {
+            self.push(e.into());
+            self.broken = true;
+            return;
+        }
+        self.machi.update(&self.state, event);
+
+        match *event {
+            Event::Tsumo { actor, pai } => {
+                self.count(&[pai]);
+                self.check_hand_size(actor, 14);
+            }
+            Event::Chi { actor, .. } | Event::Pon { actor, .. } => self.check_hand_size(actor, 14),
+            Event::Dahai { actor, .. }
+            | Event::Daiminkan { actor, .. }
+            | Event::Kakan { actor, .. }
+            | Event::Ankan { actor, .. }
+            | Event::Nukidora { actor, .. } => self.check_hand_size(actor, 13),
+            Event::Dora { dora_marker } => self.count(&[dora_marker]),
+            Event::Hora {
+                actor,
+                target,
+                ref deltas,
+                ref ura_markers,
+                ..
+            } => {
+                if actor != target {
+                    if let Some(furiten) = self.machi.furiten(&self.state, actor) {
+                        self.push(ViolationKind::FuritenRon { actor, furiten });
+                    }
+                }
+                // On double ron only the first winner takes kyotaku.
+                self.check_deltas(deltas.as_deref(), i32::from(kyotaku) * 1000);
+                if let Some(ura) = ura_markers.as_deref().filter(|u| !u.is_empty()) {
+                    if !self.ura_seen {
+                        self.count(ura);
+                        self.ura_seen = true;
+                    }
+                }
+            }
+            Event::Ryukyoku { ref deltas, .. } => self.check_deltas(deltas.as_deref(), 0),
+            Event::EndKyoku => self.finish_kyoku(),
+            _ => (),
+        }
+    }
+
+    fn check_turn(&mut self, event: &Event) {
+        let n = self.state.players.len().max(1) as u8;
+        match *event {
+            Event::Tsumo { actor, .. } => {
+                if self.turn.is_some() || self.next_tsumo.is_some_and(|a| a != actor) {
+                    self.push(ViolationKind::OutOfTurn { actor });
+                }
+                self.turn = Some(actor);
+                self.next_tsumo = None;
+                self.last_dahai = None;
+            }
+            Event::Dahai { actor, .. } => {
+                if self.turn != Some(actor) {
+                    self.push(ViolationKind::OutOfTurn { actor });
+                }
+                self.turn = None;
+                self.next_tsumo = Some((actor + 1) % n);
+                self.last_dahai = Some(actor);
+                self.recent_kans = [0, self.recent_kans[0]];
+            }
+            Event::Chi { actor, target, .. }
+            | Event::Pon { actor, target, .. }
+            | Event::Daiminkan { actor, target, .. } => {
+                if self.last_dahai != Some(target) || actor == target {
+                    self.push(ViolationKind::NakiTarget { actor, target });
+                } else if matches!(event, Event::Chi { .. }) && actor != (target + 1) % n {
+                    self.push(ViolationKind::OutOfTurn { actor });
+                }
+                if matches!(event, Event::Daiminkan { .. }) {
+                    self.kans += 1;
+                    self.recent_kans[0] += 1;
+                    self.turn = None;
+                    self.next_tsumo = Some(actor);
+                } else {
+                    self.turn = Some(actor);
+                    self.next_tsumo = None;
+                }
+                self.last_dahai = None;
+            }
+            Event::Kakan { actor, .. }
+            | Event::Ankan { actor, .. }
+            | Event::Nukidora { actor, .. } => {
+                if self.turn != Some(actor) {
+                    self.push(ViolationKind::OutOfTurn { actor });
+                }
+                if !matches!(event, Event::Nukidora { .. }) {
+                    self.kans += 1;
+                    self.recent_kans[0] += 1;
+                }
+                self.turn = None;
+                self.next_tsumo = Some(actor);
+            }
+            // Only whoever is to discard can declare reach or tsumo.
+            Event::Reach { actor } | Event::Hora { actor, .. }
+                if self.turn != Some(actor)
+                    && !matches!(*event, Event::Hora { target, .. } if target != actor) =>
+            {
+                self.push(ViolationKind::OutOfTurn { actor });
+            }
+            _ => (),
+        }
+    }
+
+    fn check_hand_size(&mut self, actor: u8, expected: usize) {
+        let player = &self.state.players[actor as usize];
+        let size = player.tehai.len() + player.melds.len() * 3;
+        if size != expected {
+            self.push(ViolationKind::HandSize { actor, size });
+        }
+    }
+
+    fn check_deltas(&mut self, deltas: Option<&[i32]>, expected: i32) {
+        let sum = deltas.map_or(expected, |d| d.iter().sum());
+        if sum != expected {
+            self.push(ViolationKind::DeltaSum { sum, expected });
+        }
+    }
+
+    /// Checks what can only be told once a kyoku is over.
+    fn finish_kyoku(&mut self) {
+        if !self.in_kyoku {
+            return;
+        }
+        self.in_kyoku = false;
+
+        // Dora markers of daiminkan and kakan are revealed only after the
+        // next discard or kan, so the kans just made may go without them.
+        let dora_markers = self.state.dora_markers.len();
+        let kans = self.kans;
+        let unrevealed = self.recent_kans.iter().sum::<usize>();
+        if dora_markers > kans + 1 || dora_markers + unrevealed < kans + 1 {
+            self.push(ViolationKind::DoraCount { dora_markers, kans });
+        }
+
+        let mut kinds = [0; 34];
+        for (i, count) in mem::take(&mut self.counts).into_iter().enumerate() {
+            let Ok(pai) = Tile::try_from(i) else {
+                continue;
+            };
+            if pai.is_unknown() {
+                continue;
+            }
+            kinds[pai.deaka().as_usize()] += count;
+            if pai.is_aka() {
+                let aka_flag = self.aka_flag.unwrap_or(true);
+                if count > u8::from(aka_flag) {
+                    self.push(ViolationKind::AkaMismatch {
+                        pai,
+                        count,
+                        aka_flag,
+                    });
+                }
+            } else if self.aka_flag == Some(true)
+                && matches!(pai.as_u8(), tu8!(5m) | tu8!(5p) | tu8!(5s))
+                && count > 3
+            {
+                // One of the four is aka.
+                self.push(ViolationKind::AkaMismatch {
+                    pai,
+                    count,
+                    aka_flag: true,
+                });
+            }
+        }
+        for (i, &count) in kinds.iter().enumerate() {
+            if count > 4 {
+                let pai = Tile::try_from(i).unwrap_or_default();
+                self.push(ViolationKind::TooManyTiles { pai, count });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tenhou::Log;
+    use crate::testdata::{self, FOUR_PLAIN_FIVES, KYOTAKU_LEFT_OUT, NOT_A_HAND};
+    use crate::{mjlog, t, tenhou_to_mjai};
+
+    #[test]
+    fn valid_logs() {
+        for (name, json) in testdata::tenhou_logs() {
+            let log = Log::from_json_str(&json).unwrap();
+            let violations = validate(&tenhou_to_mjai(&log).unwrap());
+            if KYOTAKU_LEFT_OUT.contains(&name.as_str()) {
+                assert!(violations.iter().all(|v| match v.kind {
+                    ViolationKind::DeltaSum { .. } => true,
+                    ViolationKind::FuritenRon { .. } => name == NOT_A_HAND,
+                    ViolationKind::AkaMismatch { .. } => name == FOUR_PLAIN_FIVES,
+                    _ => false,
+                }));
+            } else {
+                assert_eq!(violations, [], "{name}");
+            }
+        }
+        for (name, xml) in testdata::mjlogs() {
+            assert_eq!(validate(&mjlog::parse_str(&xml).unwrap()), [], "{name}");
+        }
+    }
+
+    #[test]
+    fn broken_logs() {
+        let log = Log::from_json_str(&testdata::read("ranked_game.json")).unwrap();
+        let events = tenhou_to_mjai(&log).unwrap();
+
+        // Two draws in a row.
+        let mut broken = events.clone();
+        let i = broken
+            .iter()
+            .position(|ev| matches!(ev, Event::Dahai { .. }))
+            .unwrap();
+        broken.remove(i);
+        let violations = validate(&broken);
+        assert!(matches!(
+            violations[0],
+            Violation {
+                index,
+                kind: ViolationKind::OutOfTurn { .. } | ViolationKind::HandSize { .. },
+            } if index == i
+        ));
+
+        // Scores not carried over.
+        let mut broken = events.clone();
+        let i = broken
+            .iter()
+            .rposition(|ev| matches!(ev, Event::StartKyoku { .. }))
+            .unwrap();
+        if let Event::StartKyoku { scores, .. } = &mut broken[i] {
+            scores[0] += 1000;
+        }
+        let violations = validate(&broken);
+        assert!(matches!(
+            violations[..],
+            [Violation {
+                kind: ViolationKind::ScoreMismatch { .. },
+                ..
+            }]
+        ));
+
+        // Kyotaku not carried over.
+        let Event::StartKyoku {
+            scores, kyotaku, ..
+        } = &mut broken[i]
+        else {
+            unreachable!();
+        };
+        scores[0] -= 1000;
+        let expected = *kyotaku;
+        *kyotaku += 1;
+        // The deltas of the kyoku then leave out the extra one.
+        assert_eq!(
+            validate(&broken)[0],
+            Violation {
+                index: i,
+                kind: ViolationKind::KyotakuMismatch {
+                    expected,
+                    actual: expected + 1,
+                },
+            }
+        );
+
+        // Deltas not summing up to zero.
+        let mut broken = events.clone();
+        for ev in &mut broken {
+            if let Event::Ryukyoku {
+                deltas: Some(deltas),
+                ..
+            }
+            | Event::Hora {
+                deltas: Some(deltas),
+                ..
+            } = ev
+            {
+                deltas[0] += 100;
+                break;
+            }
+        }
+        assert!(validate(&broken)
+            .iter()
+            .any(|v| matches!(v.kind, ViolationKind::DeltaSum { .. })));
+
+        // Four plain 5m while aka is enabled.
+        let plain_fives: Vec<Event> = serde_json::from_str(
+            r#"[
+                {"type":"start_game","names":["A","B","C","D"],"kyoku_first":0,"aka_flag":true},
+                {"type":"start_kyoku","bakaze":"E","dora_marker":"9p","kyoku":1,"honba":0,
+                 "kyotaku":0,"oya":0,"scores":[25000,25000,25000,25000],"tehais":[
+                    ["5m","5m","5m","5m","5p","6p","7p","3s","4s","5s","7s","8s","9s"],
+                    ["1p","1p","1p","2p","2p","2p","3p","3p","3p","4p","4p","4p","9p"],
+                    ["1s","1s","1s","2s","2s","2s","6s","6s","6s","S","S","S","9p"],
+                    ["W","W","W","N","N","N","P","P","P","F","F","F","C"]]},
+                {"type":"end_kyoku"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            validate(&plain_fives),
+            [Violation {
+                index: 2,
+                kind: ViolationKind::AkaMismatch {
+                    pai: t!(5m),
+                    count: 4,
+                    aka_flag: true,
+                },
+            }]
+        );
+
+        // Ron on 4m after discarding 1m.
+        let furiten: Vec<Event> = serde_json::from_str(
+            r#"[
+                {"type":"start_kyoku","bakaze":"E","dora_marker":"9p","kyoku":1,"honba":0,
+                 "kyotaku":0,"oya":0,"scores":[25000,25000,25000,25000],"tehais":[
+                    ["2m","3m","5p","6p","7p","3s","4s","5s","7s","8s","9s","E","E"],
+                    ["1p","1p","1p","2p","2p","2p","3p","3p","3p","4p","4p","4p","9p"],
+                    ["1s","1s","1s","2s","2s","2s","6s","6s","6s","S","S","S","9p"],
+                    ["W","W","W","N","N","N","P","P","P","F","F","F","C"]]},
+                {"type":"tsumo","actor":0,"pai":"1m"},
+                {"type":"dahai","actor":0,"pai":"1m","tsumogiri":true},
+                {"type":"tsumo","actor":1,"pai":"4m"},
+                {"type":"dahai","actor":1,"pai":"4m","tsumogiri":true},
+                {"type":"hora","actor":0,"target":1}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            validate(&furiten),
+            [Violation {
+                index: 5,
+                kind: ViolationKind::FuritenRon {
+                    actor: 0,
+                    furiten: Furiten::Permanent,
+                },
+            }]
+        );
+    }
+}